@@ -1,5 +1,8 @@
-use anyhow::Result;
-use clap::{builder::TypedValueParser, value_parser, Parser, ValueEnum};
+use crate::header::{Compression, IdEncoding};
+use anyhow::{anyhow, Result};
+use clap::{
+    builder::PossibleValuesParser, builder::TypedValueParser, value_parser, Parser, ValueEnum,
+};
 use clap::{Args, Subcommand};
 use printpdf::Mm;
 use qrcode::EcLevel;
@@ -32,13 +35,109 @@ fn mm_value_parser(s: &str) -> Result<Mm> {
     Ok(Mm(f32::from_str(s)?))
 }
 
+/// The shard sizes supported by `reed-solomon-simd`, which requires a multiple of 64 bytes.
+pub(crate) const SHARD_GRANULARITY: usize = 64;
+
+/// `granularity_value_parser` parses a shard granularity, which must be a positive multiple of
+/// [`SHARD_GRANULARITY`].
+fn granularity_value_parser(s: &str) -> Result<usize> {
+    let value = usize::from_str(s)?;
+    if value == 0 || value % SHARD_GRANULARITY != 0 {
+        Err(anyhow!(
+            "shard granularity must be a positive multiple of {SHARD_GRANULARITY}"
+        ))?;
+    }
+    Ok(value)
+}
+
+/// `color_value_parser` parses a hex color (e.g. "#1f4e79" or "1f4e79") into a PDF color.
+fn color_value_parser(s: &str) -> Result<printpdf::Color> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        Err(anyhow!("color must be six hex digits, e.g. \"#1f4e79\""))?;
+    }
+    let value = u32::from_str_radix(hex, 16)?;
+    let channel = |shift: u32| ((value >> shift) & 0xFF) as f32 / 255.0;
+    Ok(printpdf::Color::Rgb(printpdf::Rgb::new(
+        channel(16),
+        channel(8),
+        channel(0),
+        None,
+    )))
+}
+
 /// Paper size options.
 #[derive(Clone, Copy, Debug, ValueEnum)]
 pub(crate) enum PaperSize {
+    A3,
     A4,
+    A5,
     Letter,
 }
 
+/// What the layout should favour when picking QR code parameters.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Optimize {
+    /// Store as much data per page as possible.
+    Density,
+    /// Use the highest error correction (and then the smallest codes) that does not need more
+    /// pages than the densest layout.
+    Reliability,
+}
+
+/// How the modules (pixels) of the payload QR codes are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum ModuleStyle {
+    /// Square modules, which touch their neighbours.
+    Square,
+    /// Round dots, which can bleed together less on some printers; the finder patterns in the
+    /// corners are still drawn as squares.
+    Dot,
+}
+
+/// The order in which QR codes fill the grid on each page.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum FillOrder {
+    /// Fill each row before moving on to the next.
+    Row,
+    /// Fill each column before moving on to the next.
+    Column,
+    /// Fill rows alternately left to right and right to left, so consecutive codes stay adjacent.
+    Serpentine,
+}
+
+/// The kind of file that `create` writes.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// A single PDF document.
+    Pdf,
+    /// One SVG file per page.
+    Svg,
+    /// One PNG image per page, at the resolution given by `--dpi`.
+    Png,
+}
+
+/// The background of the images written with `--format png`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum PngBackground {
+    /// Opaque white, for printing.
+    White,
+    /// Transparent around the codes, for putting the pages onto labels or other artwork.  The
+    /// codes themselves stay white behind their dark modules.
+    Transparent,
+}
+
+/// The bit depth of the greyscale images written with `--format png`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum PngDepth {
+    /// Black and white only, for the smallest files; text loses its smoothed edges.
+    #[value(name = "1")]
+    One,
+    /// 256 shades of grey.
+    #[value(name = "8")]
+    Eight,
+}
+
 /// Describe the dimensions of a sheet of paper.
 pub(crate) struct PageDimensions {
     pub width: Mm,
@@ -48,10 +147,18 @@ pub(crate) struct PageDimensions {
 impl From<PaperSize> for PageDimensions {
     fn from(value: PaperSize) -> Self {
         match value {
+            PaperSize::A3 => PageDimensions {
+                width: Mm(297.0),
+                height: Mm(420.0),
+            },
             PaperSize::A4 => PageDimensions {
                 width: Mm(210.0),
                 height: Mm(297.0),
             },
+            PaperSize::A5 => PageDimensions {
+                width: Mm(148.0),
+                height: Mm(210.0),
+            },
             PaperSize::Letter => PageDimensions {
                 width: Mm(215.9),
                 height: Mm(279.4),
@@ -60,6 +167,15 @@ impl From<PaperSize> for PageDimensions {
     }
 }
 
+impl PaperSize {
+    /// The default module length for this paper size: 1mm for A4, scaled with the paper width (to
+    /// the nearest 0.1mm) so that other sizes get a similar layout.
+    pub(crate) fn default_module_length(self) -> Mm {
+        let dimensions: PageDimensions = self.into();
+        Mm((dimensions.width.0 / 210.0 * 10.0).round() / 10.0)
+    }
+}
+
 /// How much recovery to generate, so that we do not need the whole set of pages to restore.
 #[derive(Clone, Debug)]
 pub(crate) enum RecoveryFactor {
@@ -83,20 +199,125 @@ impl FromStr for RecoveryFactor {
     }
 }
 
+/// The height of the banner, either as a length or relative to the available page height.
+#[derive(Clone, Debug)]
+pub(crate) enum BannerHeight {
+    /// Banner height in millimetres, e.g. "60".
+    Length(Mm),
+    /// Banner height as a percentage of the page height inside the margins, e.g. "25%".
+    Percentage(f32),
+}
+
+impl FromStr for BannerHeight {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix("%") {
+            Ok(Self::Percentage(f32::from_str(percent)?))
+        } else {
+            Ok(Self::Length(Mm(f32::from_str(s)?)))
+        }
+    }
+}
+
 /// Arguments for creating documents.
 #[derive(Args, Debug)]
 pub(crate) struct CreateArgs {
-    /// File to encode.
+    /// File to encode; for `reflow`, an image of the existing document.
     #[arg(value_hint=clap::ValueHint::FilePath)]
     pub file_path: PathBuf,
 
     /// Output file to write to.
-    pub out_path: PathBuf,
+    #[arg(required_unless_present_any = ["dump_shards", "emit_matrices", "compare_sizes", "dry_run"])]
+    pub out_path: Option<PathBuf>,
+
+    /// Also encode the given file, bundled together with the first one; `restore` then unpacks
+    /// the files into a directory.  May be given more than once.
+    #[arg(long = "add-file", value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub add_files: Vec<PathBuf>,
+
+    /// Instead of creating the document, print how many pages it would need (and how its codes
+    /// would be laid out) on each paper size, with the other options as given.
+    #[arg(long, conflicts_with_all = ["out_path", "dump_shards", "emit_matrices", "shard_cache", "manifest", "porcelain", "layout_template"])]
+    pub compare_sizes: bool,
+
+    /// Instead of creating the document, print its layout (page count, QR code version and error
+    /// correction, codes per row and page, and bytes per code) and exit; with `--porcelain`, print
+    /// it as JSON.  The output file, if given, is not written.
+    #[arg(long, conflicts_with_all = ["compare_sizes", "dump_shards", "emit_matrices", "shard_cache", "manifest"])]
+    pub dry_run: bool,
+
+    /// Write each shard (header and data) as a separate file into the given directory, for use
+    /// with external tools.  If no output file is given, no PDF is generated.
+    #[arg(long, value_name = "DIR", value_hint=clap::ValueHint::DirPath)]
+    pub dump_shards: Option<PathBuf>,
+
+    /// Write the module matrices of every code (and the shard index of each) into the given file as
+    /// JSON, for laying them out with other tools.  If no output file is given, no PDF is
+    /// generated.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub emit_matrices: Option<PathBuf>,
+
+    /// Mix this string into the identifier, which is otherwise derived from the file contents
+    /// (and the paperback version) alone.  Backups of the same file made with different salts can
+    /// then be told apart when restoring; the Document ID and hash are unchanged.
+    #[arg(long, value_name = "STRING")]
+    pub identifier_salt: Option<String>,
+
+    /// Cache the encoded shards in this directory once they have all been encoded, and reuse them
+    /// on a later run with the same file and layout options instead of encoding it again.  The
+    /// pages are always rendered again.
+    #[arg(long, value_name = "DIR", value_hint=clap::ValueHint::DirPath)]
+    pub shard_cache: Option<PathBuf>,
+
+    /// Sign the document with the given Ed25519 private key (a PKCS#8 PEM file, as created by
+    /// `openssl genpkey -algorithm ed25519`), so that restores can verify who created it.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub sign_key: Option<PathBuf>,
+
+    /// Also write a JSON manifest describing the document (identifier, hash, and layout) to the
+    /// given file, for reprinting or verifying later.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub manifest: Option<PathBuf>,
+
+    /// Refuse to create a document with more than this many pages, rather than silently starting a
+    /// large print job.
+    #[arg(long, value_name = "N", help_heading = "Layout")]
+    pub max_pages: Option<usize>,
+
+    /// Instead of the usual messages, print a single line of JSON describing the document (output
+    /// path, page and shard counts, QR code parameters, identifier, and hash) for use by scripts.
+    #[arg(long)]
+    pub porcelain: bool,
+
+    /// Print the SHA-256 hash of the PDF file once it is written, to check that it was not changed
+    /// in storage or transit before printing.  This is a hash of the PDF, not of the input file.
+    #[arg(long)]
+    pub pdf_hash: bool,
+
+    /// With `--pdf-hash`, also write the hash next to the PDF, in a file named after it with
+    /// `.sha256` appended, in the format that `sha256sum --check` reads.
+    #[arg(long, requires = "pdf_hash")]
+    pub pdf_hash_file: bool,
+
+    /// Overwrite existing files when writing each page to its own file, as with `--format svg` or
+    /// `--format png`.
+    #[arg(long, short)]
+    pub force: bool,
+
+    /// Print at most this many pages of recovery data, beyond the pages needed to restore, however
+    /// much the recovery factor asks for; a warning shows how much recovery data is printed.
+    #[arg(long, value_name = "N", help_heading = "Layout")]
+    pub max_recovery_pages: Option<usize>,
 
     /// Minimum number of QR codes per row (up to 5).
     #[arg(short, long, default_value = "3", help_heading = "Layout")]
     pub row_count: usize,
 
+    /// Maximum number of QR codes per page; fewer codes per page allows larger codes.
+    #[arg(long, help_heading = "Layout")]
+    pub max_codes_per_page: Option<usize>,
+
     /// How much extra recovery data to generate; may be one of:
     /// - A percentage relative to required data, e.g. "50%"
     /// - A positive integer followed by "x" (e.g. "3x") to mean that multiple of required data
@@ -108,9 +329,105 @@ pub(crate) struct CreateArgs {
     #[arg(short, long, value_parser=value_parser!(ECLevel).map(|v| Into::<EcLevel>::into(v)), default_value = "q", help_heading = "Layout")]
     pub error_correction: EcLevel,
 
-    /// Width of one module (pixel) in a QR code; larger values are easier to read.
-    #[arg(short, long, value_parser=mm_value_parser, default_value="1.0", help_heading="Layout")]
-    pub module_length: Mm,
+    /// Maximum QR code error correction level; by default, a higher level than the minimum is used
+    /// if it fits the same amount of data per page.  Capping it stores more data per code, relying
+    /// on the recovery pages rather than each code to survive damage.
+    #[arg(long, value_parser=value_parser!(ECLevel).map(|v| Into::<EcLevel>::into(v)), help_heading = "Layout")]
+    pub max_error_correction: Option<EcLevel>,
+
+    /// Width of one module (pixel) in a QR code; larger values are easier to read.  Defaults to
+    /// 1.0 for A4 and letter paper, and is scaled with the paper width for other sizes.
+    #[arg(short, long, value_parser=mm_value_parser, help_heading="Layout")]
+    pub module_length: Option<Mm>,
+
+    /// If the module length is too small to scan reliably, enlarge it until it is not.
+    #[arg(long, help_heading = "Layout")]
+    pub auto_module: bool,
+
+    /// Round the data in each QR code down to a multiple of this many bytes.  The Reed-Solomon
+    /// library needs shards that are a multiple of 64 bytes, so 64 wastes the least space;
+    /// larger values are only useful for experimentation.
+    #[arg(long, value_name = "BYTES", value_parser=granularity_value_parser, default_value = "64", help_heading = "Layout")]
+    pub shard_granularity: usize,
+
+    /// Whether to optimize the layout for data density or for scan reliability.
+    #[arg(long, value_enum, default_value = "density", help_heading = "Layout")]
+    pub optimize: Optimize,
+
+    /// Add this many parity codes to each page, computed from that page's other codes, so that a
+    /// partly damaged page can be repaired on its own before the pages are combined.
+    #[arg(long, value_name = "N", default_value = "0", help_heading = "Layout")]
+    pub page_parity: usize,
+
+    /// Spread consecutive bytes of the file across the data shards before encoding, so that damage
+    /// to a contiguous run of the data is scattered over many shards.
+    #[arg(long, help_heading = "Layout")]
+    pub byte_interleave: bool,
+
+    /// Follow the file data with a 16 byte marker, so that the end of the data can still be found
+    /// if its recorded size is damaged.
+    #[arg(long, help_heading = "Layout")]
+    pub end_marker: bool,
+
+    /// Compress the file before encoding it, which can greatly reduce the number of pages for text
+    /// such as source code or logs.  There is no point in this for files that are already
+    /// compressed.
+    #[arg(long, value_enum, default_value = "none", help_heading = "Layout")]
+    pub compress: Compression,
+
+    /// The level of the compression chosen with --compress: 0 to 9 for gzip, or 1 to 22 for zstd.
+    /// Higher levels take longer, but can save a page or two on large files.  By default gzip uses
+    /// 9 and zstd 19.
+    #[arg(long, value_name = "N", help_heading = "Layout")]
+    pub compress_level: Option<u32>,
+
+    /// Encrypt the file with a passphrase, read from PAPERBACK_PASSPHRASE or asked for.  The key
+    /// is derived with Argon2id, and the data is encrypted with XChaCha20-Poly1305; `restore`
+    /// asks for the passphrase again.  The document's hash is still that of the unencrypted file.
+    #[arg(long, conflicts_with = "web_restorer", help_heading = "Layout")]
+    pub encrypt: bool,
+
+    /// With --encrypt, leave the salt and nonce out of the pages, and write them instead as a QR
+    /// code on a one page PDF at this path, to be kept apart from the pages.  Restoring then needs
+    /// this key card scanned along with the pages, as well as the passphrase.
+    #[arg(long, value_name = "PATH", requires = "encrypt", value_hint=clap::ValueHint::FilePath, help_heading = "Layout")]
+    pub emit_key_card: Option<PathBuf>,
+
+    /// How to draw the modules of the QR codes.
+    #[arg(long, value_enum, default_value = "square", help_heading = "Layout")]
+    pub module_style: ModuleStyle,
+
+    /// The order in which codes are placed on each page.  Every code carries its own index, so
+    /// this only changes the physical arrangement (for cutting or folding pages); restoring works
+    /// the same either way.
+    #[arg(long, value_enum, default_value = "row", help_heading = "Layout")]
+    pub fill_order: FillOrder,
+
+    /// Place the QR codes at the positions listed in the given JSON file, rather than in a grid,
+    /// for printing onto pre-cut label sheets.  The file looks like
+    /// `{"positions": [{"x": 10, "y": 15, "size": 60}, ...]}`, where `x` and `y` are the distance
+    /// in millimetres from the top left corner of the page to the top left corner of a label, and
+    /// `size` is the side of the square (including the quiet zone) the code must fit in.  The
+    /// positions must all be below the banner; `--module-length`, if given, is the smallest module
+    /// length to accept.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath, help_heading = "Layout")]
+    pub layout_template: Option<PathBuf>,
+
+    /// Store only the first two bytes of the document identifier in each QR code, rather than
+    /// four, leaving more room for data.  Codes from different documents are then more likely to
+    /// be mistaken for each other, so only use this for backups that will be kept apart.
+    #[arg(long, help_heading = "Layout")]
+    pub compact_headers: bool,
+
+    /// Height of the banner, in millimetres or as a percentage of the page (e.g. "25%"); the rest
+    /// of the page is used for QR codes.  By default, the codes take up a square area.
+    #[arg(long, value_name = "HEIGHT", help_heading = "Page Setup")]
+    pub banner_height: Option<BannerHeight>,
+
+    /// Blank space between the QR codes and the banner, in millimetres, so that a photo of the
+    /// codes is less likely to pick up parts of the banner.
+    #[arg(long, value_name = "LENGTH", value_parser=mm_value_parser, default_value = "0", help_heading = "Page Setup")]
+    pub banner_gap: Mm,
 
     /// Paper size to emit.
     #[arg(
@@ -134,6 +451,125 @@ pub(crate) struct CreateArgs {
     /// Paper left margin.
     #[arg(long, value_parser=mm_value_parser, default_value="4.32", help_heading="Page Setup")]
     pub margin_left: Mm,
+    /// Blank border to keep around everything on the page, for sheet-fed scanners that find the
+    /// page by its edges.  Any margin narrower than this is widened to it.
+    #[arg(long, value_parser=mm_value_parser, value_name="MM", help_heading="Page Setup")]
+    pub page_quiet_zone: Option<Mm>,
+
+    /// Color for the banner text and rules, as a hex value such as "#1f4e79".  QR codes are always
+    /// printed in black.
+    #[arg(long, value_parser=color_value_parser, help_heading="Page Setup")]
+    pub banner_color: Option<printpdf::Color>,
+
+    /// Number of columns to split the banner description into; useful for wide pages, such as
+    /// landscape orientations.
+    #[arg(long, value_name = "N", default_value = "1", value_parser=value_parser!(u8).range(1..=8).map(usize::from), help_heading="Page Setup")]
+    pub description_columns: usize,
+
+    /// Print each code's shard index, followed by a check character, below the code; this helps
+    /// when codes have to be transcribed by hand.
+    #[arg(long, help_heading = "Page Setup")]
+    pub index_labels: bool,
+
+    /// Also print each page's number large and faint in the space above its codes, to make
+    /// shuffled pages easy to put back in order.
+    #[arg(long, conflicts_with = "layout_template", help_heading = "Page Setup")]
+    pub big_page_numbers: bool,
+
+    /// Add a cover page describing the file format (as text and as a QR code), so that a decoder
+    /// could be written from the printout alone.
+    #[arg(long, help_heading = "Page Setup")]
+    pub decoder_spec: bool,
+
+    /// Print this many pages on each sheet, scaled down, for proofing the document cheaply; each
+    /// sheet is marked as a draft, since the smaller codes may not scan.
+    #[arg(long, value_name = "N", default_value = "1", value_parser=PossibleValuesParser::new(["1", "2", "4"]).map(|n| n.parse::<usize>().unwrap()), help_heading = "Page Setup")]
+    pub n_up: usize,
+
+    /// The kind of file to write.  With `svg` or `png`, each page is written to its own file,
+    /// named after the output file with the page number added (e.g. `out-0.svg`), or named
+    /// `out-0.png` and so on if the output is a directory.  SVG pages have the codes and the
+    /// banner in separate layers, for adding branding or notes before printing; PNG pages are for
+    /// printers that only take images.  An existing directory as the output implies `png`.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Pdf, help_heading = "Page Setup")]
+    pub format: OutputFormat,
+
+    /// The resolution of the images written with `--format png`.
+    #[arg(
+        long,
+        value_name = "DPI",
+        default_value = "300",
+        help_heading = "Page Setup"
+    )]
+    pub dpi: u32,
+
+    /// The background of the images written with `--format png`.  A transparent background needs
+    /// `--png-depth 8`.
+    #[arg(long, value_enum, default_value = "white", help_heading = "Page Setup")]
+    pub png_background: PngBackground,
+
+    /// The bit depth of the greyscale images written with `--format png`.
+    #[arg(long, value_enum, default_value = "8", help_heading = "Page Setup")]
+    pub png_depth: PngDepth,
+
+    /// Add a first page with a QR code linking to a web restorer at the given URL, with this
+    /// document's parameters (identifier, hash, and code layout) as query parameters, so that
+    /// scanning it with a phone opens a page set up to restore this document.
+    #[arg(long, value_name = "URL", value_hint=clap::ValueHint::Url, help_heading = "Page Setup")]
+    pub web_restorer: Option<String>,
+
+    /// Make the banner easier to read: all text is larger and in bold Helvetica, always in black,
+    /// and the description gets the full width of the banner.  The code area shrinks if the
+    /// banner needs more room.
+    #[arg(long, conflicts_with = "banner_color", help_heading = "Page Setup")]
+    pub accessible: bool,
+
+    /// Print a checksum of each page's codes in its banner, so that swapped codes can be detected
+    /// later: the first four bytes (in hex) of the SHA-512 of the page's payload codes, headers
+    /// included, concatenated in order of their index.
+    #[arg(long, help_heading = "Page Setup")]
+    pub page_checksums: bool,
+
+    /// Print a random ID for this printing, and the time it was made, in the banner of every page,
+    /// so that different printings of the same document (or photocopies of them) can be told
+    /// apart.  The ID is not stored in the QR codes, and does not affect restoring.
+    #[arg(long, help_heading = "Page Setup")]
+    pub print_run_id: bool,
+
+    /// Rotate the document ID and page count labels to read upwards beside the metadata QR codes,
+    /// leaving the full banner height for the description.
+    #[arg(long, help_heading = "Page Setup")]
+    pub vertical_labels: bool,
+
+    /// Number of copies of the metadata QR code in the banner; any one of them is needed to restore
+    /// the document.  Copies alternate between the left and right of the banner.
+    #[arg(long, value_name = "N", default_value = "2", value_parser=value_parser!(u8).range(1..=4).map(usize::from), help_heading="Page Setup")]
+    pub meta_copies: usize,
+
+    /// The number of bytes of the file's hash shown as the Document ID.  Longer IDs are less likely
+    /// to be shared by two documents, but are harder to read and compare by eye; the default of 6
+    /// gives 8 characters in base58.
+    #[arg(long, value_name = "N", default_value = "6", value_parser=value_parser!(u8).range(4..=16).map(usize::from), help_heading = "Page Setup")]
+    pub id_length: usize,
+
+    /// How the Document ID is written; base32 is longer than base58, but easier to read aloud.
+    #[arg(
+        long,
+        value_enum,
+        default_value = "base58",
+        help_heading = "Page Setup"
+    )]
+    pub id_encoding: IdEncoding,
+
+    /// Add a page with a strip to cut out and slide into the spine of a binder, giving the file
+    /// name, Document ID, page count, and date in large text.
+    #[arg(long, help_heading = "Page Setup")]
+    pub spine_label: bool,
+
+    /// Draw a bar in the banner showing which of the document's codes are on each page, to help
+    /// when sorting through printed pages.
+    #[arg(long, help_heading = "Page Setup")]
+    pub progress_bar: bool,
 
     /// Override the commit ID displayed in the document.  This is used to ensure we can get
     /// reproducible output for the sample PDF.
@@ -147,18 +583,128 @@ pub(crate) struct CreateArgs {
 /// Arguments for restoring documents.
 #[derive(Args, Debug)]
 pub(crate) struct RestoreArgs {
-    /// Output file to write to.
+    /// Output file to write to (or directory, with --batch).
     pub output_path: PathBuf,
 
-    /// Input files to restore from.  They must be images, but can contain multiple QR codes per
-    /// image.
-    #[arg(value_hint=clap::ValueHint::FilePath)]
+    /// Input files to restore from.  They must be images or PDFs (or directories or ZIP archives
+    /// of images), but can contain multiple QR codes per image.  Codes found more than once are
+    /// only counted once, so scans from several sessions can be combined.
+    #[arg(value_hint=clap::ValueHint::AnyPath)]
     pub input_path: Vec<PathBuf>,
 
     /// Overwrite any existing output file.
     #[arg(long, short)]
     pub force: bool,
 
+    /// Write the output to a temporary file first, and only move it into place once it has been
+    /// read back and found to be exactly the restored data, which matched the SHA-512 hash in the
+    /// metadata; otherwise it is deleted.  This guarantees that no unverified data is ever left at
+    /// the output path.
+    #[arg(long, conflicts_with_all = ["dry_run", "info_only"])]
+    pub strict: bool,
+
+    /// Also restore from the images listed in the given file (or standard input, if it is "-"),
+    /// one path per line; blank lines and lines starting with "#" are ignored.  Relative paths are
+    /// relative to the directory containing the list.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub input_list: Option<PathBuf>,
+
+    /// Require the document to be signed by the given Ed25519 public key, either a PEM file or
+    /// 64 hex digits.
+    #[arg(long, value_name = "KEY")]
+    pub verify_signature: Option<String>,
+
+    /// Refuse to restore documents created by a version of paperback with a possibly incompatible
+    /// format, rather than only warning about them.
+    #[arg(long)]
+    pub verify_tool_version: bool,
+
+    /// Only process the first N input images, counting each image in a directory or ZIP archive and
+    /// each page of a PDF, then the --base64 inputs; useful for checking scan quality on a sample.
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Stop scanning new images after this many seconds, and restore from the codes found so far;
+    /// images already being scanned are finished first.
+    #[arg(long, value_name = "SECONDS")]
+    pub time_limit: Option<u64>,
+
+    /// Only restore the document with the given Document ID (as printed on each page) or hex
+    /// identifier; codes from other documents are skipped rather than treated as errors.
+    #[arg(long, value_name = "ID")]
+    pub identifier: Option<String>,
+
+    /// Restore every document found in the input into the output directory, naming each file
+    /// after its Document ID; documents without enough codes are reported and skipped.
+    #[arg(long, conflicts_with = "identifier")]
+    pub batch: bool,
+
+    /// Create the parent directories of the output file if they do not exist.
+    #[arg(long)]
+    pub create_dirs: bool,
+
+    /// Use this shard size (the number of data bytes in each code) instead of the one in the
+    /// metadata codes, if those were damaged.  It must match the size of the codes found.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "batch",
+        help_heading = "Recovery"
+    )]
+    pub shard_bytes: Option<u64>,
+
+    /// Use this number of original shards (the number of codes needed to restore) instead of the
+    /// one in the metadata codes, if those were damaged.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "batch",
+        help_heading = "Recovery"
+    )]
+    pub original_count: Option<u16>,
+
+    /// Check that the file can be restored, and report how many more codes could be lost and
+    /// which inputs are needed, without writing anything.
+    #[arg(long, conflicts_with = "batch")]
+    pub dry_run: bool,
+
+    /// Instead of restoring, write the metadata of every document found (and how many of its
+    /// codes were found) to the output file as JSON; no data is decoded, so this works without a
+    /// complete set of codes.
+    #[arg(long, conflicts_with_all = ["batch", "dry_run", "sidecar"])]
+    pub info_only: bool,
+
+    /// After restoring, also write `<OUTPUT>.paperback.json`, recording the document that was
+    /// restored, the hash it was verified against, and how many codes were used.
+    #[arg(long)]
+    pub sidecar: bool,
+
+    /// Also read a base64 encoded image (or data URL) from the given file, or from standard input
+    /// if it is "-".  May be given more than once.
+    #[arg(long, value_name = "FILE", value_hint=clap::ValueHint::FilePath)]
+    pub base64: Vec<PathBuf>,
+
+    /// Also read shards from files in the given directory, as written by `create --dump-shards`.
+    #[arg(long, value_name = "DIR", value_hint=clap::ValueHint::DirPath)]
+    pub from_shards: Option<PathBuf>,
+
+    /// Split images larger than this many pixels (in either direction) into overlapping tiles
+    /// before scanning; this helps with very large images, such as several pages stitched together.
+    #[arg(long, value_name = "PIXELS")]
+    pub tile_size: Option<u32>,
+
+    /// Once the codes in an image have been read, shrink the remaining images so that each module
+    /// of the smallest codes is about this many pixels across.  This makes large, high resolution
+    /// scans faster to read; 3 is usually enough, and images are never enlarged.
+    #[arg(long, value_name = "PIXELS", conflicts_with = "tile_size")]
+    pub min_module_pixels: Option<f32>,
+
+    /// Scan each image quickly first, and only scan the images with fewer codes than the best one
+    /// again, more thoroughly.  This is much faster for clean scans, and reports which images
+    /// needed the slower scan.
+    #[arg(long)]
+    pub fast_scan: bool,
+
     /// Override the commit ID displayed in the document.  This is used to ensure we can get
     /// reproducible output for the sample PDF.
     #[arg(long, hide=true, default_value=match env!("VERGEN_GIT_DESCRIBE") {
@@ -168,12 +714,59 @@ pub(crate) struct RestoreArgs {
     pub override_commit: String,
 }
 
+/// Arguments for re-creating a document from a scan of an existing one.
+#[derive(Args, Debug)]
+pub(crate) struct ReflowArgs {
+    /// The layout of the new document; the file to encode is instead an image of the existing one.
+    #[command(flatten)]
+    pub create: CreateArgs,
+
+    /// Also restore from the given image, for documents scanned into more than one file.  May be
+    /// given more than once.
+    #[arg(long = "scan", value_name = "IMAGE", value_hint=clap::ValueHint::FilePath)]
+    pub scans: Vec<PathBuf>,
+
+    /// Split images larger than this many pixels (in either direction) into overlapping tiles
+    /// before scanning, as for `restore`.
+    #[arg(long, value_name = "PIXELS")]
+    pub tile_size: Option<u32>,
+}
+
+/// Arguments for checking scans of printed pages against the original file.
+#[derive(Args, Debug)]
+pub(crate) struct VerifyArgs {
+    /// The options the document was created with; the file to encode is the original file.
+    #[command(flatten)]
+    pub create: CreateArgs,
+
+    /// Scan of one printed page to check against the original file.  May be given more than once,
+    /// with one page in each image.
+    #[arg(long = "scan", value_name = "IMAGE", required = true, value_hint=clap::ValueHint::FilePath)]
+    pub scans: Vec<PathBuf>,
+
+    /// Split images larger than this many pixels (in either direction) into overlapping tiles
+    /// before scanning, as for `restore`.
+    #[arg(long, value_name = "PIXELS")]
+    pub tile_size: Option<u32>,
+}
+
 #[derive(Subcommand)]
 pub(crate) enum Commands {
     /// Create PDFs from an input file.
     Create(CreateArgs),
     /// Restore a file from scanned PDFs.
     Restore(RestoreArgs),
+    /// Describe the QR codes found in scanned images, without restoring anything.
+    Inspect(InspectArgs),
+    /// Restore a document from scanned images, and create it again with a different layout.
+    Reflow(ReflowArgs),
+    /// Check scans of printed pages against the original file, reporting how many of the codes on
+    /// each page still read correctly.
+    #[command(mut_arg("out_path", |arg| arg.required_unless_present(clap::builder::Resettable::Reset).hide(true)))]
+    Verify(VerifyArgs),
+    /// Create a calibration page with one QR code and a ruler, labelled with their exact sizes, to
+    /// check that a printer does not scale its output.
+    Ruler(RulerArgs),
 }
 
 #[derive(Parser)]
@@ -182,3 +775,88 @@ pub(crate) struct TopLevelArgs {
     #[command(subcommand)]
     pub(crate) command: Commands,
 }
+
+#[derive(Args, Debug)]
+pub(crate) struct InspectArgs {
+    /// Images to inspect.
+    #[arg(required = true, value_hint=clap::ValueHint::FilePath)]
+    pub input_path: Vec<PathBuf>,
+
+    /// Split images larger than this many pixels (in either direction) into overlapping tiles
+    /// before scanning, as for `restore`.
+    #[arg(long, value_name = "PIXELS")]
+    pub tile_size: Option<u32>,
+}
+
+/// Arguments for creating a calibration page.
+#[derive(Args, Debug)]
+pub(crate) struct RulerArgs {
+    /// Output file to write to.
+    #[arg(value_hint=clap::ValueHint::FilePath)]
+    pub out_path: PathBuf,
+
+    /// Paper size to emit.
+    #[arg(short, long, value_enum, default_value = "a4")]
+    pub paper_size: PaperSize,
+
+    /// Length of one side of each module of the QR code, in millimetres.  The default depends on
+    /// the paper size, as for `create`.
+    #[arg(short, long, value_parser=mm_value_parser)]
+    pub module_length: Option<Mm>,
+
+    /// The QR code version (size) of the code to print.
+    #[arg(long, value_name = "VERSION", default_value = "10", value_parser=value_parser!(i16).range(1..=40))]
+    pub qr_version: i16,
+
+    /// The resolution of the printer, to give the sizes in printer dots as well.
+    #[arg(long, value_name = "DPI", default_value = "600")]
+    pub dpi: u32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::{error::ErrorKind, CommandFactory};
+
+    #[test]
+    fn test_args_consistent() {
+        TopLevelArgs::command().debug_assert();
+    }
+
+    /// Combinations of options that make no sense together should be rejected, naming both.
+    #[test]
+    fn test_conflicting_args() {
+        for (first, second, second_name) in [
+            ("--compare-sizes", "out.pdf", "OUT_PATH"),
+            ("--compare-sizes", "--dump-shards=shards", "--dump-shards"),
+            ("--compare-sizes", "--manifest=manifest.json", "--manifest"),
+            ("--compare-sizes", "--porcelain", "--porcelain"),
+            ("--dry-run", "--dump-shards=shards", "--dump-shards"),
+            ("--accessible", "--banner-color=1f4e79", "--banner-color"),
+        ] {
+            let result = TopLevelArgs::try_parse_from(["paperback", "create", "in", first, second]);
+            let Err(err) = result else {
+                panic!("{first} {second} should have been rejected");
+            };
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{first} {second}");
+            let message = err.to_string();
+            assert!(message.contains(first), "{first} not in {message}");
+            assert!(
+                message.contains(second_name),
+                "{second_name} not in {message}"
+            );
+        }
+        let result = TopLevelArgs::try_parse_from([
+            "paperback",
+            "restore",
+            "out",
+            "in.png",
+            "--batch",
+            "--identifier=abc",
+        ]);
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::ArgumentConflict)
+        );
+    }
+}
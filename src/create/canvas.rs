@@ -0,0 +1,317 @@
+use super::{
+    layout,
+    render::{self, Bounds},
+};
+use crate::{
+    args::ModuleStyle,
+    fonts::metrics::{Alignment, FontMetrics},
+};
+use anyhow::Result;
+use printpdf::{BuiltinFont, Color, Mm, Pt};
+use qrcode::QrCode;
+use std::collections::VecDeque;
+
+/// `Canvas` is a page drawn one shape at a time, for the formats that write each page to its own
+/// file.  Positions are given as for the PDF, in millimetres from the bottom left corner of the
+/// page.
+pub(super) trait Canvas {
+    /// Start a group of related drawing, which formats that have layers keep together.
+    fn begin_layer(&mut self, _label: &str) {}
+
+    fn end_layer(&mut self) {}
+
+    fn rect(&mut self, left: Mm, bottom: Mm, right: Mm, top: Mm, color: &Color);
+
+    /// Write a line of text with its baseline at `y`; `x` is the left, right, or centre of the line
+    /// depending on `alignment`.
+    #[allow(clippy::too_many_arguments)]
+    fn text(
+        &mut self,
+        font: BuiltinFont,
+        size: Pt,
+        text: &str,
+        x: Mm,
+        y: Mm,
+        alignment: &Alignment,
+        color: &Color,
+    );
+
+    /// Draw a QR code, without a quiet zone, with its bottom left corner at the given position.
+    fn qr_code(&mut self, code: &QrCode, style: ModuleStyle, x: Mm, y: Mm, size: Mm) -> Result<()>;
+
+    /// Write space-separated text over the given number of columns, wrapped as the PDF does.
+    fn text_columns(
+        &mut self,
+        font: BuiltinFont,
+        size: Pt,
+        text: &str,
+        bounds: &Bounds,
+        columns: usize,
+        color: &Color,
+    ) {
+        let metrics: &FontMetrics = font.into();
+        let columns = columns.max(1);
+        let gutter = Mm::from(size);
+        let column_width =
+            (bounds.right - bounds.left - gutter * (columns - 1) as f32) / columns as f32;
+        let max_lines = ((bounds.top - bounds.bottom) / Mm::from(size)).floor() as usize;
+        let mut words: VecDeque<_> = text.split_whitespace().collect();
+        for column in 0..columns {
+            let left = bounds.left + (column_width + gutter) * column as f32;
+            let lines = metrics.wrap_lines(size, &mut words, column_width, max_lines);
+            for (line_num, line) in lines.iter().enumerate() {
+                let y = bounds.top - Mm::from(size) * (line_num + 1) as f32;
+                self.text(font, size, line, left, y, &Alignment::Left, color);
+            }
+        }
+    }
+}
+
+/// Convert a PDF colour to 8-bit red, green, and blue.
+pub(super) fn rgb(color: &Color) -> [u8; 3] {
+    let channel = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+    match color {
+        Color::Rgb(rgb) => [channel(rgb.r), channel(rgb.g), channel(rgb.b)],
+        Color::Greyscale(grey) => [channel(grey.percent); 3],
+        Color::Cmyk(printpdf::Cmyk { c, m, y, k, .. })
+        | Color::SpotColor(printpdf::SpotColor { c, m, y, k }) => [
+            channel((1. - c) * (1. - k)),
+            channel((1. - m) * (1. - k)),
+            channel((1. - y) * (1. - k)),
+        ],
+    }
+}
+
+const BLACK: Color = Color::Greyscale(printpdf::Greyscale {
+    percent: 0.,
+    icc_profile: None,
+});
+const WHITE: Color = Color::Greyscale(printpdf::Greyscale {
+    percent: 1.,
+    icc_profile: None,
+});
+
+/// Draw a page, laid out as [`render::render_page`] lays out the PDF page.  `codes` are the
+/// payload codes on the page.
+pub(super) fn draw_page(
+    page: &mut impl Canvas,
+    layout: &layout::Options,
+    codes: &[QrCode],
+    page_num: usize,
+    page_checksum: Option<&str>,
+    commit: &str,
+) -> Result<()> {
+    let (vertical_offset, banner_bounds) = render::page_areas(layout, page_num);
+
+    page.begin_layer("Codes");
+    let shard_width = layout.module_length * layout.version.width() as f32;
+    for (index, code) in codes.iter().enumerate() {
+        let (x, y) = render::code_origin(vertical_offset, layout, index);
+        page.qr_code(code, layout.module_style, x, y, shard_width)?;
+        if layout.index_labels {
+            let shard_index = layout.shard_index(page_num, index);
+            let size = render::index_label_size(layout);
+            page.text(
+                BuiltinFont::Courier,
+                size,
+                &format!("{shard_index}-{}", render::index_check_char(shard_index)),
+                x,
+                y - size.into(),
+                &Alignment::Left,
+                &BLACK,
+            );
+        }
+    }
+    page.end_layer();
+
+    if layout.big_page_numbers {
+        let (size, x, y) = render::big_page_number_position(vertical_offset, layout)?;
+        page.begin_layer("Page Number");
+        page.text(
+            BuiltinFont::HelveticaBold,
+            size,
+            &(page_num + 1).to_string(),
+            x,
+            y,
+            &Alignment::Right,
+            &render::BIG_PAGE_NUMBER_COLOR,
+        );
+        page.end_layer();
+    }
+
+    page.begin_layer("Banner");
+    draw_banner(
+        page,
+        &banner_bounds,
+        layout,
+        page_num,
+        page_checksum,
+        commit,
+    )?;
+    page.end_layer();
+    Ok(())
+}
+
+/// Draw the banner, as [`render::render_page`] does, but without the progress bar or rotated
+/// labels.
+fn draw_banner(
+    page: &mut impl Canvas,
+    bounds: &Bounds,
+    layout: &layout::Options,
+    page_num: usize,
+    page_checksum: Option<&str>,
+    commit: &str,
+) -> Result<()> {
+    let is_even = page_num.is_multiple_of(2);
+    let banner_color = layout.banner_color.clone().unwrap_or(BLACK);
+    let descender = |font: BuiltinFont, size: Pt| -> Mm {
+        let metrics: &FontMetrics = font.into();
+        (size * metrics.descender / 1000.).into()
+    };
+
+    // The repository bar, along the edge of the banner away from the codes.
+    let (repo_font, repo_size) = render::banner_font_choice(layout, BuiltinFont::Courier, Pt(14.));
+    let repo_banner_height: Mm = (repo_size * 2.).into();
+    if is_even {
+        page.rect(
+            bounds.left,
+            bounds.bottom,
+            bounds.right,
+            bounds.bottom + repo_banner_height,
+            &banner_color,
+        );
+    } else {
+        page.rect(
+            bounds.left,
+            bounds.top - repo_banner_height,
+            bounds.right,
+            bounds.top,
+            &banner_color,
+        );
+    }
+    page.text(
+        repo_font,
+        repo_size,
+        &format!("github.com/mook/paperpack@{commit}"),
+        bounds.left + (bounds.right - bounds.left) / 2.0,
+        if is_even {
+            bounds.bottom + repo_size.into() - descender(repo_font, repo_size)
+        } else {
+            bounds.top - repo_size.into() - descender(repo_font, repo_size)
+        },
+        &Alignment::Center,
+        &WHITE,
+    );
+
+    // The metadata codes.
+    let meta_code = render::meta_code(layout)?;
+    let desired_svg_length: Mm = layout::META_CODE_LENGTH.into();
+    let quiet_zone_length = desired_svg_length / ((meta_code.width() + 8) as f32) * 4.0;
+    let bottom_offset = if is_even {
+        bounds.bottom + repo_banner_height + quiet_zone_length
+    } else {
+        bounds.top - desired_svg_length - repo_banner_height
+    };
+    let left_copies = layout.meta_copies.div_ceil(2);
+    let right_copies = layout.meta_copies / 2;
+    let left_inset = desired_svg_length * left_copies as f32;
+    let right_inset = desired_svg_length * right_copies as f32;
+    let copy_offsets = (0..left_copies)
+        .map(|i| bounds.left + desired_svg_length * i as f32)
+        .chain((0..right_copies).map(|i| bounds.right - desired_svg_length * (i + 1) as f32));
+    for copy_offset in copy_offsets {
+        page.qr_code(
+            &meta_code,
+            ModuleStyle::Square,
+            copy_offset + quiet_zone_length,
+            bottom_offset,
+            desired_svg_length - quiet_zone_length * 2.,
+        )?;
+    }
+
+    // The Document ID and page count, beside the metadata codes.
+    let (_, info_size) = render::banner_font_choice(layout, BuiltinFont::Courier, Pt(24.));
+    let (label_font, label_size) =
+        render::banner_font_choice(layout, BuiltinFont::HelveticaBold, Pt(14.));
+    let document_id = layout.document_id();
+    let page_info = format!(
+        "{}/{}+{}",
+        page_num + 1,
+        layout.data_page_count,
+        layout.recovery_page_count - layout.data_page_count
+    );
+    let half_width = (bounds.right - bounds.left) / 2.;
+    for (label, value, x, room, alignment) in [
+        (
+            "Document ID",
+            document_id,
+            bounds.left + left_inset,
+            half_width - left_inset,
+            Alignment::Left,
+        ),
+        (
+            "Page Count",
+            page_info,
+            bounds.right - right_inset,
+            half_width - right_inset,
+            Alignment::Right,
+        ),
+    ] {
+        let (value_font, value_size) =
+            render::fitted_font_choice(layout, BuiltinFont::Courier, Pt(24.), &value, room);
+        page.text(
+            value_font,
+            value_size,
+            &value,
+            x,
+            bottom_offset + descender(value_font, value_size),
+            &alignment,
+            &banner_color,
+        );
+        page.text(
+            label_font,
+            label_size,
+            label,
+            x,
+            bottom_offset + info_size.into() + descender(label_font, label_size),
+            &alignment,
+            &banner_color,
+        );
+    }
+
+    // The description, beside the metadata codes, or across the banner in accessible mode.
+    let (description_font, description_size) =
+        render::banner_font_choice(layout, BuiltinFont::Helvetica, Pt(10.));
+    let description_bounds = if layout.accessible {
+        Bounds {
+            top: if is_even {
+                bounds.top
+            } else {
+                bottom_offset - quiet_zone_length
+            },
+            right: bounds.right,
+            bottom: if is_even {
+                bottom_offset + desired_svg_length
+            } else {
+                bounds.bottom
+            },
+            left: bounds.left,
+        }
+    } else {
+        Bounds {
+            top: bottom_offset + desired_svg_length - quiet_zone_length * 2.,
+            right: bounds.right - right_inset,
+            bottom: bottom_offset,
+            left: bounds.left + left_inset,
+        }
+    };
+    page.text_columns(
+        description_font,
+        description_size,
+        &render::description(layout, page_num, page_checksum),
+        &description_bounds,
+        layout.description_columns,
+        &banner_color,
+    );
+    Ok(())
+}
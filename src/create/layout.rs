@@ -1,9 +1,96 @@
-use crate::args::{CreateArgs, PageDimensions};
-use crate::header::{Identifier, PayloadHeader, Sha512Array};
-use anyhow::{anyhow, Result};
+use crate::args::{
+    BannerHeight, CreateArgs, FillOrder, ModuleStyle, Optimize, PageDimensions, PaperSize,
+    RecoveryFactor,
+};
+use crate::header::{
+    document_id, to_hex, Compression, Encryption, IdEncoding, Identifier, MetaHeader, PageParity,
+    PayloadHeader, Sha512Array, Signature, COMPACT_IDENTIFIER_LENGTH, IDENTIFIER_LENGTH,
+    TOOL_VERSION,
+};
+use crate::Failure;
+use anyhow::{anyhow, Context, Result};
 use num_integer::Integer;
-use printpdf::Mm;
+use printpdf::{Mm, Pt};
 use qrcode::EcLevel;
+use serde::Deserialize;
+use std::path::Path;
+
+/// A list of places to put QR codes on each page, as read from `--layout-template`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutTemplate {
+    pub positions: Vec<CodePosition>,
+}
+
+/// One place for a QR code on a template page, in millimetres from the top left of the page.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CodePosition {
+    pub x: f32,
+    pub y: f32,
+    /// The side of the square that the code, including its quiet zone, must fit in.
+    pub size: f32,
+}
+
+impl LayoutTemplate {
+    /// Read a template from a JSON file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read layout template {}", path.display()))?;
+        let template: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid layout template {}", path.display()))?;
+        if template.positions.is_empty() {
+            Err(anyhow!(
+                "layout template {} has no positions",
+                path.display()
+            ))?;
+        }
+        Ok(template)
+    }
+
+    /// Check that every position is on the page, clear of the page quiet zone, and below the code
+    /// area's top edge (measured from the top of the page), so that no code overlaps the banner.
+    fn check(&self, page: &PageDimensions, quiet_zone: Mm, code_area_top: Mm) -> Result<()> {
+        for (index, position) in self.positions.iter().enumerate() {
+            if position.size <= 0.0 {
+                Err(anyhow!("template position {index} has no size"))?;
+            }
+            if position.x < 0.0
+                || position.x + position.size > page.width.0
+                || position.y + position.size > page.height.0
+            {
+                Err(anyhow!("template position {index} is off the page"))?;
+            }
+            if position.x < quiet_zone.0
+                || position.y < quiet_zone.0
+                || position.x + position.size > (page.width - quiet_zone).0
+                || position.y + position.size > (page.height - quiet_zone).0
+            {
+                Err(anyhow!(
+                    "template position {index} is within the {:.1}mm page quiet zone",
+                    quiet_zone.0
+                ))?;
+            }
+            if position.y < code_area_top.0 {
+                Err(anyhow!(
+                    "template position {index} overlaps the banner; codes must be at least \
+                     {:.1}mm from the top of the page",
+                    code_area_top.0
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The side of the smallest position.
+    fn min_size(&self) -> Mm {
+        Mm(self
+            .positions
+            .iter()
+            .map(|position| position.size)
+            .fold(f32::INFINITY, f32::min))
+    }
+}
 
 /// `LayoutOptions` contains the parameters used for laying out the pages.
 #[derive(Debug)]
@@ -16,64 +103,575 @@ pub struct Options {
     pub margin_left: Mm,
     /// The length of one module (pixel in a QR code).
     pub module_length: Mm,
+    /// How the modules of the payload QR codes are drawn.
+    pub module_style: ModuleStyle,
     /// The available width, excluding margins.
     pub avail_width: Mm,
     /// The available height, excluding margins.
     pub avail_height: Mm,
+    /// The height of the area reserved for QR codes; the banner takes up the rest of
+    /// `avail_height`.
+    pub code_area_height: Mm,
+    /// The blank space between the QR codes and the banner.
+    pub banner_gap: Mm,
 
     pub identifier: Identifier,
+    /// The number of identifier bytes stored in each payload header.
+    pub payload_identifier_length: usize,
     pub hash: Sha512Array,
+    /// Signature of `hash`, if the document is signed.
+    pub signature: Option<Signature>,
     pub version: qrcode::Version,
     pub level: EcLevel,
     /// The number of QR codes per row / column.
     pub shards_per_row: usize,
+    /// Where to place each code on the page, from `--layout-template`, instead of the grid.
+    pub code_positions: Option<Vec<CodePosition>>,
+    /// The order in which codes fill the grid on each page.
+    pub fill_order: FillOrder,
+    /// The number of recovery shards per page; together with `page_parity_count`, this is at most
+    /// `shards_per_row` times the number of rows that fit in `code_area_height`.
+    pub shards_per_page: usize,
+    /// The number of parity codes per page, computed from the recovery shards on that page.
+    pub page_parity_count: usize,
+    /// Whether the data is byte interleaved across the data shards before encoding.
+    pub byte_interleave: bool,
+    /// Whether the data is followed by [`crate::header::END_MARKER`].
+    pub end_marker: bool,
+    /// Whether the data is a bundle of files; see [`crate::header::pack_bundle`].
+    pub bundle: bool,
+    /// The number of hash bytes shown as the Document ID.
+    pub document_id_length: usize,
+    /// How the Document ID is written.
+    pub document_id_encoding: IdEncoding,
+    /// How the file data is compressed before it is split into shards.
+    pub compression: Compression,
+    /// How the file data was encrypted, if it was; this is set after the layout is computed.
+    pub encryption: Option<Encryption>,
+    /// Whether the encryption record goes on a separate key card instead of in the metadata.
+    pub key_card: bool,
     /// The number of data bytes stored per QR code, excluding header.
     pub data_bytes_per_shard: usize,
     /// The total number of data shards; this is never emitted.
     pub data_shard_count: usize,
-    /// The total number of recovery shards; this is a multiple of `shards_per_row` squared, and is
-    /// the number of shards actually printed.
+    /// The total number of recovery shards; this is a multiple of `shards_per_page`, and is the
+    /// number of shards actually printed.
     pub recovery_shard_count: usize,
     /// The minimum number of pages needed to recover data.
     pub data_page_count: usize,
     /// The number of total pages.
     pub recovery_page_count: usize,
+    /// The number of total pages the recovery factor asked for, before `--max-recovery-pages`.
+    pub requested_recovery_page_count: usize,
+    /// Color for the banner text and rules; if unset, they are black.
+    pub banner_color: Option<printpdf::Color>,
+    /// The number of columns to split the banner description into.
+    pub description_columns: usize,
+    /// Whether the document ID and page count are rotated to read upwards.
+    pub vertical_labels: bool,
+    /// Whether the banner uses larger, bold text, with the description below the metadata codes.
+    pub accessible: bool,
+    /// The number of copies of the metadata QR code in the banner.
+    pub meta_copies: usize,
+    /// Whether to draw a bar in the banner showing which codes are on each page.
+    pub progress_bar: bool,
+    /// Whether to print each shard's index (with a check character) below its QR code.
+    pub index_labels: bool,
+    /// Whether to print the page number large and faint above the codes.
+    pub big_page_numbers: bool,
+    /// The ID and time of this printing, shown in the banner with `--print-run-id`.
+    pub print_run: Option<String>,
+    /// The amount of redundancy that was asked for, as a fraction of `data_shard_count`, before
+    /// rounding up to whole pages.
+    pub requested_redundancy: f32,
+}
+
+impl Options {
+    /// The Document ID printed on each page.
+    pub fn document_id(&self) -> String {
+        document_id(
+            &self.hash,
+            self.document_id_length,
+            self.document_id_encoding,
+        )
+    }
+
+    /// Build the header stored in the metadata QR codes.
+    pub fn meta_header(&self) -> Result<MetaHeader> {
+        let mut meta = MetaHeader {
+            identifier: self.identifier,
+            hash: self.hash,
+            original_count: u16::try_from(self.data_shard_count)
+                .map_err(|_| anyhow!("cannot render {} data chunks", self.data_shard_count))?,
+            recovery_count: u16::try_from(self.recovery_shard_count).map_err(|_| {
+                anyhow!(
+                    "cannot render {} recovery chunks",
+                    self.recovery_shard_count
+                )
+            })?,
+            shard_bytes: self.data_bytes_per_shard as u64,
+            signature: self.signature,
+            payload_identifier_length: self.payload_identifier_length,
+            tool_version: Some(TOOL_VERSION.to_string()),
+            page_parity: self.page_parity()?,
+            byte_interleave: self.byte_interleave,
+            end_marker: self.end_marker,
+            bundle: self.bundle,
+            format_version: None,
+            document_id_length: self.document_id_length,
+            document_id_encoding: self.document_id_encoding,
+            compression: self.compression,
+            encryption: self.encryption.filter(|_| !self.key_card),
+            key_card: self.key_card,
+        };
+        meta.format_version = Some(meta.required_format_version());
+        Ok(meta)
+    }
+
+    /// The link to a web restorer at `base_url`, with the parameters needed to restore this
+    /// document (and check that the right one was restored) as query parameters.
+    pub fn restorer_url(&self, base_url: &str, file_name: &str) -> String {
+        let version = match self.version {
+            qrcode::Version::Normal(v) | qrcode::Version::Micro(v) => v,
+        };
+        let mut params = vec![
+            ("id", self.document_id()),
+            ("name", file_name.to_string()),
+            ("identifier", to_hex(&self.identifier)),
+            ("hash", to_hex(&self.hash)),
+            ("version", version.to_string()),
+            ("ec", format!("{:?}", self.level)),
+            ("shard_bytes", self.data_bytes_per_shard.to_string()),
+            ("needed", self.data_shard_count.to_string()),
+            ("shards", self.recovery_shard_count.to_string()),
+            ("per_page", self.shards_per_page.to_string()),
+            ("id_bytes", self.payload_identifier_length.to_string()),
+        ];
+        if self.page_parity_count > 0 {
+            params.push(("page_parity", self.page_parity_count.to_string()));
+        }
+        if self.byte_interleave {
+            params.push(("interleave", "1".to_string()));
+        }
+        if self.end_marker {
+            params.push(("end_marker", "1".to_string()));
+        }
+        let query = params
+            .iter()
+            .map(|(key, value)| format!("{key}={}", percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let separator = if base_url.contains('?') { '&' } else { '?' };
+        format!("{base_url}{separator}{query}")
+    }
+
+    /// Describe the page parity codes for the metadata, checking that their indices fit.
+    fn page_parity(&self) -> Result<Option<PageParity>> {
+        if self.page_parity_count == 0 {
+            return Ok(None);
+        }
+        let last_index =
+            self.recovery_shard_count + self.recovery_page_count * self.page_parity_count;
+        if last_index >= u16::MAX as usize {
+            Err(anyhow!(
+                "cannot render {last_index} codes including page parity"
+            ))?;
+        }
+        Ok(Some(PageParity {
+            shards_per_page: self.shards_per_page as u16,
+            parity_count: self.page_parity_count as u16,
+        }))
+    }
+
+    /// The number of QR codes on each page, including parity codes.
+    pub fn codes_per_page(&self) -> usize {
+        self.shards_per_page + self.page_parity_count
+    }
+
+    /// The index in the payload header of the code at the given position on the given page.
+    pub fn shard_index(&self, page_num: usize, position: usize) -> usize {
+        if position < self.shards_per_page {
+            page_num * self.shards_per_page + position
+        } else {
+            self.recovery_shard_count
+                + page_num * self.page_parity_count
+                + (position - self.shards_per_page)
+        }
+    }
+
+    /// The row and column of the code at the given position on a page, counting rows from the
+    /// bottom of the code area.
+    pub fn grid_position(&self, position: usize) -> (usize, usize) {
+        let columns = self.shards_per_row;
+        match self.fill_order {
+            FillOrder::Row => position.div_rem(&columns),
+            FillOrder::Column => {
+                let rows = self.codes_per_page().div_ceil(columns);
+                let (col, row) = position.div_rem(&rows);
+                (row, col)
+            }
+            FillOrder::Serpentine => {
+                let (row, col) = position.div_rem(&columns);
+                if row.is_odd() {
+                    (row, columns - 1 - col)
+                } else {
+                    (row, col)
+                }
+            }
+        }
+    }
+
+    /// The amount of redundancy actually printed, as a fraction of `data_shard_count`.
+    pub fn effective_redundancy(&self) -> f32 {
+        (self.recovery_shard_count - self.data_shard_count) as f32 / self.data_shard_count as f32
+    }
+
+    /// If `--max-recovery-pages` reduced the number of pages, describe the recovery data that was
+    /// asked for and what is actually printed.
+    pub fn recovery_cap_note(&self) -> Option<String> {
+        if self.recovery_page_count >= self.requested_recovery_page_count {
+            return None;
+        }
+        let extra_pages = |page_count: usize| page_count - self.data_page_count;
+        Some(format!(
+            "requested {:.0}% recovery data ({} extra pages), but --max-recovery-pages limits it \
+             to {:.0}% ({} extra pages)",
+            self.requested_redundancy * 100.0,
+            extra_pages(self.requested_recovery_page_count),
+            self.effective_redundancy() * 100.0,
+            extra_pages(self.recovery_page_count),
+        ))
+    }
+
+    /// If rounding up to whole pages gave substantially more redundancy than was requested,
+    /// describe the difference so that the user is not surprised by the page count.
+    pub fn redundancy_note(&self) -> Option<String> {
+        let effective = self.effective_redundancy();
+        if effective <= self.requested_redundancy * 1.5 {
+            return None;
+        }
+        Some(format!(
+            "requested {:.0}% recovery data, but rounding up to whole pages ({} codes each) gives \
+             {:.0}% ({} extra codes)",
+            self.requested_redundancy * 100.0,
+            self.shards_per_page,
+            effective * 100.0,
+            self.recovery_shard_count - self.data_shard_count,
+        ))
+    }
 }
 
-/// Compute layout options.
+/// Escape a query parameter value, leaving only the characters that never need escaping.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Compute the number of data shards, the number of pages needed to recover, and the total number
+/// of pages, for the given shard sizes.  The total is limited to `max_recovery_pages` more than
+/// the pages needed to recover, if given.
+fn page_counts(
+    recovery_factor: &RecoveryFactor,
+    max_recovery_pages: Option<usize>,
+    data_size: usize,
+    data_bytes_per_shard: usize,
+    shards_per_page: usize,
+) -> (usize, usize, usize) {
+    // The buffer needs to be resized to append the original file size, so we need to grow it
+    // a bit.
+    let buffer_size = (size_of::<u64>() + data_size).next_multiple_of(data_bytes_per_shard);
+    let data_shard_count = buffer_size.div_ceil(data_bytes_per_shard);
+    let data_page_count = data_shard_count.div_ceil(shards_per_page);
+    let recovery_page_count = data_page_count
+        + match recovery_factor {
+            RecoveryFactor::Pages(c) => *c,
+            RecoveryFactor::Percentage(p) => {
+                ((p / 100.0 * data_shard_count as f32) as usize).div_ceil(shards_per_page)
+            }
+        };
+    let recovery_page_count = match max_recovery_pages {
+        Some(max) => std::cmp::min(recovery_page_count, data_page_count + max),
+        None => recovery_page_count,
+    };
+    (data_shard_count, data_page_count, recovery_page_count)
+}
+
+/// The size of each metadata QR code in the banner, including its quiet zone.
+pub const META_CODE_LENGTH: Pt = Pt(128.);
+
+/// How much larger the banner text is in accessible mode.
+pub const ACCESSIBLE_FONT_SCALE: f32 = 1.4;
+
+/// The number of lines reserved for the description in accessible mode, where it has its own row.
+pub const ACCESSIBLE_DESCRIPTION_LINES: f32 = 8.;
+
+/// The smallest module length that reliably survives printing and scanning: about five dots on a
+/// 300dpi printer.
+pub const MIN_SCANNABLE_MODULE_LENGTH: Mm = Mm(0.4);
+
+/// How much `--auto-module` enlarges the module length by at each step.
+const AUTO_MODULE_STEP: Mm = Mm(0.1);
+
+/// The module length requested on the command line, or the default for the paper size.
+pub fn requested_module_length(args: &CreateArgs) -> Mm {
+    args.module_length
+        .unwrap_or_else(|| args.paper_size.default_module_length())
+}
+
+/// Compute layout options.  With `--auto-module`, the module length is enlarged until the codes
+/// can be scanned reliably.
 pub fn compute(
     args: &CreateArgs,
     data_size: usize,
     identifier: Identifier,
     data_hash: Sha512Array,
+    signature: Option<Signature>,
 ) -> Result<Options> {
-    let page: PageDimensions = args.paper_size.into();
-    let avail_width = page.width - args.margin_left - args.margin_right;
-    let avail_height = page.height - args.margin_top - args.margin_bottom;
+    compute_for_paper(
+        args,
+        args.paper_size,
+        data_size,
+        identifier,
+        data_hash,
+        signature,
+    )
+}
+
+/// Compute layout options as for [`compute`], but for the given paper size instead of the one in
+/// `args`.
+pub fn compute_for_paper(
+    args: &CreateArgs,
+    paper_size: PaperSize,
+    data_size: usize,
+    identifier: Identifier,
+    data_hash: Sha512Array,
+    signature: Option<Signature>,
+) -> Result<Options> {
+    let template = match args.layout_template {
+        Some(ref path) => Some(LayoutTemplate::read(path)?),
+        None => None,
+    };
+    // With a template, the code sizes are fixed, so the module length is only a lower bound.
+    let mut module_length = match template {
+        Some(_) => args.module_length.unwrap_or(MIN_SCANNABLE_MODULE_LENGTH),
+        None => args
+            .module_length
+            .unwrap_or_else(|| paper_size.default_module_length()),
+    };
+    loop {
+        let layout = compute_with_module_length(
+            args,
+            paper_size,
+            template.as_ref(),
+            module_length,
+            data_size,
+            identifier,
+            data_hash,
+            signature,
+        )?;
+        if !args.auto_module || layout.module_length >= MIN_SCANNABLE_MODULE_LENGTH {
+            return Ok(layout);
+        }
+        // Round to avoid accumulating floating point errors.
+        module_length = Mm(((module_length.0 + AUTO_MODULE_STEP.0) * 100.0).round() / 100.0);
+    }
+}
+
+/// Compute layout options for a given paper size and module length.  With a template, the module
+/// length is instead the smallest that the codes may use.
+#[allow(clippy::too_many_arguments)]
+fn compute_with_module_length(
+    args: &CreateArgs,
+    paper_size: PaperSize,
+    template: Option<&LayoutTemplate>,
+    module_length: Mm,
+    data_size: usize,
+    identifier: Identifier,
+    data_hash: Sha512Array,
+    signature: Option<Signature>,
+) -> Result<Options> {
+    let page: PageDimensions = paper_size.into();
+    // Margins narrower than the page quiet zone are widened to it, so that nothing is printed in
+    // the blank border around the page.
+    let quiet_zone = args.page_quiet_zone.unwrap_or_default();
+    let [margin_top, margin_right, margin_bottom, margin_left] = [
+        args.margin_top,
+        args.margin_right,
+        args.margin_bottom,
+        args.margin_left,
+    ]
+    .map(|margin| std::cmp::max(margin, quiet_zone));
+    let payload_identifier_length = if args.compact_headers {
+        COMPACT_IDENTIFIER_LENGTH
+    } else {
+        IDENTIFIER_LENGTH
+    };
+    let payload_header_length = PayloadHeader::length(payload_identifier_length);
+    let avail_width = page.width - margin_left - margin_right;
+    let avail_height = page.height - margin_top - margin_bottom;
     let avail_min = std::cmp::min(avail_width, avail_height);
+    // The banner needs to hold the repository bar and the metadata QR codes; in accessible mode,
+    // the larger text needs more room, and the description has a row of its own.
+    let min_banner_height: Mm = if args.accessible {
+        META_CODE_LENGTH + Pt((28. + 10. * ACCESSIBLE_DESCRIPTION_LINES) * ACCESSIBLE_FONT_SCALE)
+    } else {
+        META_CODE_LENGTH + Pt(28.)
+    }
+    .into();
+    // Without an explicit banner height, the codes are laid out in a square.
+    let (code_area_width, code_area_height) = match args.banner_height {
+        None => {
+            let max_code_area_height = avail_height - args.banner_gap - min_banner_height;
+            if args.accessible {
+                // Shrink the code area rather than the text.
+                (avail_min, std::cmp::min(avail_width, max_code_area_height))
+            } else {
+                if max_code_area_height < avail_width {
+                    Err(anyhow!(
+                        "banner gap must be at most {:.1}mm",
+                        (avail_height - avail_width - min_banner_height).0
+                    ))?;
+                }
+                (avail_min, avail_width)
+            }
+        }
+        Some(ref banner_height) => {
+            let banner_height = match banner_height {
+                BannerHeight::Length(length) => *length,
+                BannerHeight::Percentage(p) => avail_height * (p / 100.0),
+            };
+            let max_banner_height = avail_height - args.banner_gap;
+            if banner_height < min_banner_height || banner_height >= max_banner_height {
+                Err(anyhow!(
+                    "banner height must be between {:.1}mm and {:.1}mm",
+                    min_banner_height.0,
+                    max_banner_height.0
+                ))?;
+            }
+            (avail_width, max_banner_height - banner_height)
+        }
+    };
+    // The metadata QR codes are in a row in the banner, and need to leave room for the text.
+    let meta_code_length: Mm = META_CODE_LENGTH.into();
+    if avail_width - meta_code_length * (args.meta_copies as f32) < meta_code_length {
+        Err(anyhow!(
+            "not enough room for {} metadata codes in the banner; try fewer copies or a wider page",
+            args.meta_copies
+        ))?;
+    }
+    if let Some(template) = template {
+        // The banner is always at the top of template pages.
+        template.check(
+            &page,
+            quiet_zone,
+            page.height - margin_bottom - code_area_height,
+        )?;
+    }
     // Width of a quiet zone
-    let quiet_zone_width = args.module_length * 4.0;
+    let quiet_zone_width = module_length * 4.0;
+    if args
+        .max_codes_per_page
+        .is_some_and(|max_codes| max_codes < args.row_count)
+    {
+        Err(Failure::BadArguments(
+            "max-codes-per-page must be at least row-count".to_string(),
+        ))?;
+    }
+    if args
+        .max_error_correction
+        .is_some_and(|max_level| max_level < args.error_correction)
+    {
+        Err(Failure::BadArguments(
+            "max-error-correction must be at least error-correction".to_string(),
+        ))?;
+    }
+
+    if template.is_none() {
+        // Even the smallest code needs 21 modules, plus a quiet zone on each side.
+        let smallest_code_modules = (qrcode::Version::Normal(1).width() + 8) as f32;
+        let code_area_min = match args.banner_height {
+            None => code_area_width,
+            Some(_) => std::cmp::min(code_area_width, code_area_height),
+        };
+        if module_length * smallest_code_modules > code_area_min {
+            Err(anyhow!(
+                "a module length of {:.2}mm is too large for {paper_size:?} paper: not even the \
+                 smallest QR code fits, which needs modules of at most {:.2}mm; try a smaller \
+                 --module-length or a larger paper size",
+                module_length.0,
+                (code_area_min / smallest_code_modules).0
+            ))?;
+        }
+    }
 
     // Compute the best QR code parameters to use: within the constraints of the minimum number of
     // codes per row and minimum error correction level (as found in `args`), calculate the maximum
-    // amount of data we can fit into one page, and pick the highest value.
+    // amount of data we can fit into one page, and pick the highest value.  When optimizing for
+    // reliability, instead pick the fewest total pages, then the highest error correction level,
+    // then the smallest version.
     let mut best_data_bytes_per_page = 0;
+    let mut best_page_count = usize::MAX;
     let mut best_version = qrcode::Version::Normal(1);
     let mut best_ec_level = EcLevel::L;
     let mut best_shards_per_row = 0;
+    let mut best_module_length = module_length;
+    let mut best_shards_per_page = 0;
     let mut best_data_bytes_per_shard = 0;
     for version_value in 1..=40 {
         let version = qrcode::Version::Normal(version_value);
-        // Width per QR code, with one side of quiet zone.
-        let width_per_shard = args.module_length * (version.width() + 4).into();
-        let shards_per_row = ((avail_min - quiet_zone_width) / width_per_shard).floor() as usize;
-        if shards_per_row < args.row_count {
+        let (version_module_length, shards_per_row, codes_per_page) = match template {
+            Some(template) => {
+                // Every code is drawn at the same scale, as large as the smallest position allows.
+                let version_module_length = template.min_size() / (version.width() + 8) as f32;
+                if version_module_length < module_length {
+                    continue;
+                }
+                let codes_per_page = template.positions.len();
+                (version_module_length, codes_per_page, codes_per_page)
+            }
+            None => {
+                // Width per QR code, with one side of quiet zone.
+                let width_per_shard = module_length * (version.width() + 4).into();
+                let shards_per_row =
+                    ((code_area_width - quiet_zone_width) / width_per_shard).floor() as usize;
+                if shards_per_row < args.row_count {
+                    continue;
+                }
+                let rows_per_page = match args.banner_height {
+                    None => shards_per_row,
+                    Some(_) => {
+                        ((code_area_height - quiet_zone_width) / width_per_shard).floor() as usize
+                    }
+                };
+                if rows_per_page == 0 {
+                    continue;
+                }
+                let codes_per_page = match args.max_codes_per_page {
+                    Some(max_codes) => std::cmp::min(shards_per_row * rows_per_page, max_codes),
+                    None => shards_per_row * rows_per_page,
+                };
+                (module_length, shards_per_row, codes_per_page)
+            }
+        };
+        if codes_per_page <= args.page_parity {
             continue;
         }
+        let shards_per_page = codes_per_page - args.page_parity;
         // Try for the most error correction first, if we end up with the same number of bytes
         // per page.
         for ec_level in [EcLevel::H, EcLevel::Q, EcLevel::M, EcLevel::L] {
-            if ec_level < args.error_correction {
+            if ec_level < args.error_correction
+                || args
+                    .max_error_correction
+                    .is_some_and(|max_level| ec_level > max_level)
+            {
                 continue;
             }
             // Number of bits that can be stored in the QR code.
@@ -90,57 +688,125 @@ pub fn compute(
             };
             // Number of bytes available for data in the QR code.
             let raw_byte_count = (bits - mode_indicator_length - char_count_length) / 8;
-            let data_bytes_per_shard = raw_byte_count - PayloadHeader::LENGTH;
+            let data_bytes_per_shard = raw_byte_count - payload_header_length;
             let data_bytes_per_page =
-                data_bytes_per_shard.prev_multiple_of(&64) * shards_per_row * shards_per_row;
-            if data_bytes_per_page > best_data_bytes_per_page {
+                data_bytes_per_shard.prev_multiple_of(&args.shard_granularity) * shards_per_page;
+            let is_better = match args.optimize {
+                Optimize::Density => data_bytes_per_page > best_data_bytes_per_page,
+                Optimize::Reliability => {
+                    if data_bytes_per_shard < args.shard_granularity {
+                        continue;
+                    }
+                    let (_, _, page_count) = page_counts(
+                        &args.recovery_factor,
+                        args.max_recovery_pages,
+                        data_size,
+                        data_bytes_per_shard.prev_multiple_of(&args.shard_granularity),
+                        shards_per_page,
+                    );
+                    // Versions are visited in increasing order, so ties go to the smaller code.
+                    let is_better = page_count < best_page_count
+                        || (page_count == best_page_count && ec_level > best_ec_level);
+                    if is_better {
+                        best_page_count = page_count;
+                    }
+                    is_better
+                }
+            };
+            if is_better {
                 best_data_bytes_per_page = data_bytes_per_page;
                 best_version = version;
                 best_ec_level = ec_level;
                 best_shards_per_row = shards_per_row;
+                best_module_length = version_module_length;
+                best_shards_per_page = shards_per_page;
                 best_data_bytes_per_shard = data_bytes_per_shard;
             }
         }
     }
 
-    if best_data_bytes_per_shard < 64 + PayloadHeader::LENGTH {
-        Err(anyhow!(
-            "Could not find QR code configuration that holds enough data; try lowering row-count"
-        ))
+    // The header length has already been taken out of `best_data_bytes_per_shard`.
+    if best_data_bytes_per_shard < args.shard_granularity {
+        match template {
+            Some(_) => Err(anyhow!(
+                "the template positions are too small for any QR code configuration at \
+                 {:.2}mm per module",
+                module_length.0
+            )),
+            None => Err(anyhow!(
+                "Could not find QR code configuration that holds enough data; try lowering \
+                 row-count"
+            )),
+        }
     } else {
-        let data_bytes_per_shard = best_data_bytes_per_shard.prev_multiple_of(&64);
-        let shards_per_page = best_shards_per_row * best_shards_per_row;
-        // The buffer needs to be resized to append the original file size, so we need to grow it
-        // a bit.
-        let buffer_size = (size_of::<u64>() + data_size).next_multiple_of(data_bytes_per_shard);
-        let data_shard_count = buffer_size.div_ceil(data_bytes_per_shard);
-        let data_page_count = data_shard_count.div_ceil(shards_per_page);
-        let recovery_page_count = data_page_count
-            + match args.recovery_factor {
-                crate::args::RecoveryFactor::Pages(c) => c,
-                crate::args::RecoveryFactor::Percentage(p) => {
-                    ((p / 100.0 * data_shard_count as f32) as usize).div_ceil(shards_per_page)
-                }
-            };
+        let data_bytes_per_shard =
+            best_data_bytes_per_shard.prev_multiple_of(&args.shard_granularity);
+        let shards_per_page = best_shards_per_page;
+        let (data_shard_count, data_page_count, recovery_page_count) = page_counts(
+            &args.recovery_factor,
+            args.max_recovery_pages,
+            data_size,
+            data_bytes_per_shard,
+            shards_per_page,
+        );
+        let (_, _, requested_recovery_page_count) = page_counts(
+            &args.recovery_factor,
+            None,
+            data_size,
+            data_bytes_per_shard,
+            shards_per_page,
+        );
+        let requested_redundancy = match args.recovery_factor {
+            RecoveryFactor::Pages(c) => (c * shards_per_page) as f32 / data_shard_count as f32,
+            RecoveryFactor::Percentage(p) => p / 100.0,
+        };
         Ok(Options {
             page_width: page.width,
             page_height: page.height,
-            margin_bottom: args.margin_bottom,
-            margin_left: args.margin_left,
-            module_length: args.module_length,
-            avail_width: page.width - args.margin_left - args.margin_right,
-            avail_height: page.height - args.margin_top - args.margin_bottom,
+            margin_bottom,
+            margin_left,
+            module_length: best_module_length,
+            module_style: args.module_style,
+            fill_order: args.fill_order,
+            byte_interleave: args.byte_interleave,
+            end_marker: args.end_marker,
+            bundle: false,
+            document_id_length: args.id_length,
+            document_id_encoding: args.id_encoding,
+            compression: args.compress,
+            encryption: None,
+            key_card: false,
+            avail_width: page.width - margin_left - margin_right,
+            avail_height: page.height - margin_top - margin_bottom,
+            code_area_height,
+            banner_gap: args.banner_gap,
 
             identifier,
+            payload_identifier_length,
             hash: data_hash,
+            signature,
             version: best_version,
             level: best_ec_level,
             shards_per_row: best_shards_per_row,
+            code_positions: template.map(|template| template.positions.clone()),
+            shards_per_page,
+            page_parity_count: args.page_parity,
             data_bytes_per_shard,
             data_shard_count,
             recovery_shard_count: recovery_page_count * shards_per_page,
             data_page_count,
             recovery_page_count,
+            requested_recovery_page_count,
+            banner_color: args.banner_color.clone(),
+            description_columns: args.description_columns,
+            vertical_labels: args.vertical_labels,
+            accessible: args.accessible,
+            progress_bar: args.progress_bar,
+            meta_copies: args.meta_copies,
+            index_labels: args.index_labels,
+            big_page_numbers: args.big_page_numbers,
+            print_run: None,
+            requested_redundancy,
         })
     }
 }
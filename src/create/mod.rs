@@ -1,105 +1,834 @@
-mod layout;
+mod canvas;
+pub(crate) mod layout;
+pub(crate) mod raster;
 mod render;
+mod svg;
 use crate::{
-    args::CreateArgs,
-    header::{self, Identifier, IDENTIFIER_LENGTH},
+    args::{
+        CreateArgs, ModuleStyle, OutputFormat, PageDimensions, PaperSize, PngBackground, PngDepth,
+        RulerArgs,
+    },
+    encryption,
+    header::{self, Compression, Identifier, Sha512Array, Signature, IDENTIFIER_LENGTH},
+    manifest::Manifest,
+    signing, Failure,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use chksum_hash_sha2_512 as sha512;
+use clap::ValueEnum;
 use itertools::Itertools;
+use num_integer::Integer;
 use printpdf::PdfDocument;
 use qrcode::QrCode;
 use rayon::prelude::*;
 use reed_solomon_simd::ReedSolomonEncoder;
-use std::{fs, io::BufWriter};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fmt::{self, Write},
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Create a document from the file (and any `--add-file`s), returning a report of what was written;
+/// there is none with `--compare-sizes`, which only prints its table.
+pub(crate) fn create(args: &CreateArgs) -> Result<Option<CreateReport>> {
+    let (data_bytes, bundle) = read_input(args)?;
+    create_from_data(args, data_bytes, bundle, None)
+}
+
+/// Read the file to encode (into memory, for now).  With `--add-file`, all the files are packed
+/// into a bundle, and `true` is returned with it.
+pub(crate) fn read_input(args: &CreateArgs) -> Result<(Vec<u8>, bool)> {
+    let read =
+        |path: &PathBuf| fs::read(path).with_context(|| format!("Failed to read {:?}", path));
+    if args.add_files.is_empty() {
+        return Ok((read(&args.file_path)?, false));
+    }
+    let files = std::iter::once(&args.file_path)
+        .chain(&args.add_files)
+        .map(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| anyhow!("{:?} does not have a usable file name", path))?;
+            Ok((name.to_string(), read(path)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((header::pack_bundle(&files)?, true))
+}
 
-pub(crate) fn create(args: &CreateArgs) -> Result<()> {
-    // Read the file (into memory, for now)
-    let mut data_bytes = fs::read(&args.file_path)
-        .map_err(|e| anyhow!("Failed to read {:?}: {}", &args.file_path, e))?;
+/// Create a document from data that is already in memory.  `bundle` is whether the data is a
+/// bundle of files, and `existing_signature` is a signature of the data from an earlier document,
+/// kept unless `--sign-key` is given.
+pub(crate) fn create_from_data(
+    args: &CreateArgs,
+    mut data_bytes: Vec<u8>,
+    bundle: bool,
+    existing_signature: Option<Signature>,
+) -> Result<Option<CreateReport>> {
     let data_size = u64::try_from(data_bytes.len())
         .map_err(|e| anyhow!("{:?} is too large: {e}", &args.file_path))?;
-    let mut hasher = sha512::new();
-    hasher.update(&data_bytes);
-    let mut identifier_hash = hasher.clone();
-    let digest = hasher.digest().into_inner();
-    identifier_hash.update(&args.override_commit);
-    let identifier: Identifier =
-        identifier_hash.digest().into_inner()[..IDENTIFIER_LENGTH].try_into()?;
+    let (identifier, digest) = identify(args, &data_bytes)?;
+
+    let signature = args
+        .sign_key
+        .as_ref()
+        .map(|key_path| signing::sign(key_path, &digest))
+        .transpose()?
+        .or(existing_signature);
+
+    // With --porcelain, standard output only has the summary; everything else goes to stderr.
+    let report = |message: String| {
+        if args.porcelain {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    };
+
+    // The hash is of the original file, so that the restored file is checked once it has been
+    // decompressed.
+    if args.compress != Compression::None && looks_incompressible(&data_bytes) {
+        report(format!(
+            "Warning: the file looks already compressed or encrypted, so --compress {} is unlikely \
+             to make it smaller",
+            format!("{:?}", args.compress).to_lowercase()
+        ));
+    }
+    data_bytes = compress(args, data_bytes)?;
+    if args.compress != Compression::None {
+        report(format!(
+            "Compressed the file from {data_size} to {} bytes",
+            data_bytes.len()
+        ));
+    }
+    let encryption = if args.encrypt {
+        let passphrase = encryption::passphrase(true)?;
+        let (encryption, encrypted) = encryption::encrypt(&passphrase, &data_bytes)?;
+        data_bytes = encrypted;
+        report("Encrypted the file".to_string());
+        Some(encryption)
+    } else {
+        None
+    };
+    let stored_size = data_bytes.len() as u64;
+
+    // The marker is part of the data as far as the layout is concerned, but not of its size.
+    if args.end_marker {
+        data_bytes.extend_from_slice(header::END_MARKER);
+    }
+
+    if args.compare_sizes {
+        compare_sizes(args, data_bytes.len(), identifier, digest, signature)?;
+        return Ok(None);
+    }
+    // An existing directory as the output gets an image of each page.
+    let format = match args.out_path {
+        Some(ref out_path) if args.format == OutputFormat::Pdf && out_path.is_dir() => {
+            OutputFormat::Png
+        }
+        _ => args.format,
+    };
+    if format != OutputFormat::Pdf {
+        let unsupported = [
+            ("--n-up", args.n_up > 1),
+            ("--decoder-spec", args.decoder_spec),
+            ("--web-restorer", args.web_restorer.is_some()),
+            ("--spine-label", args.spine_label),
+            ("--progress-bar", args.progress_bar),
+            ("--vertical-labels", args.vertical_labels),
+            ("--pdf-hash", args.pdf_hash),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+            Err(Failure::BadArguments(format!(
+                "{option} is not supported with --format {}",
+                format!("{format:?}").to_lowercase()
+            )))?;
+        }
+    }
+    if args.png_background == PngBackground::Transparent && args.png_depth == PngDepth::One {
+        Err(Failure::BadArguments(
+            "--png-background transparent needs --png-depth 8".to_string(),
+        ))?;
+    }
 
     // Calculate the layout parameters.
-    let layout = layout::compute(args, data_bytes.len(), identifier, digest)?;
+    let mut layout = layout::compute(args, data_bytes.len(), identifier, digest, signature)?;
+    layout.bundle = bundle;
+    layout.encryption = encryption;
+    layout.key_card = args.emit_key_card.is_some();
+    if args.print_run_id {
+        layout.print_run = Some(format!("{}, {}", print_run_id(), render::timestamp()));
+    }
+    let requested_module_length = layout::requested_module_length(args);
+    // With a layout template, the module length comes from the template's sizes.
+    if layout.module_length != requested_module_length && layout.code_positions.is_none() {
+        report(format!(
+            "Enlarged the module length from {:.2}mm to {:.2}mm so that the codes can be scanned",
+            requested_module_length.0, layout.module_length.0
+        ));
+    } else if layout.module_length < layout::MIN_SCANNABLE_MODULE_LENGTH {
+        report(format!(
+            "Warning: modules of {:.2}mm are smaller than {:.1}mm and may not scan reliably; \
+             use a larger --module-length, or --auto-module to pick one",
+            layout.module_length.0,
+            layout::MIN_SCANNABLE_MODULE_LENGTH.0
+        ));
+    }
+    let page_count = layout.recovery_page_count + front_page_count(args);
+    if let Some(max_pages) = args.max_pages {
+        if page_count > max_pages {
+            Err(anyhow!(
+                "the document would need {page_count} pages, more than the maximum of {max_pages}; \
+                 try compressing the file, lowering --recovery-factor, or a smaller --module-length"
+            ))?;
+        }
+    }
+    if let Some(note) = layout.recovery_cap_note() {
+        report(format!("Warning: {note}"));
+    }
+    if let Some(note) = layout.redundancy_note() {
+        report(format!("Note: {note}"));
+    }
+    if args.dry_run {
+        return Ok(Some(CreateReport::new(&layout, None, page_count)?));
+    }
+    // Check for existing page files before the slow work of generating the codes.
+    let page_paths = match (&args.out_path, format) {
+        (Some(out_path), OutputFormat::Svg) => {
+            Some(page_paths(out_path, page_count, "svg", args.force)?)
+        }
+        (Some(out_path), OutputFormat::Png) => {
+            Some(page_paths(out_path, page_count, "png", args.force)?)
+        }
+        _ => None,
+    };
+    if let Some(ref manifest_path) = args.manifest {
+        Manifest::new(args, &layout, data_size)?.write(manifest_path)?;
+    }
 
-    // Given the QR code info, resize the data to have the actual size appended.  This is necessary
-    // so that we can avoid having trailing null bytes at the end after decode.
-    let buffer_size =
-        (size_of::<u64>() + data_bytes.len()).next_multiple_of(layout.data_bytes_per_shard);
-    data_bytes.resize(buffer_size, 0);
-    LittleEndian::write_u64(&mut data_bytes[buffer_size - size_of::<u64>()..], data_size);
+    pad_data(&layout, &mut data_bytes, stored_size);
+
+    let cached_shards = match args.shard_cache {
+        Some(ref cache_dir) => read_shard_cache(&layout, cache_dir)?,
+        None => None,
+    };
+    let shards = match (cached_shards, &args.shard_cache) {
+        (Some(shards), Some(cache_dir)) => {
+            report(format!(
+                "Reused the shards cached in {}",
+                cache_dir.display()
+            ));
+            shards
+        }
+        (_, cache_dir) => {
+            let shards = generate_shards(&layout, &data_bytes, identifier)?;
+            if let Some(cache_dir) = cache_dir {
+                dump_shards(&layout, &shards, cache_dir)?;
+            }
+            shards
+        }
+    };
+    if let (Some(key_card_path), Some(encryption)) = (&args.emit_key_card, &layout.encryption) {
+        write_key_card(args, &layout, encryption, key_card_path)?;
+        report(format!("Wrote the key card to {}", key_card_path.display()));
+    }
+    if let Some(ref dump_dir) = args.dump_shards {
+        dump_shards(&layout, &shards, dump_dir)?;
+        report(format!(
+            "Wrote {} shards to {}",
+            shards.len() + 1,
+            dump_dir.display()
+        ));
+    }
+    if let Some(ref matrices_path) = args.emit_matrices {
+        emit_matrices(&layout, &shards, matrices_path)?;
+        report(format!(
+            "Wrote the matrices of {} codes to {}",
+            shards.len() + 1,
+            matrices_path.display()
+        ));
+    }
+    let Some(ref out_path) = args.out_path else {
+        return Ok(Some(CreateReport::new(&layout, None, page_count)?));
+    };
 
-    let mut svgs = generate_svgs(&layout, &data_bytes, identifier)?;
-    let shards_per_page = layout.shards_per_row * layout.shards_per_row;
-    let svg_chunks = svgs.drain(..).chunks(shards_per_page);
+    let page_checksums = shards
+        .chunks(layout.codes_per_page())
+        .map(|page_shards| header::page_checksum(page_shards.iter().map(Vec::as_slice)))
+        .collect::<Vec<_>>();
+    if let Some(page_paths) = page_paths {
+        let codes = generate_codes(&layout, &shards)?;
+        codes
+            .par_chunks(layout.codes_per_page())
+            .enumerate()
+            .try_for_each(|(page_num, page_codes)| -> Result<()> {
+                let page_checksum = args
+                    .page_checksums
+                    .then(|| page_checksums[page_num].as_str());
+                let page = match format {
+                    OutputFormat::Png => raster::render_page(
+                        &layout,
+                        page_codes,
+                        page_num,
+                        page_checksum,
+                        &args.override_commit,
+                        &raster::PngOptions {
+                            dpi: args.dpi,
+                            background: args.png_background,
+                            depth: args.png_depth,
+                        },
+                    )?,
+                    _ => svg::render_page(
+                        &layout,
+                        page_codes,
+                        page_num,
+                        page_checksum,
+                        &args.override_commit,
+                    )?
+                    .into_bytes(),
+                };
+                write_with_retry(&page_paths[page_num], &page)
+                    .with_context(|| format!("Failed to write {}", page_paths[page_num].display()))
+            })?;
+        return Ok(Some(CreateReport {
+            page_paths,
+            ..CreateReport::new(&layout, Some(out_path), page_count)?
+        }));
+    }
+    let mut svgs = generate_svgs(&layout, &shards)?;
+    let svg_chunks = svgs.drain(..).chunks(layout.codes_per_page());
 
     // Set up the PDF document.
-    let (doc, mut page_index, mut layer_index) = PdfDocument::new(
-        args.file_path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or("PaperBack"),
-        layout.page_width,
-        layout.page_height,
-        "",
-    );
+    let file_name = args
+        .file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("PaperBack");
+    let (sheet_width, sheet_height) =
+        render::sheet_size(layout.page_width, layout.page_height, args.n_up);
+    let (doc, page_index, layer_index) = PdfDocument::new(file_name, sheet_width, sheet_height, "");
+    let mut sheets = render::Sheets::new(&doc, page_index, layer_index, &layout, args.n_up);
+
+    if let Some(ref base_url) = args.web_restorer {
+        render::render_web_restorer(
+            &layout,
+            &layout.restorer_url(base_url, file_name),
+            &doc,
+            &sheets.next_page()?,
+            &args.override_commit,
+        )?;
+    }
+    if args.decoder_spec {
+        render::render_cover(&layout, &doc, &sheets.next_page()?, &args.override_commit)?;
+    }
 
     // Fill in the PDF pages.  The PDF references don't implement Send, so we can't work with them
     // in parallel here.
     for (page_num, mut page_svgs) in svg_chunks.into_iter().enumerate() {
-        if page_num > 0 {
-            (page_index, layer_index) = doc.add_page(layout.page_width, layout.page_height, "");
-        }
-        let page = doc.get_page(page_index);
-        let layer = page.get_layer(layer_index);
+        let layer = sheets.next_page()?;
         render::render_page(
             &layout,
             &mut page_svgs,
             page_num,
+            args.page_checksums
+                .then(|| page_checksums[page_num].as_str()),
             &doc,
             &layer,
             &args.override_commit,
         )?;
     }
 
-    doc.save(&mut BufWriter::new(fs::File::create(
-        args.out_path.clone(),
-    )?))?;
+    if args.spine_label {
+        render::render_spine_label(&layout, file_name, page_count, &doc, &sheets.next_page()?)?;
+    }
+    let sheet_count = sheets.finish();
 
-    println!(
-        "Wrote {} pages to {} ({} {:?}{:?} shards, {} needed to recover)",
-        layout.recovery_page_count,
-        args.out_path.display(),
-        layout.recovery_shard_count,
-        layout.version,
-        layout.level,
-        layout.data_shard_count
+    // Render the PDF in memory first, so that a failure to write it does not lose the work.
+    let pdf_bytes = doc.save_to_bytes()?;
+    write_pdf(out_path, &pdf_bytes)?;
+    let pdf_hash = args
+        .pdf_hash
+        .then(|| header::to_hex(&Sha256::digest(&pdf_bytes)));
+    if let Some(ref pdf_hash) = pdf_hash {
+        if args.pdf_hash_file {
+            write_hash_file(out_path, pdf_hash)?;
+        }
+    }
+
+    Ok(Some(CreateReport {
+        pdf_sha256: pdf_hash,
+        draft_sheet_count: (args.n_up > 1).then_some(sheet_count),
+        ..CreateReport::new(&layout, Some(out_path), page_count)?
+    }))
+}
+
+/// The payload shards of the document that `create` makes from the given data with these options,
+/// and its layout; this is used to `verify` a scan against the original file.  `bundle` is whether
+/// the data is a bundle of files, as returned by [`read_input`].
+pub(crate) fn document_shards(
+    args: &CreateArgs,
+    data_bytes: Vec<u8>,
+    bundle: bool,
+) -> Result<(layout::Options, Vec<Vec<u8>>)> {
+    if args.encrypt {
+        Err(Failure::BadArguments(
+            "an encrypted document cannot be created again, as its salt and nonce are random"
+                .to_string(),
+        ))?;
+    }
+    let (identifier, digest) = identify(args, &data_bytes)?;
+    let mut data_bytes = compress(args, data_bytes)?;
+    let stored_size = data_bytes.len() as u64;
+    if args.end_marker {
+        data_bytes.extend_from_slice(header::END_MARKER);
+    }
+    // The signature is only stored in the metadata, so it does not change the payload shards.
+    let mut layout = layout::compute(args, data_bytes.len(), identifier, digest, None)?;
+    layout.bundle = bundle;
+    pad_data(&layout, &mut data_bytes, stored_size);
+    let shards = generate_shards(&layout, &data_bytes, identifier)?;
+    Ok((layout, shards))
+}
+
+/// Compress the data as asked by `--compress` and `--compress-level`, checking that the level is
+/// one that the compression has.
+fn compress(args: &CreateArgs, data_bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if let Some(level) = args.compress_level {
+        let Some(levels) = args.compress.levels() else {
+            Err(Failure::BadArguments(
+                "--compress-level needs --compress gzip or --compress zstd".to_string(),
+            ))?
+        };
+        if !levels.contains(&level) {
+            Err(Failure::BadArguments(format!(
+                "--compress-level for {} must be from {} to {}, not {level}",
+                format!("{:?}", args.compress).to_lowercase(),
+                levels.start(),
+                levels.end()
+            )))?;
+        }
+    }
+    args.compress.compress(data_bytes, args.compress_level)
+}
+
+/// Whether the start of the data is so close to random that compressing it is pointless, as it is
+/// for archives, most image formats and encrypted files.  This estimates the entropy of the bytes
+/// in the first 64 KiB, which is near 8 bits per byte for such data and well below it for text.
+fn looks_incompressible(data_bytes: &[u8]) -> bool {
+    let sample = &data_bytes[..data_bytes.len().min(64 * 1024)];
+    // Too few bytes to tell; the estimate of random data only nears 8 bits from about 1 KiB.
+    if sample.len() < 1024 {
+        return false;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / sample.len() as f64;
+            -p * p.log2()
+        })
+        .sum();
+    entropy > 7.5
+}
+
+/// Hash the given data, and derive the document's identifier from the hash.
+fn identify(args: &CreateArgs, data_bytes: &[u8]) -> Result<(Identifier, Sha512Array)> {
+    let hasher = hash_data(data_bytes);
+    let mut identifier_hash = hasher.clone();
+    let digest = hasher.digest().into_inner();
+    identifier_hash.update(&args.override_commit);
+    if let Some(ref salt) = args.identifier_salt {
+        identifier_hash.update(salt);
+    }
+    let identifier: Identifier =
+        identifier_hash.digest().into_inner()[..IDENTIFIER_LENGTH].try_into()?;
+    Ok((identifier, digest))
+}
+
+/// Given the QR code info, resize the data to have its size (before any end marker) appended.
+/// This is necessary so that we can avoid having trailing null bytes at the end after decode.
+/// The padding of an encrypted document is random rather than zeros, so that it cannot be told
+/// apart from the encrypted data; restoring only relies on the appended size.
+fn pad_data(layout: &layout::Options, data_bytes: &mut Vec<u8>, data_size: u64) {
+    let buffer_size =
+        (size_of::<u64>() + data_bytes.len()).next_multiple_of(layout.data_bytes_per_shard);
+    let padding_start = data_bytes.len();
+    data_bytes.resize(buffer_size, 0);
+    if layout.encryption.is_some() {
+        encryption::fill_random(&mut data_bytes[padding_start..buffer_size - size_of::<u64>()]);
+    }
+    LittleEndian::write_u64(&mut data_bytes[buffer_size - size_of::<u64>()..], data_size);
+    if layout.byte_interleave {
+        *data_bytes = header::interleave_bytes(data_bytes, layout.data_shard_count);
+    }
+}
+
+/// A random ID for this printing of the document, so that different printings of the same
+/// document can be told apart.  It is only printed, and never affects restoring.
+fn print_run_id() -> String {
+    let mut hasher = sha512::new();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.update(now.as_nanos().to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    header::to_hex(&hasher.digest().into_inner()[..4]).to_uppercase()
+}
+
+/// The number of pages printed before the pages of codes.
+fn front_page_count(args: &CreateArgs) -> usize {
+    usize::from(args.web_restorer.is_some()) + usize::from(args.decoder_spec)
+}
+
+/// Create a calibration page, with a single QR code and a ruler labelled with their sizes.
+pub(crate) fn ruler(args: &RulerArgs) -> Result<()> {
+    let page: PageDimensions = args.paper_size.into();
+    let module_length = args
+        .module_length
+        .unwrap_or_else(|| args.paper_size.default_module_length());
+    let (doc, page_index, layer_index) =
+        PdfDocument::new("PaperBack calibration", page.width, page.height, "");
+    render::render_ruler(
+        &doc,
+        &doc.get_page(page_index).get_layer(layer_index),
+        &page,
+        module_length,
+        qrcode::Version::Normal(args.qr_version),
+        args.dpi,
+    )?;
+    write_with_retry(&args.out_path, &doc.save_to_bytes()?)
+        .with_context(|| format!("Failed to write {}", args.out_path.display()))?;
+    println!("Wrote a calibration page to {}", args.out_path.display());
+    Ok(())
+}
+
+/// Write the key card of an encrypted document as a one page PDF, the size of the document's pages.
+fn write_key_card(
+    args: &CreateArgs,
+    layout: &layout::Options,
+    encryption: &header::Encryption,
+    path: &Path,
+) -> Result<()> {
+    let (doc, page_index, layer_index) = PdfDocument::new(
+        format!("Key card for {}", layout.document_id()),
+        layout.page_width,
+        layout.page_height,
+        "",
     );
+    render::render_key_card(
+        layout,
+        encryption,
+        &doc,
+        &doc.get_page(page_index).get_layer(layer_index),
+        &args.override_commit,
+    )?;
+    write_pdf(path, &doc.save_to_bytes()?)
+}
 
+/// Print a table of the layouts the document would have on each paper size, without creating it.
+fn compare_sizes(
+    args: &CreateArgs,
+    data_size: usize,
+    identifier: Identifier,
+    digest: header::Sha512Array,
+    signature: Option<Signature>,
+) -> Result<()> {
+    println!(
+        "{:<8} {:>6} {:>11} {:>8}  QR codes",
+        "Paper", "Pages", "Codes/page", "Module"
+    );
+    for &paper_size in PaperSize::value_variants() {
+        let name = format!("{paper_size:?}");
+        match layout::compute_for_paper(args, paper_size, data_size, identifier, digest, signature)
+        {
+            Ok(layout) => println!(
+                "{name:<8} {:>6} {:>11} {:>6.2}mm  {:?}{:?}",
+                layout.recovery_page_count + front_page_count(args),
+                layout.codes_per_page(),
+                layout.module_length.0,
+                layout.version,
+                layout.level
+            ),
+            Err(e) => println!("{name:<8} does not fit: {e}"),
+        }
+    }
     Ok(())
 }
 
-/// Generate the SVGs from the given data bytes.
-fn generate_svgs(
+/// `CreateReport` describes the document that `create` laid out, and where it was written (unless
+/// only its layout or shards were asked for), for the caller to print.  With `--porcelain` it is
+/// printed as a single line of JSON, so that scripts can pick out the details of the document.
+/// Fields may be added, but existing ones will not change.
+#[derive(Debug, Serialize)]
+pub(crate) struct CreateReport {
+    pub out_path: Option<PathBuf>,
+    /// The files the pages were written to, when each page has its own file.
+    #[serde(skip)]
+    pub page_paths: Vec<PathBuf>,
+    pub page_count: usize,
+    pub data_shard_count: usize,
+    pub recovery_shard_count: usize,
+    pub shards_per_page: usize,
+    pub shards_per_row: usize,
+    /// The number of pages needed to restore the document.
+    pub data_page_count: usize,
+    /// The number of pages of codes, leaving out any cover pages.
+    pub recovery_page_count: usize,
+    #[serde(skip)]
+    pub page_parity_count: usize,
+    pub shard_bytes: usize,
+    pub module_length_mm: f32,
+    #[serde(skip)]
+    pub version: qrcode::Version,
+    pub qr_version: i16,
+    #[serde(skip)]
+    pub level: qrcode::EcLevel,
+    pub error_correction: String,
+    pub identifier: String,
+    pub hash: String,
+    pub document_id: String,
+    /// The SHA-256 hash of the PDF file, with `--pdf-hash`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdf_sha256: Option<String>,
+    /// The number of sheets the pages were printed on, with `--n-up`.
+    #[serde(skip)]
+    pub draft_sheet_count: Option<usize>,
+}
+
+impl CreateReport {
+    fn new(layout: &layout::Options, out_path: Option<&Path>, page_count: usize) -> Result<Self> {
+        let meta = layout.meta_header()?;
+        Ok(CreateReport {
+            out_path: out_path.map(Path::to_path_buf),
+            page_paths: Vec::new(),
+            page_count,
+            data_shard_count: layout.data_shard_count,
+            recovery_shard_count: layout.recovery_shard_count,
+            shards_per_page: layout.shards_per_page,
+            shards_per_row: layout.shards_per_row,
+            data_page_count: layout.data_page_count,
+            recovery_page_count: layout.recovery_page_count,
+            page_parity_count: layout.page_parity_count,
+            shard_bytes: layout.data_bytes_per_shard,
+            module_length_mm: layout.module_length.0,
+            version: layout.version,
+            qr_version: match layout.version {
+                qrcode::Version::Normal(v) | qrcode::Version::Micro(v) => v,
+            },
+            level: layout.level,
+            error_correction: format!("{:?}", layout.level),
+            identifier: header::to_hex(&meta.identifier),
+            hash: header::to_hex(&meta.hash),
+            document_id: meta.document_id(),
+            pdf_sha256: None,
+            draft_sheet_count: None,
+        })
+    }
+
+    /// The warning that an `--n-up` printout is only a draft.
+    pub(crate) fn draft_note(&self) -> Option<String> {
+        self.draft_sheet_count.map(|sheet_count| {
+            format!(
+                "Draft: {} pages on {sheet_count} sheets; do not use this printout to restore",
+                self.page_count
+            )
+        })
+    }
+
+    /// The layout of the document as a table, for `--dry-run`.
+    pub(crate) fn layout_table(&self) -> String {
+        let mut table = String::new();
+        _ = writeln!(
+            table,
+            "Pages:            {} (any {} of them restore the file)",
+            self.page_count, self.data_page_count
+        );
+        _ = writeln!(
+            table,
+            "QR codes:         version {}, error correction {}",
+            self.qr_version, self.error_correction
+        );
+        _ = writeln!(table, "Module length:    {:.2}mm", self.module_length_mm);
+        _ = writeln!(table, "Codes per row:    {}", self.shards_per_row);
+        _ = writeln!(
+            table,
+            "Codes per page:   {}{}",
+            self.shards_per_page + self.page_parity_count,
+            if self.page_parity_count > 0 {
+                format!(" ({} of them parity)", self.page_parity_count)
+            } else {
+                String::new()
+            }
+        );
+        _ = writeln!(table, "Bytes per code:   {}", self.shard_bytes);
+        _ = writeln!(
+            table,
+            "Shards:           {} printed, {} needed to restore",
+            self.recovery_shard_count, self.data_shard_count
+        );
+        table
+    }
+}
+
+/// Where the document was written, and with what codes; followed by the hash of the PDF and the
+/// draft warning, when there are any.
+impl fmt::Display for CreateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Wrote {} pages to ", self.page_count)?;
+        match (
+            self.page_paths.first(),
+            self.page_paths.last(),
+            &self.out_path,
+        ) {
+            (Some(first), Some(last), _) => {
+                write!(f, "{} ... {}", first.display(), last.display())?
+            }
+            (_, _, Some(out_path)) => write!(f, "{}", out_path.display())?,
+            _ => write!(f, "nowhere")?,
+        }
+        write!(
+            f,
+            " ({} {:?}{:?} shards, {} per page, {} needed to recover)",
+            self.recovery_shard_count,
+            self.version,
+            self.level,
+            self.shards_per_page,
+            self.data_shard_count
+        )?;
+        if let Some(ref pdf_hash) = self.pdf_sha256 {
+            write!(f, "\nPDF SHA-256: {pdf_hash}")?;
+        }
+        if let Some(note) = self.draft_note() {
+            write!(f, "\n{note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the hash of the PDF at `out_path` to a file next to it, with `.sha256` appended to its
+/// name, in the format that `sha256sum --check` reads.
+fn write_hash_file(out_path: &Path, pdf_hash: &str) -> Result<()> {
+    let mut hash_path = out_path.as_os_str().to_owned();
+    hash_path.push(".sha256");
+    let file_name = out_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    write_with_retry(
+        Path::new(&hash_path),
+        format!("{pdf_hash}  {file_name}\n").as_bytes(),
+    )
+    .with_context(|| format!("Failed to write {}", Path::new(&hash_path).display()))
+}
+
+/// Whether a failed write is worth retrying: errors such as a missing directory or a read-only file
+/// system will not go away by themselves, but a busy or timed out network file system may.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Write the given bytes to a file, retrying a few times (with increasing delays) to ride out
+/// transient errors, such as on network file systems; other errors are returned straight away.
+fn write_with_retry(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    const ATTEMPTS: u32 = 4;
+    let mut delay = Duration::from_millis(500);
+    let mut attempt = 1;
+    loop {
+        match fs::write(path, bytes) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= ATTEMPTS || !is_transient(&e) => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "Failed to write {} (attempt {attempt} of {ATTEMPTS}): {e}; retrying in {delay:?}",
+                    path.display()
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Write the PDF, as [`write_with_retry`] does.  If that fails, the PDF is written to the temporary
+/// directory instead, so that the work of encoding it is not lost and it can be moved into place.
+fn write_pdf(path: &Path, bytes: &[u8]) -> Result<()> {
+    let Err(error) = write_with_retry(path, bytes) else {
+        return Ok(());
+    };
+    let fallback_path = env::temp_dir().join(path.file_name().unwrap_or("paperback.pdf".as_ref()));
+    fs::write(&fallback_path, bytes).map_err(|fallback_error| {
+        anyhow!(
+            "Failed to write {}: {error}; also failed to write {}: {fallback_error}",
+            path.display(),
+            fallback_path.display()
+        )
+    })?;
+    Err(anyhow!(
+        "Failed to write {}: {error}; the output was written to {} instead",
+        path.display(),
+        fallback_path.display()
+    ))
+}
+
+/// Hash the given data, printing progress to stderr for large inputs if it is a terminal.
+fn hash_data(data: &[u8]) -> sha512::Update {
+    const CHUNK_SIZE: usize = 16 * 1024 * 1024;
+    let mut hasher = sha512::new();
+    let show_progress = data.len() > 4 * CHUNK_SIZE && std::io::stderr().is_terminal();
+    for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+        hasher.update(chunk);
+        if show_progress {
+            let done = index * CHUNK_SIZE + chunk.len();
+            eprint!("\rHashing: {}%", done * 100 / data.len());
+        }
+    }
+    if show_progress {
+        eprintln!();
+    }
+    hasher
+}
+
+/// Generate the payload shards (including headers) from the given data bytes.
+fn generate_shards(
     layout: &layout::Options,
     data_bytes: &[u8],
     identifier: Identifier,
-) -> Result<Vec<printpdf::svg::Svg>> {
+) -> Result<Vec<Vec<u8>>> {
     // Compute the reed-solomon shards.
     let mut rs_encoder = ReedSolomonEncoder::new(
         layout.data_shard_count,
         layout.recovery_shard_count,
         layout.data_bytes_per_shard,
-    )?;
+    )
+    .map_err(|e| {
+        header::shard_parameter_error(
+            e,
+            layout.data_shard_count,
+            layout.recovery_shard_count,
+            layout.data_bytes_per_shard,
+        )
+    })?;
     for index in 0..layout.data_shard_count {
         rs_encoder.add_original_shard(
             &data_bytes
@@ -107,45 +836,557 @@ fn generate_svgs(
         )?;
     }
 
-    // Encode the reed-solomon shards into QR codes.
-    let svgs = rs_encoder
-        .encode()?
-        .recovery_iter()
-        .collect::<Vec<_>>()
+    let encoder_result = rs_encoder.encode()?;
+    let recovery_shards = encoder_result.recovery_iter().collect::<Vec<_>>();
+
+    // Prepend the headers, following each page's shards with its parity codes (if any).
+    let format_version = layout.meta_header()?.format_version;
+    let with_header = |index: usize, shard: &[u8]| -> Result<Vec<u8>> {
+        let header = header::Header::Payload(header::PayloadHeader {
+            index: index.try_into()?,
+            identifier,
+            identifier_length: layout.payload_identifier_length,
+            format_version,
+        });
+        let mut buf = Vec::<u8>::with_capacity(
+            header::PayloadHeader::length(layout.payload_identifier_length)
+                + layout.data_bytes_per_shard,
+        );
+        header.write_to(&mut buf)?;
+        buf.extend_from_slice(shard);
+        Ok(buf)
+    };
+    let mut shards = Vec::with_capacity(layout.recovery_page_count * layout.codes_per_page());
+    for (page_num, page_shards) in recovery_shards.chunks(layout.shards_per_page).enumerate() {
+        for (position, shard) in page_shards.iter().enumerate() {
+            shards.push(with_header(layout.shard_index(page_num, position), shard)?);
+        }
+        if layout.page_parity_count == 0 {
+            continue;
+        }
+        let mut page_encoder = ReedSolomonEncoder::new(
+            page_shards.len(),
+            layout.page_parity_count,
+            layout.data_bytes_per_shard,
+        )
+        .map_err(|e| {
+            header::shard_parameter_error(
+                e,
+                page_shards.len(),
+                layout.page_parity_count,
+                layout.data_bytes_per_shard,
+            )
+        })?;
+        for shard in page_shards {
+            page_encoder.add_original_shard(shard)?;
+        }
+        for (position, parity) in page_encoder.encode()?.recovery_iter().enumerate() {
+            let index = layout.shard_index(page_num, layout.shards_per_page + position);
+            shards.push(with_header(index, parity)?);
+        }
+    }
+    Ok(shards)
+}
+
+/// Write the metadata and payload shards as separate files into the given directory, for use by
+/// external tools.  The metadata is written last, so that an interrupted write is not mistaken
+/// for a complete shard cache.
+fn dump_shards(layout: &layout::Options, shards: &[Vec<u8>], dump_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dump_dir)
+        .with_context(|| format!("Failed to create {}", dump_dir.display()))?;
+    let meta_path = dump_dir.join("meta.bin");
+    if meta_path.exists() {
+        fs::remove_file(&meta_path)?;
+    }
+    for (index, shard) in shards.iter().enumerate() {
+        fs::write(dump_dir.join(format!("{index:05}.bin")), shard)?;
+    }
+    fs::write(meta_path, meta_bytes(layout)?)?;
+    Ok(())
+}
+
+/// Read the shards cached by `--shard-cache` in an earlier run, if they were made from the same
+/// data with the same layout.  The metadata holds the hash of the data and every parameter that the
+/// shards depend on, so the shards are only reused if it is identical.
+fn read_shard_cache(layout: &layout::Options, dir: &Path) -> Result<Option<Vec<Vec<u8>>>> {
+    match fs::read(dir.join("meta.bin")) {
+        Ok(saved_meta) if saved_meta == meta_bytes(layout)? => {}
+        _ => return Ok(None),
+    }
+    let shard_count =
+        layout.recovery_shard_count + layout.recovery_page_count * layout.page_parity_count;
+    Ok((0..shard_count)
+        .map(|index| fs::read(dir.join(format!("{index:05}.bin"))).ok())
+        .collect())
+}
+
+/// The encoded metadata code of the document.
+fn meta_bytes(layout: &layout::Options) -> Result<Vec<u8>> {
+    let mut meta = Vec::<u8>::with_capacity(header::MetaHeader::LENGTH);
+    header::Header::Meta(layout.meta_header()?).write_to(&mut meta)?;
+    Ok(meta)
+}
+
+/// Generate the SVGs from the given payload shards.
+fn generate_svgs(layout: &layout::Options, shards: &[Vec<u8>]) -> Result<Vec<printpdf::svg::Svg>> {
+    // Encode the shards into QR codes.
+    shards
+        .par_iter()
+        .map(|buf| encode_svg(buf, layout.version, layout.level, layout.module_style))
+        .collect()
+}
+
+/// Encode the given payload shards into QR codes, for drawing onto pages.
+fn generate_codes(layout: &layout::Options, shards: &[Vec<u8>]) -> Result<Vec<QrCode>> {
+    shards
+        .par_iter()
+        .map(|buf| encode_shard(buf, layout.version, layout.level))
+        .collect()
+}
+
+/// Encode one shard into a QR code SVG.  Any failure is returned as an error, rather than
+/// panicking, so that it can be reported from inside the parallel iterator.
+fn encode_svg(
+    buf: &[u8],
+    version: qrcode::Version,
+    level: qrcode::EcLevel,
+    style: ModuleStyle,
+) -> Result<printpdf::svg::Svg> {
+    // We need to convert the QR code into an SVG, and then parse it _back_ into an object.
+    let svg_string = code_svg(&encode_shard(buf, version, level)?, style);
+    Ok(printpdf::svg::Svg::parse(&svg_string)?)
+}
+
+/// The text of an SVG of the QR code, with one unit per module and no quiet zone.
+fn code_svg(code: &QrCode, style: ModuleStyle) -> String {
+    match style {
+        ModuleStyle::Square => code
+            .render::<qrcode::render::svg::Color>()
+            .quiet_zone(false)
+            .module_dimensions(1, 1)
+            .build(),
+        ModuleStyle::Dot => dot_svg(code),
+    }
+}
+
+/// The file a page is written to when each page gets its own file: the output path, without its
+/// extension, followed by the page number (from zero) and the given extension.  Page numbers are
+/// padded with zeros to the width of the last one, so that the files sort in page order.
+fn page_path(out_path: &Path, page_num: usize, page_count: usize, extension: &str) -> PathBuf {
+    let stem = out_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let width = page_count.saturating_sub(1).to_string().len();
+    out_path.with_file_name(format!("{stem}-{page_num:0width$}.{extension}"))
+}
+
+/// The files every page is written to when each page gets its own file, checking that none of
+/// them exist unless `force` is set.  If the output is a directory, the files are named `out-0`
+/// and so on inside it, as they would be for an output file named `out`.
+fn page_paths(
+    out_path: &Path,
+    page_count: usize,
+    extension: &str,
+    force: bool,
+) -> Result<Vec<PathBuf>> {
+    let out_path = if out_path.is_dir() {
+        &out_path.join("out")
+    } else {
+        out_path
+    };
+    let paths = (0..page_count)
+        .map(|page_num| page_path(out_path, page_num, page_count, extension))
+        .collect::<Vec<_>>();
+    if !force {
+        let existing = paths
+            .iter()
+            .filter(|path| path.exists())
+            .collect::<Vec<_>>();
+        match existing.as_slice() {
+            [] => {}
+            [path] => Err(anyhow!(
+                "{} already exists; use --force to overwrite it",
+                path.display()
+            ))?,
+            [path, rest @ ..] => Err(anyhow!(
+                "{} and {} other page files already exist; use --force to overwrite them",
+                path.display(),
+                rest.len()
+            ))?,
+        }
+    }
+    Ok(paths)
+}
+
+/// Encode one shard into a QR code of the given version and level.
+fn encode_shard(buf: &[u8], version: qrcode::Version, level: qrcode::EcLevel) -> Result<QrCode> {
+    let encode_error = |e: qrcode::types::QrError| {
+        anyhow!(
+            "failed to encode {} bytes of data into {:?}{:?}: {e}",
+            buf.len(),
+            version,
+            level
+        )
+    };
+    // We need to force byte mode to avoid issues where sometimes the "optimal" segmentation
+    // algorithm ends up taking more space.
+    let mut bits = qrcode::bits::Bits::new(version);
+    bits.push_byte_data(buf).map_err(encode_error)?;
+    bits.push_terminator(level).map_err(encode_error)?;
+    QrCode::with_bits(bits, level).map_err(encode_error)
+}
+
+/// `Matrices` is written by `--emit-matrices`, so that other tools can lay out the codes
+/// themselves.  Each row of a matrix is a string of `1` (dark) and `0` (light) modules, from the
+/// top; the quiet zone around the code is not included.
+#[derive(Serialize)]
+struct Matrices {
+    /// The QR code version of the payload codes; the metadata code may differ.
+    qr_version: i16,
+    error_correction: String,
+    /// The number of payload codes on each page, in order, when laid out as paperback does.
+    codes_per_page: usize,
+    meta: Vec<String>,
+    codes: Vec<CodeMatrix>,
+}
+
+#[derive(Serialize)]
+struct CodeMatrix {
+    /// The shard index stored in the code.
+    index: usize,
+    rows: Vec<String>,
+}
+
+/// Write the module matrices of the metadata and payload codes as JSON to the given file.
+fn emit_matrices(layout: &layout::Options, shards: &[Vec<u8>], path: &Path) -> Result<()> {
+    let rows = |code: &QrCode| -> Vec<String> {
+        code.to_colors()
+            .chunks(code.width())
+            .map(|row| {
+                row.iter()
+                    .map(|&color| {
+                        if color == qrcode::Color::Dark {
+                            '1'
+                        } else {
+                            '0'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    let meta = QrCode::with_error_correction_level(meta_bytes(layout)?, qrcode::EcLevel::H)?;
+    let codes = shards
         .par_iter()
         .enumerate()
-        .map(|(i, shard)| {
-            let header = header::Header::Payload(header::PayloadHeader {
-                index: i.try_into()?,
-                identifier,
-            });
-            let mut buf = Vec::<u8>::with_capacity(
-                header::PayloadHeader::LENGTH + layout.data_bytes_per_shard,
-            );
-            header.write_to(&mut buf)?;
-            buf.extend_from_slice(shard);
-
-            // We need to convert the QR code into an SVG, and then parse it _back_ into an
-            // object.  Also, we need to force byte mode to avoid issues where sometimes the
-            // "optimal" segmentation algorithm ends up taking more space.
-            let mut bits = qrcode::bits::Bits::new(layout.version);
-            bits.push_byte_data(&buf)?;
-            bits.push_terminator(layout.level)?;
-            let svg_string = QrCode::with_bits(bits, layout.level)
-                .map_err(|e| {
-                    anyhow!(
-                        "failed to encode {} bytes of data into {:?}{:?}: {e}",
-                        &buf.len(),
-                        layout.version,
-                        layout.level
-                    )
-                })?
-                .render::<qrcode::render::svg::Color>()
-                .quiet_zone(false)
-                .module_dimensions(1, 1)
-                .build();
-            Ok(printpdf::svg::Svg::parse(&svg_string)?)
+        .map(|(position, shard)| {
+            let (page_num, position) = position.div_rem(&layout.codes_per_page());
+            Ok(CodeMatrix {
+                index: layout.shard_index(page_num, position),
+                rows: rows(&encode_shard(shard, layout.version, layout.level)?),
+            })
         })
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    Ok(svgs)
+        .collect::<Result<Vec<_>>>()?;
+    let matrices = Matrices {
+        qr_version: match layout.version {
+            qrcode::Version::Normal(v) | qrcode::Version::Micro(v) => v,
+        },
+        error_correction: format!("{:?}", layout.level),
+        codes_per_page: layout.codes_per_page(),
+        meta: rows(&meta),
+        codes,
+    };
+    let file =
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &matrices)?;
+    Ok(())
+}
+
+/// The radius of a module drawn as a dot, relative to the module length.  This leaves a small gap
+/// between neighbouring dots, without making them so small that they are missed.
+const DOT_RADIUS: f32 = 0.45;
+
+/// Whether the module at the given position is part of one of the three finder patterns in the
+/// corners; these are always drawn as squares, as readers locate the code by them.
+fn is_finder_module(x: usize, y: usize, width: usize) -> bool {
+    const FINDER_WIDTH: usize = 7;
+    (x < FINDER_WIDTH || x >= width - FINDER_WIDTH) && y < FINDER_WIDTH
+        || x < FINDER_WIDTH && y >= width - FINDER_WIDTH
+}
+
+/// Render the QR code as an SVG with dots for modules (other than the finder patterns), in the
+/// same form as the `qrcode` renderer produces.
+fn dot_svg(code: &QrCode) -> String {
+    let width = code.width();
+    let mut path = String::new();
+    for (index, color) in code.to_colors().iter().enumerate() {
+        if *color != qrcode::Color::Dark {
+            continue;
+        }
+        let (y, x) = index.div_rem(&width);
+        if is_finder_module(x, y, width) {
+            _ = write!(path, "M{x} {y}h1v1H{x}V{y}");
+        } else {
+            let r = DOT_RADIUS;
+            _ = write!(
+                path,
+                "M{} {}a{r} {r} 0 1 0 {} 0a{r} {r} 0 1 0 {} 0",
+                x as f32 + 0.5 - r,
+                y as f32 + 0.5,
+                r * 2.,
+                -r * 2.
+            );
+        }
+    }
+    format!(
+        concat!(
+            r#"<?xml version="1.0" standalone="yes"?>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg""#,
+            r#" version="1.1" width="{w}" height="{w}" viewBox="0 0 {w} {w}">"#,
+            r##"<rect x="0" y="0" width="{w}" height="{w}" fill="#fff"/>"##,
+            r##"<path fill="#000" d="{path}"/></svg>"##,
+        ),
+        w = width,
+        path = path
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        create, create_from_data, document_shards, encode_svg, is_finder_module, layout,
+        looks_incompressible, pad_data, page_path, page_paths, write_with_retry, DOT_RADIUS,
+    };
+    use crate::{
+        args::{Commands, ModuleStyle, TopLevelArgs},
+        header::{self, Encryption},
+        restore::scan_image,
+    };
+    use anyhow::Result;
+    use assert_fs::{prelude::*, TempDir};
+    use clap::Parser;
+    use image::{DynamicImage, GrayImage, Luma};
+    use qrcode::{EcLevel, QrCode, Version};
+    use std::{
+        fs, io,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_encode_oversized_shard() {
+        let Err(error) = encode_svg(
+            &[0u8; 64],
+            Version::Normal(1),
+            EcLevel::H,
+            ModuleStyle::Square,
+        ) else {
+            panic!("encoding should fail");
+        };
+        let message = error.to_string();
+        assert!(
+            message.starts_with("failed to encode 64 bytes of data into Normal(1)H"),
+            "unexpected error: {message}"
+        );
+    }
+
+    #[test]
+    fn test_encode_shard() {
+        assert!(encode_svg(
+            &[0u8; 64],
+            Version::Normal(10),
+            EcLevel::H,
+            ModuleStyle::Square
+        )
+        .is_ok());
+        assert!(encode_svg(
+            &[0u8; 64],
+            Version::Normal(10),
+            EcLevel::H,
+            ModuleStyle::Dot
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_dot_modules_scan() -> Result<()> {
+        // Draw a code with dots the same way as `dot_svg`, and check that it can still be read.
+        const SCALE: usize = 8;
+        const QUIET_ZONE: usize = 4;
+        let data = b"paperback dot module test ".repeat(8);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::Q)?;
+        let width = code.width();
+        let colors = code.to_colors();
+        let size = ((width + QUIET_ZONE * 2) * SCALE) as u32;
+        let image = GrayImage::from_fn(size, size, |px, py| {
+            let (x, y) = (px as f32 / SCALE as f32, py as f32 / SCALE as f32);
+            let (x, y) = (x - QUIET_ZONE as f32, y - QUIET_ZONE as f32);
+            if x < 0. || y < 0. || x >= width as f32 || y >= width as f32 {
+                return Luma([255]);
+            }
+            let (module_x, module_y) = (x as usize, y as usize);
+            let dark = colors[module_y * width + module_x] == qrcode::Color::Dark
+                && (is_finder_module(module_x, module_y, width)
+                    || (x.fract() - 0.5).hypot(y.fract() - 0.5) <= DOT_RADIUS);
+            Luma([if dark { 0 } else { 255 }])
+        });
+        assert_eq!(
+            scan_image(DynamicImage::ImageLuma8(image), true)?,
+            vec![data]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_path() {
+        let out_path = Path::new("backup/out.pdf");
+        assert_eq!(
+            page_path(out_path, 3, 5, "svg"),
+            Path::new("backup/out-3.svg")
+        );
+        // Padded to the width of the last page number, so that the files sort in order.
+        assert_eq!(
+            page_path(out_path, 3, 10, "svg"),
+            Path::new("backup/out-3.svg")
+        );
+        assert_eq!(
+            page_path(out_path, 3, 11, "svg"),
+            Path::new("backup/out-03.svg")
+        );
+        assert_eq!(
+            page_path(out_path, 42, 1000, "svg"),
+            Path::new("backup/out-042.svg")
+        );
+    }
+
+    #[test]
+    fn test_pad_data() -> Result<()> {
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from(["paperback", "create", "input.txt", "output.pdf"])?
+        else {
+            panic!("expected the create command");
+        };
+        let data = vec![1u8; 1000];
+        let mut layout = layout::compute(&args, data.len(), [0; 4], [0; 64], None)?;
+        let mut padded = data.clone();
+        pad_data(&layout, &mut padded, 1000);
+        let padding = &padded[1000..padded.len() - 8];
+        assert!(padding.len() >= 16, "the test needs enough padding");
+        assert!(padding.iter().all(|&byte| byte == 0));
+        assert_eq!(padded[padded.len() - 8..], 1000u64.to_le_bytes());
+
+        layout.encryption = Some(Encryption {
+            salt: [0; 16],
+            nonce: [0; 24],
+            key_check: [0; 8],
+        });
+        let mut padded = data.clone();
+        pad_data(&layout, &mut padded, 1000);
+        assert_eq!(padded[..1000], data);
+        assert!(padded[1000..padded.len() - 8].iter().any(|&byte| byte != 0));
+        assert_eq!(padded[padded.len() - 8..], 1000u64.to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_looks_incompressible() -> Result<()> {
+        let text = fs::read("LICENSE")?;
+        assert!(!looks_incompressible(&text));
+        let compressed = header::Compression::Zstd.compress(text, None)?;
+        assert!(looks_incompressible(&compressed));
+        assert!(!looks_incompressible(&compressed[..512]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_with_retry() -> Result<()> {
+        let work_dir = TempDir::new()?;
+        let path = work_dir.child("out.pdf");
+        write_with_retry(path.path(), b"pdf")?;
+        path.assert("pdf");
+        // A missing directory is not transient, so it fails without waiting to retry.
+        let start = Instant::now();
+        let error = write_with_retry(&work_dir.path().join("missing/out.pdf"), b"pdf").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_paths_in_directory() -> Result<()> {
+        let work_dir = TempDir::new()?;
+        assert_eq!(
+            page_paths(work_dir.path(), 2, "png", false)?,
+            [
+                work_dir.path().join("out-0.png"),
+                work_dir.path().join("out-1.png")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_report() -> Result<()> {
+        let work_dir = TempDir::new()?;
+        let input = work_dir.child("input.txt");
+        input.write_binary(&b"paperback report test ".repeat(200))?;
+        let out_path = work_dir.child("out.pdf");
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from([
+            "paperback".as_ref(),
+            "create".as_ref(),
+            input.path().as_os_str(),
+            out_path.path().as_os_str(),
+        ])?
+        else {
+            panic!("expected the create command");
+        };
+        let report = create(&args)?.expect("create should report what it wrote");
+        assert_eq!(report.out_path.as_deref(), Some(out_path.path()));
+        assert!(report.data_page_count <= report.page_count);
+        assert!(report.data_shard_count < report.recovery_shard_count);
+        assert_eq!(report.error_correction, format!("{:?}", report.level));
+        assert_eq!(report.identifier.len(), 8);
+        assert!(report
+            .to_string()
+            .starts_with(&format!("Wrote {} pages to ", report.page_count)));
+        out_path.assert(predicates::path::exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_shards_bundle() -> Result<()> {
+        let work_dir = TempDir::new()?;
+        let dump_dir = work_dir.child("shards");
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from([
+            "paperback".as_ref(),
+            "create".as_ref(),
+            "key.txt".as_ref(),
+            "--dump-shards".as_ref(),
+            dump_dir.path().as_os_str(),
+        ])?
+        else {
+            panic!("expected the create command");
+        };
+        let data = header::pack_bundle(&[
+            ("key.txt".to_string(), b"secret".to_vec()),
+            (
+                "notes.txt".to_string(),
+                b"paperback bundle test ".repeat(50),
+            ),
+        ])?;
+        create_from_data(&args, data.clone(), true, None)?;
+        // The shards that verify expects must be the ones that create wrote.
+        let (layout, shards) = document_shards(&args, data, true)?;
+        assert!(layout.bundle);
+        for (index, shard) in shards.iter().enumerate() {
+            assert_eq!(&fs::read(dump_dir.join(format!("{index:05}.bin")))?, shard);
+        }
+        Ok(())
+    }
 }
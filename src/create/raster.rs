@@ -0,0 +1,467 @@
+use super::{
+    canvas::{self, Canvas},
+    is_finder_module, layout, DOT_RADIUS,
+};
+use crate::{
+    args::{ModuleStyle, PngBackground, PngDepth},
+    fonts::metrics::{Alignment, FontMetrics},
+};
+use ab_glyph::{point, Font, FontRef, Glyph, ScaleFont};
+use anyhow::{anyhow, Result};
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+use num_integer::Integer;
+use printpdf::{BuiltinFont, Color, Mm, Pt};
+use qrcode::QrCode;
+
+const MM_PER_INCH: f32 = 25.4;
+const PT_PER_INCH: f32 = 72.;
+
+/// `RasterPage` draws a page into an image at a given resolution.  The builtin PDF fonts are not
+/// available as outlines, so text is drawn in similar bundled fonts, stretched to the width the PDF
+/// fonts would give, so that it fits where the layout expects.  With a transparent background, only
+/// what is drawn is opaque.
+struct RasterPage {
+    image: RgbaImage,
+    /// The number of pixels per millimetre.
+    scale: f32,
+    height: Mm,
+}
+
+impl RasterPage {
+    fn new(width: Mm, height: Mm, dpi: u32, background: PngBackground) -> Self {
+        let scale = dpi as f32 / MM_PER_INCH;
+        let alpha = match background {
+            PngBackground::White => 255,
+            PngBackground::Transparent => 0,
+        };
+        RasterPage {
+            image: RgbaImage::from_pixel(
+                (width.0 * scale).round() as u32,
+                (height.0 * scale).round() as u32,
+                Rgba([255, 255, 255, alpha]),
+            ),
+            scale,
+            height,
+        }
+    }
+
+    /// The pixel column of a horizontal position.
+    fn x(&self, x: Mm) -> f32 {
+        x.0 * self.scale
+    }
+
+    /// The pixel row of a vertical position; rows count down from the top of the page.
+    fn y(&self, y: Mm) -> f32 {
+        (self.height - y).0 * self.scale
+    }
+
+    /// Fill the pixels whose centres are inside the given rectangle, measured in pixels.
+    fn fill(&mut self, left: f32, top: f32, right: f32, bottom: f32, color: Rgb<u8>) {
+        let (width, height) = self.image.dimensions();
+        let columns = left.round().max(0.) as u32..(right.round().max(0.) as u32).min(width);
+        let rows = top.round().max(0.) as u32..(bottom.round().max(0.) as u32).min(height);
+        for y in rows {
+            for x in columns.clone() {
+                self.image.put_pixel(x, y, opaque(color));
+            }
+        }
+    }
+
+    /// Fill the pixels whose centres are inside the given circle, measured in pixels.
+    fn fill_circle(&mut self, centre_x: f32, centre_y: f32, radius: f32, color: Rgb<u8>) {
+        let (width, height) = self.image.dimensions();
+        let rows = (centre_y - radius).floor().max(0.) as u32
+            ..((centre_y + radius).ceil().max(0.) as u32).min(height);
+        for y in rows {
+            let columns = (centre_x - radius).floor().max(0.) as u32
+                ..((centre_x + radius).ceil().max(0.) as u32).min(width);
+            for x in columns {
+                let (dx, dy) = (x as f32 + 0.5 - centre_x, y as f32 + 0.5 - centre_y);
+                if dx * dx + dy * dy <= radius * radius {
+                    self.image.put_pixel(x, y, opaque(color));
+                }
+            }
+        }
+    }
+
+    /// Mix the colour into a pixel in proportion to `coverage`, which is between 0 and 1, as if it
+    /// were painted over it.
+    fn blend(&mut self, x: i32, y: i32, color: Rgb<u8>, coverage: f32) {
+        let (Ok(x), Ok(y)) = (u32::try_from(x), u32::try_from(y)) else {
+            return;
+        };
+        let Some(pixel) = self.image.get_pixel_mut_checked(x, y) else {
+            return;
+        };
+        let coverage = coverage.clamp(0., 1.);
+        let below = pixel.0[3] as f32 / 255. * (1. - coverage);
+        let alpha = below + coverage;
+        if alpha <= 0. {
+            return;
+        }
+        for (channel, target) in pixel.0.iter_mut().zip(color.0) {
+            *channel = ((*channel as f32 * below + target as f32 * coverage) / alpha).round() as u8;
+        }
+        pixel.0[3] = (alpha * 255.).round() as u8;
+    }
+}
+
+/// The colour as a fully opaque pixel.
+fn opaque(Rgb([red, green, blue]): Rgb<u8>) -> Rgba<u8> {
+    Rgba([red, green, blue, 255])
+}
+
+impl Canvas for RasterPage {
+    fn rect(&mut self, left: Mm, bottom: Mm, right: Mm, top: Mm, color: &Color) {
+        let color = Rgb(canvas::rgb(color));
+        self.fill(
+            self.x(left),
+            self.y(top),
+            self.x(right),
+            self.y(bottom),
+            color,
+        );
+    }
+
+    fn text(
+        &mut self,
+        font: BuiltinFont,
+        size: Pt,
+        text: &str,
+        x: Mm,
+        y: Mm,
+        alignment: &Alignment,
+        color: &Color,
+    ) {
+        let face = FontRef::try_from_slice(match font {
+            BuiltinFont::Courier
+            | BuiltinFont::CourierBold
+            | BuiltinFont::CourierOblique
+            | BuiltinFont::CourierBoldOblique => epaint_default_fonts::HACK_REGULAR,
+            _ => epaint_default_fonts::UBUNTU_LIGHT,
+        })
+        .expect("the bundled fonts are valid");
+        let metrics: &FontMetrics = font.into();
+        let em = size.0 * self.scale * MM_PER_INCH / PT_PER_INCH;
+        let Some(mut scale) = face.pt_to_px_scale(em) else {
+            return;
+        };
+        let natural_width = line_width(&face.as_scaled(scale), text);
+        if natural_width > 0. {
+            scale.x *= metrics.measure(text) * em / natural_width;
+        }
+        let scaled = face.as_scaled(scale);
+        let width = line_width(&scaled, text);
+        let left = self.x(x)
+            - match alignment {
+                Alignment::Left => 0.,
+                Alignment::Right => width,
+                Alignment::Center => width / 2.,
+            };
+        let baseline = self.y(y);
+        let color = Rgb(canvas::rgb(color));
+        // Bold text is drawn more than once, a little further right each time.
+        let strikes = match font {
+            BuiltinFont::HelveticaBold | BuiltinFont::CourierBold => (em / 25.).round().max(1.),
+            _ => 0.,
+        } as usize;
+        for strike in 0..=strikes {
+            for glyph in layout_line(&scaled, text, left + strike as f32, baseline) {
+                let Some(outline) = scaled.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outline.px_bounds();
+                outline.draw(|glyph_x, glyph_y, coverage| {
+                    self.blend(
+                        bounds.min.x as i32 + glyph_x as i32,
+                        bounds.min.y as i32 + glyph_y as i32,
+                        color,
+                        coverage,
+                    )
+                });
+            }
+        }
+    }
+
+    fn qr_code(&mut self, code: &QrCode, style: ModuleStyle, x: Mm, y: Mm, size: Mm) -> Result<()> {
+        let width = code.width();
+        let (left, top) = (self.x(x), self.y(y + size));
+        let module = size.0 * self.scale / width as f32;
+        if module < 1. {
+            Err(anyhow!(
+                "modules of {:.2}mm are smaller than a pixel; use a higher --dpi",
+                size.0 / width as f32
+            ))?;
+        }
+        let (black, white) = (Rgb([0; 3]), Rgb([255; 3]));
+        self.fill(
+            left,
+            top,
+            left + module * width as f32,
+            top + module * width as f32,
+            white,
+        );
+        for (index, color) in code.to_colors().iter().enumerate() {
+            if *color != qrcode::Color::Dark {
+                continue;
+            }
+            let (row, column) = index.div_rem(&width);
+            let (module_left, module_top) =
+                (left + module * column as f32, top + module * row as f32);
+            if style == ModuleStyle::Dot && !is_finder_module(column, row, width) {
+                self.fill_circle(
+                    module_left + module / 2.,
+                    module_top + module / 2.,
+                    module * DOT_RADIUS,
+                    black,
+                );
+            } else {
+                self.fill(
+                    module_left,
+                    module_top,
+                    module_left + module,
+                    module_top + module,
+                    black,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lay out a line of text with its baseline starting at the given point, in pixels.
+fn layout_line<F: Font>(font: &impl ScaleFont<F>, text: &str, x: f32, y: f32) -> Vec<Glyph> {
+    let mut caret = x;
+    let mut previous = None;
+    text.chars()
+        .map(|c| {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            previous = Some(id);
+            let glyph = id.with_scale_and_position(font.scale(), point(caret, y));
+            caret += font.h_advance(id);
+            glyph
+        })
+        .collect()
+}
+
+/// The width of a line of text, in pixels.
+fn line_width<F: Font>(font: &impl ScaleFont<F>, text: &str) -> f32 {
+    layout_line(font, text, 0., 0.)
+        .last()
+        .map_or(0., |glyph| glyph.position.x + font.h_advance(glyph.id))
+}
+
+/// Encode an image as a PNG, recording its resolution so that it prints at its intended size.
+pub(crate) fn encode_png(image: &RgbImage, dpi: u32) -> Result<Vec<u8>> {
+    write_png(
+        image.width(),
+        image.height(),
+        png::ColorType::Rgb,
+        png::BitDepth::Eight,
+        image.as_raw(),
+        dpi,
+    )
+}
+
+/// Encode a page as a greyscale PNG of the given depth, with an alpha channel if its background
+/// is transparent.  At a depth of 1, pixels at least half white are white.
+fn encode_page_png(
+    image: &RgbaImage,
+    dpi: u32,
+    background: PngBackground,
+    depth: PngDepth,
+) -> Result<Vec<u8>> {
+    let luma = |Rgba([red, green, blue, _]): &Rgba<u8>| {
+        ((*red as u32 * 299 + *green as u32 * 587 + *blue as u32 * 114) / 1000) as u8
+    };
+    let (width, height) = image.dimensions();
+    match (background, depth) {
+        (PngBackground::White, PngDepth::Eight) => {
+            let data = image.pixels().map(luma).collect::<Vec<_>>();
+            write_png(
+                width,
+                height,
+                png::ColorType::Grayscale,
+                png::BitDepth::Eight,
+                &data,
+                dpi,
+            )
+        }
+        (PngBackground::Transparent, PngDepth::Eight) => {
+            let data = image
+                .pixels()
+                .flat_map(|pixel| [luma(pixel), pixel.0[3]])
+                .collect::<Vec<_>>();
+            write_png(
+                width,
+                height,
+                png::ColorType::GrayscaleAlpha,
+                png::BitDepth::Eight,
+                &data,
+                dpi,
+            )
+        }
+        (PngBackground::White, PngDepth::One) => {
+            // Each row starts on a new byte, with the leftmost pixel in the highest bit.
+            let data = image
+                .rows()
+                .flat_map(|row| {
+                    row.collect::<Vec<_>>()
+                        .chunks(8)
+                        .map(|pixels| {
+                            pixels.iter().enumerate().fold(0u8, |byte, (bit, pixel)| {
+                                byte | (u8::from(luma(pixel) >= 128) << (7 - bit))
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            write_png(
+                width,
+                height,
+                png::ColorType::Grayscale,
+                png::BitDepth::One,
+                &data,
+                dpi,
+            )
+        }
+        (PngBackground::Transparent, PngDepth::One) => {
+            Err(anyhow!("--png-background transparent needs --png-depth 8"))
+        }
+    }
+}
+
+/// Write the PNG, recording its resolution.
+fn write_png(
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    depth: png::BitDepth,
+    data: &[u8],
+    dpi: u32,
+) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let pixels_per_metre = (dpi as f32 * 1000. / MM_PER_INCH).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_metre,
+        yppu: pixels_per_metre,
+        unit: png::Unit::Meter,
+    }));
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(bytes)
+}
+
+/// The options for the PNG images of pages.
+pub(super) struct PngOptions {
+    pub dpi: u32,
+    pub background: PngBackground,
+    pub depth: PngDepth,
+}
+
+/// Render a page as a PNG image, laid out as [`canvas::draw_page`] lays out SVG pages.  `codes`
+/// are the payload codes on the page.
+pub(super) fn render_page(
+    layout: &layout::Options,
+    codes: &[QrCode],
+    page_num: usize,
+    page_checksum: Option<&str>,
+    commit: &str,
+    options: &PngOptions,
+) -> Result<Vec<u8>> {
+    let mut page = RasterPage::new(
+        layout.page_width,
+        layout.page_height,
+        options.dpi,
+        options.background,
+    );
+    canvas::draw_page(&mut page, layout, codes, page_num, page_checksum, commit)?;
+    encode_page_png(&page.image, options.dpi, options.background, options.depth)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_page_png, Canvas, RasterPage};
+    use crate::{
+        args::{ModuleStyle, PngBackground, PngDepth},
+        fonts::metrics::Alignment,
+        restore::scan_image,
+    };
+    use anyhow::Result;
+    use image::{ColorType, DynamicImage, Rgba};
+    use printpdf::{BuiltinFont, Color, Greyscale, Mm, Pt};
+    use qrcode::{EcLevel, QrCode};
+
+    #[test]
+    fn test_raster_codes_scan() -> Result<()> {
+        let data = b"paperback raster test ".repeat(8);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::Q)?;
+        let black = Color::Greyscale(Greyscale::new(0., None));
+        for style in [ModuleStyle::Square, ModuleStyle::Dot] {
+            let mut page = RasterPage::new(Mm(80.), Mm(80.), 300, PngBackground::White);
+            page.qr_code(&code, style, Mm(10.), Mm(20.), Mm(60.))?;
+            // Text beside the code does not get in the way of reading it.
+            page.text(
+                BuiltinFont::HelveticaBold,
+                Pt(14.),
+                "Document ID",
+                Mm(40.),
+                Mm(10.),
+                &Alignment::Center,
+                &black,
+            );
+            // The code's bottom left corner is 10mm from the left and 20mm from the bottom.
+            let pixel = |x: f32, y: f32| {
+                *page
+                    .image
+                    .get_pixel((x * page.scale) as u32, (y * page.scale) as u32)
+            };
+            assert_eq!(pixel(10.2, 59.8), Rgba([0, 0, 0, 255]));
+            assert_eq!(pixel(9.8, 59.8), Rgba([255; 4]));
+            assert_eq!(pixel(10.2, 60.2), Rgba([255; 4]));
+            assert_eq!(
+                scan_image(DynamicImage::ImageRgba8(page.image), true)?,
+                vec![data.clone()]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_png_scans() -> Result<()> {
+        let data = b"paperback png test ".repeat(8);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::Q)?;
+        for (background, depth, color_type) in [
+            (PngBackground::White, PngDepth::Eight, ColorType::L8),
+            (PngBackground::White, PngDepth::One, ColorType::L8),
+            (PngBackground::Transparent, PngDepth::Eight, ColorType::La8),
+        ] {
+            let mut page = RasterPage::new(Mm(80.), Mm(80.), 300, background);
+            page.qr_code(&code, ModuleStyle::Square, Mm(10.), Mm(10.), Mm(60.))?;
+            let image =
+                image::load_from_memory(&encode_page_png(&page.image, 300, background, depth)?)?;
+            assert_eq!(image.color(), color_type);
+            if background == PngBackground::Transparent {
+                // Only the code is opaque.
+                assert_eq!(image.to_luma_alpha8().get_pixel(0, 0).0, [255, 0]);
+                assert_eq!(image.to_luma_alpha8().get_pixel(400, 400).0[1], 255);
+            }
+            assert_eq!(scan_image(image, true)?, vec![data.clone()]);
+        }
+        assert!(encode_page_png(
+            &RasterPage::new(Mm(1.), Mm(1.), 300, PngBackground::Transparent).image,
+            300,
+            PngBackground::Transparent,
+            PngDepth::One
+        )
+        .is_err());
+        Ok(())
+    }
+}
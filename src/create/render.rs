@@ -1,10 +1,11 @@
 use super::layout;
 use crate::{
+    args::PageDimensions,
     fonts::metrics::{self, Alignment, SizedFont},
-    header::{Header, MetaHeader},
+    header::{Encryption, Header, MetaHeader, DECODER_SPEC},
 };
 use anyhow::{anyhow, Result};
-use base58::ToBase58;
+use num_integer::Integer;
 use printpdf::{BuiltinFont, Mm, PdfDocumentReference, PdfLayerReference, Pt, Svg};
 use qrcode::QrCode;
 
@@ -12,98 +13,886 @@ const DOTS_PER_INCH: f32 = 300.0;
 const MM_PER_INCH: f32 = 25.4;
 
 pub(crate) struct Bounds {
-    top: Mm,
-    right: Mm,
-    bottom: Mm,
-    left: Mm,
+    pub(super) top: Mm,
+    pub(super) right: Mm,
+    pub(super) bottom: Mm,
+    pub(super) left: Mm,
 }
 
 impl Bounds {
     pub(crate) fn width(&self) -> Mm {
         self.right - self.left
     }
-    pub(crate) fn height(&self) -> Mm {
-        self.top - self.bottom
-    }
 }
 
-/// Render a page
+/// Render a page; `page_checksum`, if given, is printed in the banner.
 pub fn render_page(
     layout: &layout::Options,
     codes: &mut impl Iterator<Item = Svg>,
     page_num: usize,
+    page_checksum: Option<&str>,
     doc: &PdfDocumentReference,
     layer: &PdfLayerReference,
     commit: &str,
 ) -> Result<()> {
-    let is_odd = (page_num % 2) == 0;
+    let (vertical_offset, banner_bounds) = page_areas(layout, page_num);
+    render_codes(vertical_offset, layout, codes, page_num, doc, layer)?;
+    if layout.big_page_numbers {
+        render_big_page_number(vertical_offset, layout, page_num, doc, layer)?;
+    }
+    render_banner(
+        &banner_bounds,
+        layout,
+        page_num,
+        page_checksum,
+        doc,
+        layer,
+        commit,
+    )?;
+
+    Ok(())
+}
+
+/// Work out where the parts of a page go: the vertical offset of the codes, and the bounds of the
+/// banner.  The banner alternates between the top and bottom of the page.
+pub(super) fn page_areas(layout: &layout::Options, page_num: usize) -> (Mm, Bounds) {
+    // Template positions are fixed, so the banner cannot alternate between the top and bottom.
+    let is_odd = page_num.is_multiple_of(2) || layout.code_positions.is_some();
     let vertical_offset = if is_odd {
         Mm(0.0)
     } else {
-        layout.avail_height - layout.avail_width
+        layout.avail_height - layout.code_area_height
     };
-    render_codes(vertical_offset, layout, codes, layer)?;
-
     let banner_bounds = Bounds {
         // For even pages, the top is smaller by margin-bottom for gutter.
         top: if is_odd {
             layout.avail_height
         } else {
-            layout.avail_height - layout.avail_width
+            layout.avail_height - layout.code_area_height - layout.banner_gap
         },
         right: layout.margin_left + layout.avail_width,
         bottom: if is_odd {
-            layout.avail_width + layout.margin_bottom
+            layout.code_area_height + layout.margin_bottom + layout.banner_gap
         } else {
             layout.margin_bottom
         },
         left: layout.margin_left,
     };
-    render_banner(&banner_bounds, layout, page_num, doc, layer, commit)?;
+    (vertical_offset, banner_bounds)
+}
+
+/// The height of the band at the top of each draft sheet, which warns that it is not for restoring.
+const DRAFT_BAND_HEIGHT: Mm = Mm(12.);
+
+/// The size of each physical sheet when `pages_per_sheet` pages are printed on it: two pages go
+/// side by side on a landscape sheet, and four in a grid on a sheet the same way up as the pages.
+pub fn sheet_size(page_width: Mm, page_height: Mm, pages_per_sheet: usize) -> (Mm, Mm) {
+    match pages_per_sheet {
+        2 => (page_height, page_width),
+        _ => (page_width, page_height),
+    }
+}
+
+/// `Sheets` hands out the layer to draw each page of the document on.  With more than one page per
+/// sheet, the pages are scaled down into a grid, and each sheet is marked as a draft.
+pub struct Sheets<'a> {
+    doc: &'a PdfDocumentReference,
+    page_width: Mm,
+    page_height: Mm,
+    pages_per_sheet: usize,
+    page_index: printpdf::PdfPageIndex,
+    layer_index: printpdf::PdfLayerIndex,
+    pages_drawn: usize,
+    sheet_count: usize,
+}
+
+impl<'a> Sheets<'a> {
+    /// Start handing out pages, beginning with the (blank) page the document was created with.
+    pub fn new(
+        doc: &'a PdfDocumentReference,
+        page_index: printpdf::PdfPageIndex,
+        layer_index: printpdf::PdfLayerIndex,
+        layout: &layout::Options,
+        pages_per_sheet: usize,
+    ) -> Self {
+        Sheets {
+            doc,
+            page_width: layout.page_width,
+            page_height: layout.page_height,
+            pages_per_sheet,
+            page_index,
+            layer_index,
+            pages_drawn: 0,
+            sheet_count: 1,
+        }
+    }
+
+    fn layer(&self) -> PdfLayerReference {
+        self.doc
+            .get_page(self.page_index)
+            .get_layer(self.layer_index)
+    }
+
+    /// The layer to draw the next page on, set up so that the page can be drawn at full size.
+    pub fn next_page(&mut self) -> Result<PdfLayerReference> {
+        let slot = self.pages_drawn % self.pages_per_sheet;
+        if self.pages_per_sheet > 1 && self.pages_drawn > 0 {
+            // Undo the scaling for the previous page.
+            self.layer().restore_graphics_state();
+        }
+        if slot == 0 && self.pages_drawn > 0 {
+            let (width, height) = self.sheet_size();
+            (self.page_index, self.layer_index) = self.doc.add_page(width, height, "");
+            self.sheet_count += 1;
+        }
+        self.pages_drawn += 1;
+        let layer = self.layer();
+        if self.pages_per_sheet == 1 {
+            return Ok(layer);
+        }
+        if slot == 0 {
+            self.render_draft_band(&layer)?;
+        }
+
+        let (sheet_width, sheet_height) = self.sheet_size();
+        let (columns, rows) = match self.pages_per_sheet {
+            2 => (2, 1),
+            _ => (2, 2),
+        };
+        let scale = f32::min(
+            sheet_width.0 / (self.page_width.0 * columns as f32),
+            (sheet_height - DRAFT_BAND_HEIGHT).0 / (self.page_height.0 * rows as f32),
+        );
+        let (tile_width, tile_height) = (self.page_width * scale, self.page_height * scale);
+        let left = (sheet_width - tile_width * columns as f32) / 2.;
+        let bottom = (sheet_height - DRAFT_BAND_HEIGHT - tile_height * rows as f32) / 2.;
+        let (row, column) = slot.div_rem(&columns);
+        let x = left + tile_width * column as f32;
+        // Fill the rows from the top of the sheet.
+        let y = bottom + tile_height * (rows - 1 - row) as f32;
+
+        layer.save_graphics_state();
+        layer.set_ctm(printpdf::CurTransMat::Raw([
+            scale,
+            0.,
+            0.,
+            scale,
+            x.into_pt().0,
+            y.into_pt().0,
+        ]));
+        // Outline the page, so that neighbouring pages can be told apart.
+        layer.set_outline_thickness(0.5);
+        layer.add_rect(printpdf::Rect {
+            ll: printpdf::Point {
+                x: Pt(0.),
+                y: Pt(0.),
+            },
+            ur: printpdf::Point {
+                x: self.page_width.into_pt(),
+                y: self.page_height.into_pt(),
+            },
+            mode: printpdf::path::PaintMode::Stroke,
+            winding: printpdf::path::WindingOrder::EvenOdd,
+        });
+        Ok(layer)
+    }
+
+    /// Finish the last page, returning the number of sheets used.
+    pub fn finish(self) -> usize {
+        if self.pages_per_sheet > 1 && self.pages_drawn > 0 {
+            self.layer().restore_graphics_state();
+        }
+        self.sheet_count
+    }
+
+    fn sheet_size(&self) -> (Mm, Mm) {
+        sheet_size(self.page_width, self.page_height, self.pages_per_sheet)
+    }
+
+    /// Mark the top of a sheet as a draft, since the scaled down codes may not scan.
+    fn render_draft_band(&self, layer: &PdfLayerReference) -> Result<()> {
+        let font = SizedFont::new(self.doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
+        let (sheet_width, sheet_height) = self.sheet_size();
+        font.write(
+            layer,
+            format!(
+                "DRAFT - NOT FOR RESTORE  ({} pages per sheet, scaled down)",
+                self.pages_per_sheet
+            ),
+            sheet_width / 2.,
+            sheet_height - (DRAFT_BAND_HEIGHT + font.size.into()) / 2.,
+            &Alignment::Center,
+            0.,
+        );
+        Ok(())
+    }
+}
+
+/// Render the cover page, which holds the decoder specification as both a QR code and text.
+pub fn render_cover(
+    layout: &layout::Options,
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+    commit: &str,
+) -> Result<()> {
+    let title_font = SizedFont::new(doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
+    let text_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(10.0))?;
+    let top = layout.margin_bottom + layout.avail_height;
+    let left = layout.margin_left;
+
+    title_font.write(
+        layer,
+        "Paperback decoder specification",
+        left,
+        top - title_font.size.into(),
+        &Alignment::Left,
+        0.,
+    );
+    text_font.write(
+        layer,
+        format!("github.com/mook/paperpack@{commit}"),
+        left,
+        top - (title_font.size + text_font.size * 1.5).into(),
+        &Alignment::Left,
+        0.,
+    );
+
+    let svg = text_code_svg(DECODER_SPEC, "decoder specification")?;
+    let desired_svg_length = std::cmp::min(Mm(80.), layout.avail_width);
+    let actual_svg_length: Mm = svg.height.into_pt(DOTS_PER_INCH).into();
+    let svg_top = top - (title_font.size + text_font.size * 4.).into();
+    svg.add_to_layer(
+        layer,
+        printpdf::svg::SvgTransform {
+            translate_x: Some(left.into()),
+            translate_y: Some((svg_top - desired_svg_length).into()),
+            rotate: None,
+            scale_x: Some(desired_svg_length / actual_svg_length),
+            scale_y: Some(desired_svg_length / actual_svg_length),
+            dpi: Some(DOTS_PER_INCH),
+        },
+    );
+
+    let mut y = svg_top - desired_svg_length - (text_font.size * 3.).into();
+    for line in DECODER_SPEC.lines() {
+        text_font.write(layer, line, left, y, &Alignment::Left, 0.);
+        y -= (text_font.size * 1.2).into();
+    }
+
+    Ok(())
+}
+
+/// Encode text as the smallest QR code that holds it, as an SVG without a quiet zone.  Byte mode
+/// is forced, like the payload codes, so that readers return the text unchanged.
+fn text_code_svg(text: &str, what: &str) -> Result<Svg> {
+    let bits = (1..=40)
+        .find_map(|version| {
+            let mut bits = qrcode::bits::Bits::new(qrcode::Version::Normal(version));
+            bits.push_byte_data(text.as_bytes()).ok()?;
+            bits.push_terminator(qrcode::EcLevel::M).ok()?;
+            Some(bits)
+        })
+        .ok_or(anyhow!("{what} does not fit in a QR code"))?;
+    let svg_string = QrCode::with_bits(bits, qrcode::EcLevel::M)?
+        .render::<qrcode::render::svg::Color>()
+        .quiet_zone(false)
+        .module_dimensions(1, 1)
+        .build();
+    Ok(printpdf::svg::Svg::parse(&svg_string)?)
+}
+
+/// Render a page with a QR code linking to a web restorer set up for this document, for people
+/// who would rather use a phone than install paperback.
+pub fn render_web_restorer(
+    layout: &layout::Options,
+    url: &str,
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+    commit: &str,
+) -> Result<()> {
+    let title_font = SizedFont::new(doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
+    let text_font = SizedFont::new(doc, BuiltinFont::Helvetica, Pt(11.0))?;
+    let url_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(8.0))?;
+    let top = layout.margin_bottom + layout.avail_height;
+    let left = layout.margin_left;
+    let right = left + layout.avail_width;
+
+    let mut y = top - title_font.size.into();
+    title_font.write(
+        layer,
+        format!("Restoring document {}", layout.document_id()),
+        left,
+        y,
+        &Alignment::Left,
+        0.,
+    );
+    y -= (title_font.size * 0.5).into();
+    let instructions = format!(
+        "Scan this code with a phone to open a web page set up to restore this document, then \
+         scan the other {} pages with it; any {} of them are enough. If the page is not \
+         available, the pages can also be restored with paperback, from \
+         github.com/mook/paperpack@{commit}.",
+        layout.recovery_page_count, layout.data_page_count
+    );
+    let instruction_lines = 5;
+    text_font.write_section(
+        layer,
+        instructions.split_whitespace(),
+        &metrics::Bounds {
+            top: y,
+            right,
+            bottom: y - (text_font.size * instruction_lines as f32).into(),
+            left,
+        },
+        &Alignment::Left,
+    );
+    y -= (text_font.size * (instruction_lines + 1) as f32).into();
+
+    let svg = text_code_svg(url, "web restorer link")?;
+    let desired_svg_length = std::cmp::min(Mm(80.), layout.avail_width);
+    let actual_svg_length: Mm = svg.height.into_pt(DOTS_PER_INCH).into();
+    svg.add_to_layer(
+        layer,
+        printpdf::svg::SvgTransform {
+            translate_x: Some(left.into()),
+            translate_y: Some((y - desired_svg_length).into()),
+            rotate: None,
+            scale_x: Some(desired_svg_length / actual_svg_length),
+            scale_y: Some(desired_svg_length / actual_svg_length),
+            dpi: Some(DOTS_PER_INCH),
+        },
+    );
+    y -= desired_svg_length + (url_font.size * 2.).into();
+
+    // Print the link too, in case the code cannot be scanned.
+    let url_lines = url_font.metrics.wrap_lines(
+        url_font.size,
+        &mut std::iter::once(url).collect(),
+        layout.avail_width,
+        usize::MAX,
+    );
+    for line in url_lines {
+        url_font.write(layer, line, left, y, &Alignment::Left, 0.);
+        y -= (url_font.size * 1.2).into();
+    }
+
+    Ok(())
+}
+
+/// Render a key card, with a QR code holding the record that is needed along with the passphrase
+/// to decrypt the document, for keeping apart from the pages.
+pub fn render_key_card(
+    layout: &layout::Options,
+    encryption: &Encryption,
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+    commit: &str,
+) -> Result<()> {
+    let title_font = SizedFont::new(doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
+    let text_font = SizedFont::new(doc, BuiltinFont::Helvetica, Pt(11.0))?;
+    let record_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(8.0))?;
+    let top = layout.margin_bottom + layout.avail_height;
+    let left = layout.margin_left;
+    let right = left + layout.avail_width;
+
+    let mut y = top - title_font.size.into();
+    title_font.write(
+        layer,
+        format!("Key card for document {}", layout.document_id()),
+        left,
+        y,
+        &Alignment::Left,
+        0.,
+    );
+    y -= (title_font.size * 0.5).into();
+    let instructions = format!(
+        "This card holds the salt and nonce that are needed, along with the passphrase, to \
+         decrypt the document; the pages do not have them. Keep it apart from the pages. To \
+         restore the document, scan this code along with the pages, with paperback from \
+         github.com/mook/paperpack@{commit}."
+    );
+    let instruction_lines = 5;
+    text_font.write_section(
+        layer,
+        instructions.split_whitespace(),
+        &metrics::Bounds {
+            top: y,
+            right,
+            bottom: y - (text_font.size * instruction_lines as f32).into(),
+            left,
+        },
+        &Alignment::Left,
+    );
+    y -= (text_font.size * (instruction_lines + 1) as f32).into();
+
+    let text = encryption.key_card_text(&layout.identifier);
+    let svg = text_code_svg(&text, "key card")?;
+    let desired_svg_length = std::cmp::min(Mm(50.), layout.avail_width);
+    let actual_svg_length: Mm = svg.height.into_pt(DOTS_PER_INCH).into();
+    svg.add_to_layer(
+        layer,
+        printpdf::svg::SvgTransform {
+            translate_x: Some(left.into()),
+            translate_y: Some((y - desired_svg_length).into()),
+            rotate: None,
+            scale_x: Some(desired_svg_length / actual_svg_length),
+            scale_y: Some(desired_svg_length / actual_svg_length),
+            dpi: Some(DOTS_PER_INCH),
+        },
+    );
+    y -= desired_svg_length + (record_font.size * 2.).into();
+
+    // Print the code's text too, in case the code cannot be scanned.
+    let text_lines = record_font.metrics.wrap_lines(
+        record_font.size,
+        &mut std::iter::once(text.as_str()).collect(),
+        layout.avail_width,
+        usize::MAX,
+    );
+    for line in text_lines {
+        record_font.write(layer, line, left, y, &Alignment::Left, 0.);
+        y -= (record_font.size * 1.2).into();
+    }
+
+    Ok(())
+}
+
+/// Render a calibration page: one QR code of the given version and module length, and a ruler,
+/// labelled with their exact sizes so that a printout can be checked for scaling.
+pub fn render_ruler(
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+    page: &PageDimensions,
+    module_length: Mm,
+    version: qrcode::Version,
+    printer_dpi: u32,
+) -> Result<()> {
+    const MARGIN: Mm = Mm(20.);
+    let title_font = SizedFont::new(doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
+    let text_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(10.0))?;
+    let label_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(8.0))?;
+    let left = MARGIN;
+    let avail_width = page.width - MARGIN * 2.;
+    let code_length = module_length * version.width() as f32;
+    if code_length + module_length * 8. > avail_width {
+        Err(anyhow!(
+            "a {version:?} code of {:.2}mm modules ({:.1}mm) does not fit on the page",
+            module_length.0,
+            code_length.0
+        ))?;
+    }
+    let ruler_length = Mm(100_f32.min(avail_width.0.floor()));
+    let dots = |length: Mm| length.0 / MM_PER_INCH * printer_dpi as f32;
+
+    let mut y = page.height - MARGIN - title_font.size.into();
+    title_font.write(
+        layer,
+        "Paperback print calibration",
+        left,
+        y,
+        &Alignment::Left,
+        0.,
+    );
+    y -= (text_font.size * 2.).into();
+    let lines = [
+        format!(
+            "Module: {:.2} mm = {:.2} dots at {printer_dpi} DPI",
+            module_length.0,
+            dots(module_length)
+        ),
+        format!(
+            "Code:   {:.2} mm = {:.1} dots ({} modules)",
+            code_length.0,
+            dots(code_length),
+            version.width()
+        ),
+        format!(
+            "Ruler:  {:.0} mm = {:.1} dots",
+            ruler_length.0,
+            dots(ruler_length)
+        ),
+    ];
+    for line in &lines {
+        text_font.write(layer, line, left, y, &Alignment::Left, 0.);
+        y -= (text_font.size * 1.2).into();
+    }
+    let advice = "The code is measured without its quiet zone. If the printed code or ruler \
+        measures differently, the printer is scaling the page; turn off any \"fit to page\" \
+        option. Modules of a whole number of printer dots print most evenly.";
+    let advice_lines = 6;
+    text_font.write_section(
+        layer,
+        advice.split_whitespace(),
+        &metrics::Bounds {
+            top: y,
+            right: left + avail_width,
+            bottom: y - (text_font.size * advice_lines as f32).into(),
+            left,
+        },
+        &Alignment::Left,
+    );
+    y -= (text_font.size * (advice_lines + 1) as f32).into();
+
+    // The code only needs to be the right size, so it holds a short description of itself.
+    let mut bits = qrcode::bits::Bits::new(version);
+    bits.push_byte_data(format!("RULER {:.2}MM", module_length.0).as_bytes())?;
+    bits.push_terminator(qrcode::EcLevel::L)?;
+    let svg_string = QrCode::with_bits(bits, qrcode::EcLevel::L)?
+        .render::<qrcode::render::svg::Color>()
+        .quiet_zone(false)
+        .module_dimensions(1, 1)
+        .build();
+    let svg = printpdf::svg::Svg::parse(&svg_string)?;
+    let code_top = y - module_length * 4.;
+    let scale_factor = module_length.0 * DOTS_PER_INCH / MM_PER_INCH;
+    svg.add_to_layer(
+        layer,
+        printpdf::svg::SvgTransform {
+            translate_x: Some((left + module_length * 4.).into()),
+            translate_y: Some((code_top - code_length).into()),
+            rotate: None,
+            scale_x: Some(scale_factor),
+            scale_y: Some(scale_factor),
+            dpi: Some(DOTS_PER_INCH),
+        },
+    );
+
+    // The ruler has a tick every millimetre, longer ones every 5mm, and labels every 10mm.
+    let ruler_top = code_top - code_length - module_length * 4. - Mm(10.);
+    let rect = |left: Mm, right: Mm, bottom: Mm, top: Mm| printpdf::Rect {
+        ll: printpdf::Point {
+            x: left.into_pt(),
+            y: bottom.into_pt(),
+        },
+        ur: printpdf::Point {
+            x: right.into_pt(),
+            y: top.into_pt(),
+        },
+        mode: printpdf::path::PaintMode::Fill,
+        winding: printpdf::path::WindingOrder::EvenOdd,
+    };
+    let thickness = Mm(0.15);
+    layer.add_rect(rect(
+        left - thickness / 2.,
+        left + ruler_length + thickness / 2.,
+        ruler_top - thickness,
+        ruler_top,
+    ));
+    for mm in 0..=ruler_length.0 as usize {
+        let x = left + Mm(mm as f32);
+        let tick_length = match mm {
+            _ if mm.is_multiple_of(10) => Mm(5.),
+            _ if mm.is_multiple_of(5) => Mm(3.5),
+            _ => Mm(2.),
+        };
+        layer.add_rect(rect(
+            x - thickness / 2.,
+            x + thickness / 2.,
+            ruler_top - tick_length,
+            ruler_top,
+        ));
+        if mm.is_multiple_of(10) {
+            label_font.write(
+                layer,
+                mm.to_string(),
+                x,
+                ruler_top - Mm(5.) - label_font.size.into(),
+                &Alignment::Center,
+                0.,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The width of the strip drawn by [`render_spine_label`], to fit the spine of a typical binder.
+const SPINE_LABEL_WIDTH: Mm = Mm(30.);
+
+/// Render a strip along the left of the page, to be cut out and put in the spine of a binder.  It
+/// describes the document in text running up the strip.
+pub fn render_spine_label(
+    layout: &layout::Options,
+    file_name: &str,
+    page_count: usize,
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+) -> Result<()> {
+    let left = layout.margin_left;
+    let bottom = layout.margin_bottom;
+    let length = layout.avail_height;
+    let padding = Mm(5.);
+
+    // Outline the strip, as a guide for cutting it out.
+    layer.set_outline_thickness(0.25);
+    layer.add_rect(printpdf::Rect {
+        ll: printpdf::Point {
+            x: left.into_pt(),
+            y: bottom.into_pt(),
+        },
+        ur: printpdf::Point {
+            x: (left + SPINE_LABEL_WIDTH).into_pt(),
+            y: (bottom + length).into_pt(),
+        },
+        mode: printpdf::path::PaintMode::Stroke,
+        winding: printpdf::path::WindingOrder::EvenOdd,
+    });
+
+    let details = format!(
+        "{}  |  {page_count} pages  |  {}",
+        layout.document_id(),
+        today()
+    );
+    // Shrink the text if needed so that it fits along the strip.
+    let fitted = |font: BuiltinFont, size: Pt, text: &str| {
+        let metrics: &metrics::FontMetrics = font.into();
+        let max_size = Pt::from(length - padding * 2.) / metrics.measure(text).max(f32::EPSILON);
+        SizedFont::new(doc, font, std::cmp::min(size, max_size))
+    };
+    let title_font = fitted(BuiltinFont::HelveticaBold, Pt(24.), file_name)?;
+    let details_font = fitted(BuiltinFont::Helvetica, Pt(14.), &details)?;
 
+    // The text is rotated to run up the strip, so its ascenders point to the left.
+    let text_width = title_font.size + details_font.size * 1.3;
+    let title_x = left + (SPINE_LABEL_WIDTH - text_width.into()) / 2. + title_font.size.into();
+    let y = bottom + padding;
+    title_font.write(layer, file_name, title_x, y, &Alignment::Left, 90.);
+    details_font.write(
+        layer,
+        &details,
+        title_x + (details_font.size * 1.3).into(),
+        y,
+        &Alignment::Left,
+        90.,
+    );
     Ok(())
 }
 
+/// Today's date (in UTC), as YYYY-MM-DD.
+fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86400);
+    civil_date(days as i64)
+}
+
+/// The current time, as YYYY-MM-DD HH:MM UTC.
+pub(crate) fn timestamp() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, seconds_of_day) = seconds.div_rem(&86400);
+    format!(
+        "{} {:02}:{:02} UTC",
+        civil_date(days as i64),
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60
+    )
+}
+
+/// The date the given number of days after 1970-01-01, as YYYY-MM-DD.
+fn civil_date(days: i64) -> String {
+    // Convert days since 1970-01-01 to a proleptic Gregorian date, by counting 400 year eras
+    // starting on 0000-03-01 (so that the leap day is at the end of each year).
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The bottom left corner of the code at `index` on a page whose codes are at the given vertical
+/// offset.
+pub(super) fn code_origin(vertical_offset: Mm, layout: &layout::Options, index: usize) -> (Mm, Mm) {
+    let shard_width = layout.module_length * layout.version.width().into();
+    match layout.code_positions {
+        // Centre the code in its position; template coordinates are from the top of the page.
+        Some(ref positions) => {
+            let position = positions[index];
+            let inset = (Mm(position.size) - shard_width) / 2.0;
+            (
+                Mm(position.x) + inset,
+                layout.page_height - Mm(position.y + position.size) + inset,
+            )
+        }
+        None => {
+            let quiet_offset = layout.module_length * 4.0;
+            let area_width = shard_width * layout.shards_per_row as f32
+                + quiet_offset * (layout.shards_per_row - 1) as f32;
+            let left_offset = (layout.page_width - area_width) / 2.0;
+            let chunk_offset = shard_width + quiet_offset;
+            let (row, col) = layout.grid_position(index);
+            (
+                left_offset + chunk_offset * col as f32,
+                layout.margin_bottom + vertical_offset + chunk_offset * row as f32,
+            )
+        }
+    }
+}
+
+/// The size of the index labels, which go in the quiet zone below each code, so they must be
+/// small.
+pub(super) fn index_label_size(layout: &layout::Options) -> Pt {
+    std::cmp::min(Pt(6.), (layout.module_length * 2.5).into())
+}
+
 /// Render the QR codes on a page at the given vertical offset
 fn render_codes(
     vertical_offset: Mm,
     layout: &layout::Options,
     codes: &mut impl Iterator<Item = Svg>,
+    page_num: usize,
+    doc: &PdfDocumentReference,
     layer: &PdfLayerReference,
 ) -> Result<()> {
-    let shard_width = layout.module_length * layout.version.width().into();
-    let quiet_offset = layout.module_length * 4.0;
-    let area_width = shard_width * layout.shards_per_row as f32
-        + quiet_offset * (layout.shards_per_row - 1) as f32;
-    let left_offset = (layout.page_width - area_width) / 2.0;
-    let chunk_offset = shard_width + quiet_offset;
-    for row in 0..layout.shards_per_row {
-        for col in 0..layout.shards_per_row {
-            let svg = codes.next().ok_or(anyhow!("Ran out of QR codes"))?;
-            // Scale factor, in dots.
-            let scale_factor = layout.module_length.0 * DOTS_PER_INCH / MM_PER_INCH;
-            let transform = printpdf::svg::SvgTransform {
-                translate_x: Some((left_offset + chunk_offset * col as f32).into()),
-                translate_y: Some(
-                    (layout.margin_bottom + vertical_offset + chunk_offset * row as f32).into(),
-                ),
-                rotate: None,
-                scale_x: Some(scale_factor),
-                scale_y: Some(scale_factor),
-                dpi: Some(DOTS_PER_INCH),
-            };
-            svg.add_to_layer(layer, transform);
+    let label_font = if layout.index_labels {
+        Some(SizedFont::new(
+            doc,
+            BuiltinFont::Courier,
+            index_label_size(layout),
+        )?)
+    } else {
+        None
+    };
+    for index in 0..layout.codes_per_page() {
+        let (x, y) = code_origin(vertical_offset, layout, index);
+        let svg = codes.next().ok_or(anyhow!("Ran out of QR codes"))?;
+        // Scale factor, in dots.
+        let scale_factor = layout.module_length.0 * DOTS_PER_INCH / MM_PER_INCH;
+        let transform = printpdf::svg::SvgTransform {
+            translate_x: Some(x.into()),
+            translate_y: Some(y.into()),
+            rotate: None,
+            scale_x: Some(scale_factor),
+            scale_y: Some(scale_factor),
+            dpi: Some(DOTS_PER_INCH),
+        };
+        svg.add_to_layer(layer, transform);
+        if let Some(ref label_font) = label_font {
+            let shard_index = layout.shard_index(page_num, index);
+            label_font.write(
+                layer,
+                format!("{shard_index}-{}", index_check_char(shard_index)),
+                x,
+                y - label_font.size.into(),
+                &Alignment::Left,
+                0.,
+            );
         }
     }
 
     Ok(())
 }
 
+/// The largest size for `--big-page-numbers`.
+const BIG_PAGE_NUMBER_SIZE: Pt = Pt(96.);
+
+/// The smallest size for `--big-page-numbers` that is still worth printing.
+const MIN_BIG_PAGE_NUMBER_SIZE: Pt = Pt(18.);
+
+/// Print the page number large and faint in the space between the top row of codes and the end of
+/// the code area, so that shuffled pages are easy to put back in order.
+fn render_big_page_number(
+    vertical_offset: Mm,
+    layout: &layout::Options,
+    page_num: usize,
+    doc: &PdfDocumentReference,
+    layer: &PdfLayerReference,
+) -> Result<()> {
+    let (size, x, y) = big_page_number_position(vertical_offset, layout)?;
+    let font = SizedFont::new(doc, BuiltinFont::HelveticaBold, size)?;
+    layer.save_graphics_state();
+    layer.set_fill_color(BIG_PAGE_NUMBER_COLOR);
+    font.write(
+        layer,
+        (page_num + 1).to_string(),
+        x,
+        y,
+        &Alignment::Right,
+        0.,
+    );
+    layer.restore_graphics_state();
+    Ok(())
+}
+
+/// The colour of the big page numbers, which is light enough not to be mistaken for a code.
+pub(super) const BIG_PAGE_NUMBER_COLOR: printpdf::Color =
+    printpdf::Color::Greyscale(printpdf::Greyscale {
+        percent: 0.8,
+        icc_profile: None,
+    });
+
+/// Work out the size of the big page number, and where the right end of its baseline goes.
+pub(super) fn big_page_number_position(
+    vertical_offset: Mm,
+    layout: &layout::Options,
+) -> Result<(Pt, Mm, Mm)> {
+    let shard_width = layout.module_length * layout.version.width().into();
+    let quiet_offset = layout.module_length * 4.0;
+    let rows = layout.codes_per_page().div_ceil(layout.shards_per_row);
+    let grid_height = (shard_width + quiet_offset) * rows as f32 - quiet_offset;
+    let area_width = (shard_width + quiet_offset) * layout.shards_per_row as f32 - quiet_offset;
+    // Leave a quiet zone between the number and the codes below it, and the banner above it.
+    let room = layout.code_area_height - grid_height - quiet_offset * 2.0;
+    let metrics: &metrics::FontMetrics = BuiltinFont::HelveticaBold.into();
+    let size = std::cmp::min(
+        BIG_PAGE_NUMBER_SIZE,
+        Pt::from(room) / (metrics.ascender / 1000.),
+    );
+    if size < MIN_BIG_PAGE_NUMBER_SIZE {
+        Err(anyhow!(
+            "not enough room above the codes for --big-page-numbers ({:.1}mm)",
+            room.0
+        ))?;
+    }
+    Ok((
+        size,
+        (layout.page_width + area_width) / 2.0,
+        layout.margin_bottom + vertical_offset + grid_height + quiet_offset,
+    ))
+}
+
+/// The characters used for index check digits: digits and upper case letters, without I and O as
+/// they are easily confused with 1 and 0.
+const CHECK_CHARS: &[u8; 34] = b"0123456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Compute a check character for a shard index, so that someone transcribing codes by hand can
+/// verify the index.  This is the Luhn mod N algorithm over the base-34 digits of the index, which
+/// detects any single wrong digit and most swaps of adjacent digits.
+pub(super) fn index_check_char(index: usize) -> char {
+    let base = CHECK_CHARS.len();
+    let mut remaining = index;
+    let mut sum = 0;
+    let mut double = true;
+    loop {
+        let (quotient, digit) = remaining.div_rem(&base);
+        let addend = if double { digit * 2 } else { digit };
+        sum += addend / base + addend % base;
+        double = !double;
+        remaining = quotient;
+        if remaining == 0 {
+            break;
+        }
+    }
+    CHECK_CHARS[(base - sum % base) % base] as char
+}
+
 /// Render the banner at the given verical offset.
 fn render_banner(
     bounds: &Bounds,
     layout: &layout::Options,
     page_num: usize,
+    page_checksum: Option<&str>,
     doc: &PdfDocumentReference,
     layer: &PdfLayerReference,
     commit: &str,
@@ -117,12 +906,13 @@ fn render_banner(
         percent: 100.,
         icc_profile: None,
     });
-    let repo_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(14.0))?;
-    layer.set_fill_color(BLACK);
+    let banner_color = layout.banner_color.clone().unwrap_or(BLACK);
+    let repo_font = banner_font(doc, layout, BuiltinFont::Courier, Pt(14.0))?;
+    layer.set_fill_color(banner_color.clone());
     layer.add_rect(printpdf::Rect {
         ll: printpdf::Point {
             x: bounds.left.into_pt(),
-            y: if page_num % 2 == 0 {
+            y: if page_num.is_multiple_of(2) {
                 bounds.bottom.into_pt()
             } else {
                 bounds.top.into_pt() - repo_font.size * 2.
@@ -130,7 +920,7 @@ fn render_banner(
         },
         ur: printpdf::Point {
             x: bounds.right.into_pt(),
-            y: if page_num % 2 == 0 {
+            y: if page_num.is_multiple_of(2) {
                 bounds.bottom.into_pt() + repo_font.size * 2.
             } else {
                 bounds.top.into_pt()
@@ -145,140 +935,425 @@ fn render_banner(
         layer,
         &repo,
         bounds.left + bounds.width() / 2.0,
-        if page_num % 2 == 0 {
+        if page_num.is_multiple_of(2) {
             bounds.bottom + (repo_font.size - repo_font.descender()).into()
         } else {
             bounds.top - (repo_font.size + repo_font.descender()).into()
         },
         &Alignment::Center,
+        0.,
     );
-    layer.set_fill_color(BLACK);
+    layer.set_fill_color(banner_color);
 
     // Draw the metadata QR codes.
-    let mut buf = Vec::<u8>::with_capacity(MetaHeader::LENGTH);
-    Header::Meta(MetaHeader {
-        identifier: layout.identifier,
-        hash: layout.hash,
-        original_count: u16::try_from(layout.data_shard_count)
-            .map_err(|_| anyhow!("cannot render {} data chunks", layout.data_shard_count))?,
-        recovery_count: u16::try_from(layout.recovery_shard_count).map_err(|_| {
-            anyhow!(
-                "cannot render {} recovery chunks",
-                layout.recovery_shard_count
-            )
-        })?,
-        shard_bytes: layout.data_bytes_per_shard as u64,
-    })
-    .write_to(&mut buf)?;
     // Similar to the recovery chunks, we need to convert to string and back to SVG.
-    let svg_string = QrCode::with_error_correction_level(&buf, qrcode::EcLevel::H)?
-        .render::<qrcode::render::svg::Color>()
-        .quiet_zone(false)
-        .module_dimensions(1, 1)
-        .build();
-    let svg = printpdf::svg::Svg::parse(&svg_string)?;
+    let svg = printpdf::svg::Svg::parse(&meta_code_svg(layout)?.0)?;
     let repo_banner_height = repo_font.size * 2.;
-    let desired_svg_length: Mm = Pt(128.).into();
+    let desired_svg_length: Mm = layout::META_CODE_LENGTH.into();
     let actual_svg_length: Mm = svg.height.into_pt(DOTS_PER_INCH).into();
     let quiet_zone_length = desired_svg_length / ((svg.height.0 + 8) as f32) * 4.0;
-    let bottom_offset = if page_num % 2 == 0 {
+    let bottom_offset = if page_num.is_multiple_of(2) {
         bounds.bottom + repo_banner_height.into() + quiet_zone_length
     } else {
         bounds.top - desired_svg_length - repo_banner_height.into()
     };
+    // The copies alternate between the left and right ends of the banner, working inwards.
+    let left_copies = layout.meta_copies.div_ceil(2);
+    let right_copies = layout.meta_copies / 2;
+    let left_inset = desired_svg_length * left_copies as f32;
+    let right_inset = desired_svg_length * right_copies as f32;
     let object = svg.into_xobject(layer);
-    object.clone().add_to_layer(
-        layer,
-        printpdf::svg::SvgTransform {
-            translate_x: Some((bounds.left + quiet_zone_length).into()),
-            translate_y: Some(bottom_offset.into()),
-            rotate: None,
-            scale_x: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
-            scale_y: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
-            dpi: Some(DOTS_PER_INCH),
-        },
-    );
-    object.clone().add_to_layer(
-        layer,
-        printpdf::svg::SvgTransform {
-            translate_x: Some((bounds.right - desired_svg_length + quiet_zone_length).into()),
-            translate_y: Some(bottom_offset.into()),
-            rotate: None,
-            scale_x: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
-            scale_y: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
-            dpi: Some(DOTS_PER_INCH),
-        },
-    );
+    let copy_offsets = (0..left_copies)
+        .map(|i| bounds.left + desired_svg_length * i as f32)
+        .chain((0..right_copies).map(|i| bounds.right - desired_svg_length * (i + 1) as f32));
+    for copy_offset in copy_offsets {
+        object.clone().add_to_layer(
+            layer,
+            printpdf::svg::SvgTransform {
+                translate_x: Some((copy_offset + quiet_zone_length).into()),
+                translate_y: Some(bottom_offset.into()),
+                rotate: None,
+                scale_x: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
+                scale_y: Some((desired_svg_length - quiet_zone_length * 2.) / actual_svg_length),
+                dpi: Some(DOTS_PER_INCH),
+            },
+        );
+    }
 
     // Draw the title text: repo, page info, and document id (hash).
-    let info_font = SizedFont::new(doc, BuiltinFont::Courier, Pt(24.0))?;
-    let label_font = SizedFont::new(doc, BuiltinFont::HelveticaBold, Pt(14.0))?;
-    let description_font = SizedFont::new(doc, BuiltinFont::Helvetica, Pt(10.0))?;
-
-    let document_id = layout.hash[..6].to_base58();
-    info_font.write(
-        layer,
-        document_id,
-        bounds.left + desired_svg_length,
-        bottom_offset + info_font.descender().into(),
-        &Alignment::Left,
-    );
-    label_font.write(
-        layer,
-        "Document ID",
-        bounds.left + desired_svg_length,
-        bottom_offset + info_font.size.into() + label_font.descender().into(),
-        &Alignment::Left,
-    );
+    let info_font = banner_font(doc, layout, BuiltinFont::Courier, INFO_FONT_SIZE)?;
+    let label_font = banner_font(doc, layout, BuiltinFont::HelveticaBold, Pt(14.0))?;
+    let description_font = banner_font(doc, layout, BuiltinFont::Helvetica, Pt(10.0))?;
 
+    let document_id = layout.document_id();
     let page_info = format!(
         "{}/{}+{}",
         page_num + 1,
         layout.data_page_count,
         layout.recovery_page_count - layout.data_page_count
     );
-    info_font.write(
-        layer,
-        page_info,
-        bounds.right - desired_svg_length,
-        bottom_offset + info_font.descender().into(),
-        &Alignment::Right,
-    );
-    label_font.write(
+    // Width of the label column when the labels are rotated.
+    let label_width: Mm = (info_font.size + label_font.size).into();
+    // The values are shrunk if needed to fit beside the metadata codes, up to the middle of the
+    // banner, or when rotated, along the codes.
+    let fitted_info_font = |text: &str, inset: Mm| {
+        let room = if layout.vertical_labels {
+            desired_svg_length - quiet_zone_length * 2.
+        } else {
+            bounds.width() / 2. - inset
+        };
+        fitted_banner_font(
+            doc,
+            layout,
+            BuiltinFont::Courier,
+            INFO_FONT_SIZE,
+            text,
+            room,
+        )
+    };
+    let id_font = fitted_info_font(&document_id, left_inset)?;
+    let page_info_font = fitted_info_font(&page_info, right_inset)?;
+    if layout.vertical_labels {
+        // The text reads upwards, so the tops of the glyphs face left; `right` is the edge the
+        // bottoms of the glyphs face.
+        for (label, value, value_font, right) in [
+            (
+                "Document ID",
+                document_id,
+                &id_font,
+                bounds.left + left_inset + label_width,
+            ),
+            (
+                "Page Count",
+                page_info,
+                &page_info_font,
+                bounds.right - right_inset,
+            ),
+        ] {
+            value_font.write(
+                layer,
+                value,
+                right - value_font.descender().into(),
+                bottom_offset,
+                &Alignment::Left,
+                90.,
+            );
+            label_font.write(
+                layer,
+                label,
+                right - info_font.size.into() - label_font.descender().into(),
+                bottom_offset,
+                &Alignment::Left,
+                90.,
+            );
+        }
+    } else {
+        id_font.write(
+            layer,
+            document_id,
+            bounds.left + left_inset,
+            bottom_offset + id_font.descender().into(),
+            &Alignment::Left,
+            0.,
+        );
+        label_font.write(
+            layer,
+            "Document ID",
+            bounds.left + left_inset,
+            bottom_offset + info_font.size.into() + label_font.descender().into(),
+            &Alignment::Left,
+            0.,
+        );
+        page_info_font.write(
+            layer,
+            page_info,
+            bounds.right - right_inset,
+            bottom_offset + page_info_font.descender().into(),
+            &Alignment::Right,
+            0.,
+        );
+        label_font.write(
+            layer,
+            "Page Count",
+            bounds.right - right_inset,
+            bottom_offset + info_font.size.into() + label_font.descender().into(),
+            &Alignment::Right,
+            0.,
+        );
+    }
+
+    let description = description(layout, page_num, page_checksum);
+    // With rotated labels, the description gets the full height between the label columns.
+    let (left_inset, right_inset) = if layout.vertical_labels {
+        (
+            left_inset + label_width + quiet_zone_length,
+            right_inset + label_width + quiet_zone_length,
+        )
+    } else {
+        (left_inset, right_inset)
+    };
+    let mut description_bounds = metrics::Bounds {
+        top: bottom_offset + desired_svg_length - quiet_zone_length * 2.,
+        right: bounds.right - right_inset,
+        bottom: bottom_offset,
+        left: bounds.left + left_inset,
+    };
+    // In accessible mode, the description is too large to fit beside the metadata codes, so it
+    // goes in the rest of the banner, on the side away from the repository bar.
+    let full_width_bounds = layout.accessible.then(|| metrics::Bounds {
+        top: if page_num.is_multiple_of(2) {
+            bounds.top
+        } else {
+            bottom_offset - quiet_zone_length
+        },
+        right: bounds.right,
+        bottom: if page_num.is_multiple_of(2) {
+            bottom_offset + desired_svg_length
+        } else {
+            bounds.bottom
+        },
+        left: bounds.left,
+    });
+    if layout.progress_bar {
+        // Without rotated labels, the labels take up the bottom of the description area.
+        if !layout.vertical_labels {
+            description_bounds.bottom += (info_font.size + label_font.size).into();
+        }
+        description_bounds.bottom = render_progress_bar(
+            layer,
+            layout,
+            page_num,
+            &description_font,
+            &description_bounds,
+        );
+    }
+    description_font.write_columns(
         layer,
-        "Page Count",
-        bounds.right - desired_svg_length,
-        bottom_offset + info_font.size.into() + label_font.descender().into(),
-        &Alignment::Right,
+        description.split_whitespace(),
+        full_width_bounds.as_ref().unwrap_or(&description_bounds),
+        layout.description_columns,
+        &Alignment::Left,
     );
 
-    // Write some descriptive text.
-    let description = format!(
+    Ok(())
+}
+
+/// Encode the metadata as a QR code.
+pub(super) fn meta_code(layout: &layout::Options) -> Result<QrCode> {
+    let mut buf = Vec::<u8>::with_capacity(MetaHeader::LENGTH);
+    Header::Meta(layout.meta_header()?).write_to(&mut buf)?;
+    Ok(QrCode::with_error_correction_level(
+        &buf,
+        qrcode::EcLevel::H,
+    )?)
+}
+
+/// Encode the metadata as a QR code, returning it as an SVG without a quiet zone (so that each
+/// module is one unit), and its width in modules.
+pub(super) fn meta_code_svg(layout: &layout::Options) -> Result<(String, usize)> {
+    let code = meta_code(layout)?;
+    let svg_string = code
+        .render::<qrcode::render::svg::Color>()
+        .quiet_zone(false)
+        .module_dimensions(1, 1)
+        .build();
+    Ok((svg_string, code.width()))
+}
+
+/// The descriptive text in the banner, explaining what the page is and how to restore it.
+pub(super) fn description(
+    layout: &layout::Options,
+    page_num: usize,
+    page_checksum: Option<&str>,
+) -> String {
+    let meta_position = match (layout.accessible, layout.meta_copies) {
+        (true, _) if page_num.is_multiple_of(2) => "below",
+        (true, _) => "above",
+        (false, 1) => "to the left of",
+        (false, _) => "to the left and right of",
+    };
+    format!(
         "
         This is a paper backup created using the program listed {}.
         When {}, it can be used to restore the original file.
         More pages may be required if some QR codes fail to be decoded.
-        At least one copy of the QR code to the left and right of this text is required.
+        {}
+        {}
     ",
-        if page_num % 2 == 0 { "below" } else { "above" },
+        if page_num.is_multiple_of(2) {
+            "below"
+        } else {
+            "above"
+        },
         if layout.data_page_count == 1 {
             "any page is scanned".to_string()
         } else {
             format!("at least {} pages are combined", layout.data_page_count)
-        }
-    );
-    let description_bounds = &metrics::Bounds {
-        top: bottom_offset + desired_svg_length - quiet_zone_length * 2.,
-        right: bounds.right - desired_svg_length,
-        bottom: bottom_offset,
-        left: bounds.left + desired_svg_length,
-    };
-    description_font.write_section(
+        },
+        match (layout.meta_copies, meta_position) {
+            (1, position) => format!("The QR code {position} this text is required."),
+            (_, position) =>
+                format!("At least one copy of the QR code {position} this text is required."),
+        },
+        [
+            page_checksum.map(|checksum| format!("Page checksum: {checksum}.")),
+            layout
+                .print_run
+                .as_ref()
+                .map(|print_run| format!("Print run: {print_run}.")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ")
+    )
+}
+
+/// Load a font for the banner, as chosen by [`banner_font_choice`].
+fn banner_font(
+    doc: &PdfDocumentReference,
+    layout: &layout::Options,
+    font: BuiltinFont,
+    size: Pt,
+) -> Result<SizedFont<'static>> {
+    let (font, size) = banner_font_choice(layout, font, size);
+    SizedFont::new(doc, font, size)
+}
+
+/// Pick the font for the banner; in accessible mode, every font is a larger bold Helvetica.
+pub(super) fn banner_font_choice(
+    layout: &layout::Options,
+    font: BuiltinFont,
+    size: Pt,
+) -> (BuiltinFont, Pt) {
+    if layout.accessible {
+        (
+            BuiltinFont::HelveticaBold,
+            size * layout::ACCESSIBLE_FONT_SCALE,
+        )
+    } else {
+        (font, size)
+    }
+}
+
+/// The size of the Document ID and page count in the banner, and the smallest size they are
+/// shrunk to when they do not fit.
+const INFO_FONT_SIZE: Pt = Pt(24.0);
+const MIN_INFO_FONT_SIZE: Pt = Pt(12.0);
+
+/// Get a font for the banner as [`banner_font`] does, shrunk as [`fitted_font_choice`] does.
+fn fitted_banner_font(
+    doc: &PdfDocumentReference,
+    layout: &layout::Options,
+    font: BuiltinFont,
+    size: Pt,
+    text: &str,
+    room: Mm,
+) -> Result<SizedFont<'static>> {
+    let (font, size) = fitted_font_choice(layout, font, size, text, room);
+    SizedFont::new(doc, font, size)
+}
+
+/// Pick a font for the banner as [`banner_font_choice`] does, shrunk if needed (down to
+/// [`MIN_INFO_FONT_SIZE`], before any accessible scaling) so that `text` fits in `room`.
+pub(super) fn fitted_font_choice(
+    layout: &layout::Options,
+    font: BuiltinFont,
+    size: Pt,
+    text: &str,
+    room: Mm,
+) -> (BuiltinFont, Pt) {
+    let (full_font, full_size) = banner_font_choice(layout, font, size);
+    let metrics: &metrics::FontMetrics = full_font.into();
+    let width: Mm = (full_size * metrics.measure(text)).into();
+    if width <= room {
+        return (full_font, full_size);
+    }
+    let shrunk = Pt((size.0 * room.0 / width.0).max(MIN_INFO_FONT_SIZE.0));
+    banner_font_choice(layout, font, shrunk)
+}
+
+/// Render a line at the bottom of the given bounds showing which of the document's codes are on
+/// this page, as a label followed by a bar.  Returns the top of the line.
+fn render_progress_bar(
+    layer: &PdfLayerReference,
+    layout: &layout::Options,
+    page_num: usize,
+    font: &SizedFont,
+    bounds: &metrics::Bounds,
+) -> Mm {
+    let first = page_num * layout.shards_per_page;
+    let last = first + layout.shards_per_page;
+    let total = layout.recovery_shard_count;
+    let label = format!("Codes {}-{} of {total} ", first + 1, last);
+    font.write(
         layer,
-        description.split_whitespace(),
-        description_bounds,
+        &label,
+        bounds.left,
+        bounds.bottom + font.descender().into(),
         &Alignment::Left,
+        0.,
     );
 
-    Ok(())
+    let bar_left = bounds.left + font.measure(&label).into();
+    let bar_width = bounds.right - bar_left;
+    let bar_bottom = bounds.bottom + (font.size * 0.2).into();
+    let rect = |left: Mm, right: Mm, mode| printpdf::Rect {
+        ll: printpdf::Point {
+            x: left.into_pt(),
+            y: bar_bottom.into_pt(),
+        },
+        ur: printpdf::Point {
+            x: right.into_pt(),
+            y: bar_bottom.into_pt() + font.size * 0.6,
+        },
+        mode,
+        winding: printpdf::path::WindingOrder::EvenOdd,
+    };
+    layer.add_rect(rect(
+        bar_left + bar_width * (first as f32 / total as f32),
+        bar_left + bar_width * (last as f32 / total as f32),
+        printpdf::path::PaintMode::Fill,
+    ));
+    if let Some(ref color) = layout.banner_color {
+        layer.set_outline_color(color.clone());
+    }
+    layer.set_outline_thickness(0.5);
+    layer.add_rect(rect(
+        bar_left,
+        bounds.right,
+        printpdf::path::PaintMode::Stroke,
+    ));
+    bounds.bottom + (font.size * 1.5).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{civil_date, index_check_char};
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(11_016), "2000-02-29");
+        assert_eq!(civil_date(11_017), "2000-03-01");
+        assert_eq!(civil_date(19_722), "2023-12-31");
+        assert_eq!(civil_date(-1), "1969-12-31");
+    }
+
+    #[test]
+    fn test_index_check_char() {
+        assert_eq!(index_check_char(0), '0');
+        // Changing any one base-34 digit must change the check character.
+        for index in 0..34 * 34 {
+            let (high, low) = (index / 34, index % 34);
+            for other in 0..34 {
+                if other != low {
+                    assert_ne!(index_check_char(index), index_check_char(high * 34 + other));
+                }
+                if other != high {
+                    assert_ne!(index_check_char(index), index_check_char(other * 34 + low));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,189 @@
+use super::{
+    canvas::{self, Canvas},
+    layout,
+};
+use crate::{args::ModuleStyle, fonts::metrics::Alignment};
+use anyhow::{anyhow, Result};
+use printpdf::{BuiltinFont, Color, Mm, Pt};
+use qrcode::QrCode;
+use std::fmt::Write;
+
+/// `SvgPage` collects the drawing for one page as SVG elements.  Positions are given as for the
+/// PDF, in millimetres from the bottom left corner of the page, and are flipped here since SVG
+/// measures from the top.
+struct SvgPage {
+    width: Mm,
+    height: Mm,
+    body: String,
+}
+
+impl SvgPage {
+    fn new(width: Mm, height: Mm) -> Self {
+        SvgPage {
+            width,
+            height,
+            body: String::new(),
+        }
+    }
+
+    /// Draw a QR code with its bottom left corner at the given position.  `code` is an SVG as
+    /// rendered by the `qrcode` crate (or in the same form), with one unit per module and no quiet
+    /// zone, and `modules` is its width in modules.
+    fn code(&mut self, code: &str, modules: usize, x: Mm, y: Mm, size: Mm) -> Result<()> {
+        let contents = code
+            .split_once("<svg")
+            .and_then(|(_, rest)| rest.split_once('>'))
+            .and_then(|(_, rest)| rest.rsplit_once("</svg>"))
+            .map(|(contents, _)| contents)
+            .ok_or(anyhow!("unexpected QR code SVG"))?;
+        _ = write!(
+            self.body,
+            r#"<g transform="translate({} {}) scale({})">{contents}</g>"#,
+            x.0,
+            (self.height - y - size).0,
+            size.0 / modules as f32
+        );
+        Ok(())
+    }
+
+    fn finish(self) -> String {
+        format!(
+            concat!(
+                r#"<?xml version="1.0" standalone="yes"?>"#,
+                "\n",
+                r#"<svg xmlns="http://www.w3.org/2000/svg""#,
+                r#" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape""#,
+                r#" version="1.1" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+                "{body}</svg>\n"
+            ),
+            w = self.width.0,
+            h = self.height.0,
+            body = self.body
+        )
+    }
+}
+
+impl Canvas for SvgPage {
+    /// Start a group that editors such as Inkscape show as a layer.
+    fn begin_layer(&mut self, label: &str) {
+        _ = write!(
+            self.body,
+            r#"<g inkscape:groupmode="layer" inkscape:label="{}">"#,
+            escape(label)
+        );
+    }
+
+    fn end_layer(&mut self) {
+        self.body.push_str("</g>");
+    }
+
+    fn rect(&mut self, left: Mm, bottom: Mm, right: Mm, top: Mm, color: &Color) {
+        _ = write!(
+            self.body,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            left.0,
+            (self.height - top).0,
+            (right - left).0,
+            (top - bottom).0,
+            css_color(color)
+        );
+    }
+
+    fn text(
+        &mut self,
+        font: BuiltinFont,
+        size: Pt,
+        text: &str,
+        x: Mm,
+        y: Mm,
+        alignment: &Alignment,
+        color: &Color,
+    ) {
+        let (family, weight) = match font {
+            BuiltinFont::Courier => ("Courier, monospace", "normal"),
+            BuiltinFont::HelveticaBold => ("Helvetica, Arial, sans-serif", "bold"),
+            _ => ("Helvetica, Arial, sans-serif", "normal"),
+        };
+        let anchor = match alignment {
+            Alignment::Left => "start",
+            Alignment::Right => "end",
+            Alignment::Center => "middle",
+        };
+        _ = write!(
+            self.body,
+            r#"<text x="{}" y="{}" font-family="{family}" font-weight="{weight}" font-size="{}" text-anchor="{anchor}" fill="{}" xml:space="preserve">{}</text>"#,
+            x.0,
+            (self.height - y).0,
+            Mm::from(size).0,
+            css_color(color),
+            escape(text)
+        );
+    }
+
+    fn qr_code(&mut self, code: &QrCode, style: ModuleStyle, x: Mm, y: Mm, size: Mm) -> Result<()> {
+        self.code(&super::code_svg(code, style), code.width(), x, y, size)
+    }
+}
+
+/// Escape text for use in SVG content and attribute values.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Convert a PDF colour to the CSS form used in SVG.
+fn css_color(color: &Color) -> String {
+    let [r, g, b] = canvas::rgb(color);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Render a page as an SVG document, laid out as [`render::render_page`] lays out the PDF page.
+/// `codes` are the payload codes on the page.
+pub(super) fn render_page(
+    layout: &layout::Options,
+    codes: &[QrCode],
+    page_num: usize,
+    page_checksum: Option<&str>,
+    commit: &str,
+) -> Result<String> {
+    let mut page = SvgPage::new(layout.page_width, layout.page_height);
+    canvas::draw_page(&mut page, layout, codes, page_num, page_checksum, commit)?;
+    Ok(page.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{css_color, SvgPage};
+    use anyhow::Result;
+    use printpdf::{Color, Greyscale, Mm, Rgb};
+
+    #[test]
+    fn test_css_color() {
+        let grey = Color::Greyscale(Greyscale::new(0.8, None));
+        assert_eq!(css_color(&grey), "#cccccc");
+        let blue = Color::Rgb(Rgb::new(
+            0x1f as f32 / 255.,
+            0x4e as f32 / 255.,
+            0x79 as f32 / 255.,
+            None,
+        ));
+        assert_eq!(css_color(&blue), "#1f4e79");
+    }
+
+    #[test]
+    fn test_code_position() -> Result<()> {
+        // The code is placed by its top left corner, measured from the top of the page.
+        let mut page = SvgPage::new(Mm(100.), Mm(200.));
+        let code =
+            r#"<?xml version="1.0"?><svg viewBox="0 0 21 21"><path d="M0 0h1v1H0V0"/></svg>"#;
+        page.code(code, 21, Mm(10.), Mm(20.), Mm(42.))?;
+        assert_eq!(
+            page.body,
+            r#"<g transform="translate(10 138) scale(2)"><path d="M0 0h1v1H0V0"/></g>"#
+        );
+        assert!(page.code("<path/>", 21, Mm(0.), Mm(0.), Mm(1.)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{header::Encryption, Failure};
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305,
+};
+use chksum_hash_sha2_512 as sha512;
+
+/// The environment variable read for the passphrase before prompting for one.
+pub(crate) const PASSPHRASE_VARIABLE: &str = "PAPERBACK_PASSPHRASE";
+
+/// Get the passphrase from [`PASSPHRASE_VARIABLE`], or else by prompting on the terminal; if
+/// `confirm` is set, the prompt asks for it twice.
+pub(crate) fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VARIABLE) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Passphrase: ").map_err(|e| {
+        anyhow!("failed to read the passphrase (or set {PASSPHRASE_VARIABLE}): {e}")
+    })?;
+    if confirm && rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+        Err(anyhow!("the passphrases do not match"))?;
+    }
+    if passphrase.is_empty() {
+        Err(anyhow!("the passphrase is empty"))?;
+    }
+    Ok(passphrase)
+}
+
+/// Derive the XChaCha20-Poly1305 key from the passphrase with Argon2id, using the library's
+/// default parameters (19 MiB of memory, 2 passes, 1 lane).
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("failed to derive the key: {e}"))?;
+    Ok(key)
+}
+
+/// The first bytes of the SHA-512 of the key, which tell a wrong passphrase apart from damaged
+/// data without revealing anything about the key.
+fn key_check(key: &[u8]) -> [u8; 8] {
+    let mut check = [0; 8];
+    check.copy_from_slice(&sha512::hash(key).into_inner()[..8]);
+    check
+}
+
+/// Fill the buffer with random bytes from the operating system.
+pub(crate) fn fill_random(buf: &mut [u8]) {
+    OsRng.fill_bytes(buf);
+}
+
+/// Encrypt the data with a key derived from the passphrase and a new random salt, returning the
+/// record needed to decrypt it along with the encrypted data (which is 16 bytes longer).
+pub(crate) fn encrypt(passphrase: &str, data: &[u8]) -> Result<(Encryption, Vec<u8>)> {
+    let mut salt = [0; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = XChaCha20Poly1305::new(&key.into())
+        .encrypt(&nonce, data)
+        .map_err(|e| anyhow!("failed to encrypt: {e}"))?;
+    let encryption = Encryption {
+        salt,
+        nonce: nonce.into(),
+        key_check: key_check(&key),
+    };
+    Ok((encryption, encrypted))
+}
+
+/// Decrypt data encrypted by [`encrypt`].  A wrong passphrase is reported as such; data that does
+/// not decrypt with the right one is a [`Failure::ChecksumMismatch`], as the shards are damaged.
+pub(crate) fn decrypt(passphrase: &str, encryption: &Encryption, data: &[u8]) -> Result<Vec<u8>> {
+    let key = derive_key(passphrase, &encryption.salt)?;
+    if key_check(&key) != encryption.key_check {
+        Err(anyhow!("wrong passphrase"))?;
+    }
+    XChaCha20Poly1305::new(&key.into())
+        .decrypt(&encryption.nonce.into(), data)
+        .map_err(|_| {
+            anyhow!(Failure::ChecksumMismatch(
+                "restored data could not be decrypted, although the passphrase is right"
+                    .to_string()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decrypt, encrypt};
+    use crate::Failure;
+    use anyhow::Result;
+
+    #[test]
+    fn test_encryption() -> Result<()> {
+        let data = b"some secret data".to_vec();
+        let (encryption, mut encrypted) = encrypt("correct horse", &data)?;
+        assert_eq!(encrypted.len(), data.len() + 16);
+        assert_ne!(&encrypted[..data.len()], data.as_slice());
+        assert_eq!(decrypt("correct horse", &encryption, &encrypted)?, data);
+
+        let error = decrypt("battery staple", &encryption, &encrypted).unwrap_err();
+        assert_eq!(error.to_string(), "wrong passphrase");
+        assert!(error.downcast_ref::<Failure>().is_none());
+
+        encrypted[0] ^= 1;
+        let error = decrypt("correct horse", &encryption, &encrypted).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Failure>(),
+            Some(Failure::ChecksumMismatch(_))
+        ));
+        Ok(())
+    }
+}
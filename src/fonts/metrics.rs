@@ -39,6 +39,74 @@ impl FontMetrics {
         }
         sum / 1000.
     }
+
+    /// Measure text as it is drawn.  Lines are written as a single string, which PDF viewers do
+    /// not kern, so this can be wider than [`FontMetrics::measure`].
+    pub(crate) fn measure_unkerned(&self, text: impl AsRef<str>) -> f32 {
+        let sum: f32 = text
+            .as_ref()
+            .chars()
+            .map(|ch| self.widths.get(&ch).unwrap_or(&0.))
+            .sum();
+        sum / 1000.
+    }
+
+    /// Split space-separated words into at most `max_lines` lines, each no wider than `width` at
+    /// the given font size; the words used are removed from `words`.  A word that is too wide on
+    /// its own is broken wherever it overflows.
+    pub(crate) fn wrap_lines(
+        &self,
+        size: printpdf::Pt,
+        words: &mut VecDeque<&str>,
+        width: Mm,
+        max_lines: usize,
+    ) -> Vec<String> {
+        let estimate_fits = |line: &str| Mm::from(size * self.measure(line)) <= width;
+        let fits = |line: &str| {
+            estimate_fits(line) && Mm::from(size * self.measure_unkerned(line)) <= width
+        };
+        let mut lines = Vec::new();
+        while lines.len() < max_lines {
+            let Some(&first) = words.front() else {
+                break;
+            };
+            // Approximate how many words fit, then check the line as it will be drawn, moving
+            // words to the next line until it fits.
+            let mut line = first.to_string();
+            let mut taken = 1;
+            while let Some(word) = words.get(taken) {
+                let start = line.len();
+                line.push(' ');
+                line.push_str(word);
+                if !estimate_fits(&line) {
+                    line.truncate(start);
+                    break;
+                }
+                taken += 1;
+            }
+            while taken > 1 && !fits(&line) {
+                taken -= 1;
+                line.truncate(line.rfind(' ').unwrap_or(line.len()));
+            }
+            if !fits(&line) {
+                let split = first
+                    .char_indices()
+                    .skip(1)
+                    .map(|(index, _)| index)
+                    .take_while(|&index| fits(&first[..index]))
+                    .last()
+                    .unwrap_or_else(|| first.chars().next().map_or(0, char::len_utf8));
+                if split < first.len() {
+                    lines.push(first[..split].to_string());
+                    words[0] = &first[split..];
+                    continue;
+                }
+            }
+            words.drain(..taken);
+            lines.push(line);
+        }
+        lines
+    }
 }
 
 impl From<printpdf::font::BuiltinFont> for &'static FontMetrics {
@@ -90,7 +158,8 @@ impl SizedFont<'_> {
     pub(crate) fn descender(&self) -> printpdf::Pt {
         self.size * self.metrics.descender / 1000.
     }
-    /// Write a line of text (without any line breaks).
+    /// Write a line of text (without any line breaks), rotated counter-clockwise by `rotation`
+    /// degrees around the given point.  Alignment is along the rotated baseline.
     pub(crate) fn write(
         &self,
         layer: &printpdf::PdfLayerReference,
@@ -98,50 +167,82 @@ impl SizedFont<'_> {
         x: Mm,
         y: Mm,
         alignment: &Alignment,
+        rotation: f32,
     ) {
-        let final_x = match alignment {
-            Alignment::Left => x,
-            Alignment::Right => x - self.measure(&text).into(),
-            Alignment::Center => x - (self.measure(&text) / 2.).into(),
+        let offset: Mm = match alignment {
+            Alignment::Left => Mm(0.),
+            Alignment::Right => self.measure(&text).into(),
+            Alignment::Center => (self.measure(&text) / 2.).into(),
         };
-        layer.use_text(text.as_ref(), self.size.0, final_x, y, &self.font);
+        if rotation == 0. {
+            layer.use_text(text.as_ref(), self.size.0, x - offset, y, &self.font);
+            return;
+        }
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        layer.begin_text_section();
+        layer.set_font(&self.font, self.size.0);
+        layer.set_text_matrix(printpdf::TextMatrix::TranslateRotate(
+            (x - offset * cos).into(),
+            (y - offset * sin).into(),
+            rotation,
+        ));
+        layer.write_text(text.as_ref(), &self.font);
+        layer.end_text_section();
     }
-    /// Write some space-separated text over multiple lines.  This currently ignores the bottm bound
-    /// and will happily write text too far down.
+    /// Write some space-separated text over multiple lines.  Lines that would extend past the
+    /// bottom bound are not written; the words that did not fit are returned instead.
     pub(crate) fn write_section<'a>(
         &self,
         layer: &printpdf::PdfLayerReference,
         words: impl Iterator<Item = &'a str>,
         bounds: &Bounds,
         alignment: &Alignment,
-    ) {
+    ) -> VecDeque<&'a str> {
         layer.begin_text_section();
         layer.set_font(&self.font, self.size.0);
         layer.set_line_height(self.size.0);
         // Move the cursor as absolute coordinates.  All moves are relative after.
         layer.set_text_cursor(bounds.left, bounds.top - self.size.into());
 
-        // Split the words into lines by first approximating how many we can fit in a line.
         let mut word_vec: VecDeque<_> = words.collect();
-        let mut line = String::with_capacity(4096);
-
-        while let Some(word) = word_vec.pop_front() {
-            let line_length = line.len();
-            if !line.is_empty() {
-                line.push(' ');
-            }
-            line.push_str(word);
-            if bounds.width() < self.measure(&line).into() {
-                word_vec.push_front(word);
-                self.write_line(layer, &line[..line_length], alignment, bounds.width());
-                line.clear();
-            }
-        }
-        if !line.is_empty() {
+        let max_lines = (bounds.height() / Mm::from(self.size)).floor() as usize;
+        for line in self
+            .metrics
+            .wrap_lines(self.size, &mut word_vec, bounds.width(), max_lines)
+        {
             self.write_line(layer, &line, alignment, bounds.width());
         }
 
         layer.end_text_section();
+        word_vec
+    }
+
+    /// Write some space-separated text over the given number of columns, filling each column
+    /// before moving to the next one.  Text that does not fit in the last column is not written.
+    pub(crate) fn write_columns<'a>(
+        &self,
+        layer: &printpdf::PdfLayerReference,
+        words: impl Iterator<Item = &'a str>,
+        bounds: &Bounds,
+        columns: usize,
+        alignment: &Alignment,
+    ) {
+        let columns = columns.max(1);
+        let gutter = Mm::from(self.size);
+        let column_width = (bounds.width() - gutter * (columns - 1) as f32) / columns as f32;
+        let mut remaining: VecDeque<_> = words.collect();
+        for column in 0..columns {
+            if remaining.is_empty() {
+                break;
+            }
+            let left = bounds.left + (column_width + gutter) * column as f32;
+            let column_bounds = Bounds {
+                left,
+                right: left + column_width,
+                ..*bounds
+            };
+            remaining = self.write_section(layer, remaining.into_iter(), &column_bounds, alignment);
+        }
     }
 
     /// Write a single line of text, for use by `write_section`.  Use `write` for writing a line of
@@ -169,6 +270,8 @@ impl SizedFont<'_> {
 mod test {
     use crate::fonts::metrics::FontMetrics;
     use anyhow::Result;
+    use printpdf::{Mm, Pt};
+    use std::collections::VecDeque;
 
     #[test]
     fn test_measure_courier() -> Result<()> {
@@ -191,4 +294,35 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_wrap_lines_kerning() {
+        let font: &FontMetrics = printpdf::BuiltinFont::Helvetica.into();
+        let size = Pt(10.);
+        // These pairs are kerned closer together, so the kerned width underestimates the line.
+        let text = "AVAVAV Tory WAVY Yoyo AVAWAY To Ty VAT";
+        let width = Mm::from(size * font.measure("AVAVAV Tory WAVY"));
+        let mut words: VecDeque<_> = text.split(' ').collect();
+        let lines = font.wrap_lines(size, &mut words, width, 10);
+        assert!(words.is_empty());
+        assert_eq!(lines.join(" "), text);
+        for line in &lines {
+            assert!(
+                Mm::from(size * font.measure_unkerned(line)) <= width,
+                "{line}"
+            );
+        }
+        assert_eq!(lines[0], "AVAVAV Tory");
+    }
+
+    #[test]
+    fn test_wrap_lines_long_word() {
+        let font: &FontMetrics = printpdf::BuiltinFont::Courier.into();
+        let size = Pt(10.);
+        let width = Mm::from(size * font.measure("abcd"));
+        let mut words: VecDeque<_> = "abcdefghij kl".split(' ').collect();
+        let lines = font.wrap_lines(size, &mut words, width, 3);
+        assert_eq!(lines, ["abcd", "efgh", "ij"]);
+        assert_eq!(words, ["kl"]);
+    }
 }
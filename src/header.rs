@@ -1,23 +1,51 @@
-use anyhow::Result;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use anyhow::{anyhow, Result};
+use base58::{FromBase58, ToBase58};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use chksum_hash_sha2_512 as sha512;
-use std::io::{Read, Write};
+use clap::ValueEnum;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{Read, Write},
+    ops::RangeInclusive,
+};
 
 /// The byte length of the identifier, based on the document and the executable.
 pub const IDENTIFIER_LENGTH: usize = 4;
 
+/// The version of paperback creating documents, as recorded in their metadata.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 /// `Sha512Array` is a alias for an [`u8`] array that is the length of a sha512 output.
 pub(crate) type Sha512Array = [u8; sha512::DIGEST_LENGTH_BYTES];
 
+/// The byte length of the identifier in payload headers when compact headers are requested.
+pub const COMPACT_IDENTIFIER_LENGTH: usize = 2;
+
+/// The default number of hash bytes shown as the Document ID.
+pub const DOCUMENT_ID_LENGTH: usize = 6;
+
+/// The newest format version, written as the first byte of every code.  Readers refuse documents
+/// with a newer version than they know, so that they are reported as such instead of being decoded
+/// into garbage; it must be increased whenever the headers or the data change in a way that older
+/// readers cannot skip over.  Version 0 documents can be decoded while skipping any records the
+/// reader does not know; version 1 documents have records that change how the data is decoded
+/// (see [`MetaHeader::required_format_version`]).
+pub const FORMAT_VERSION: u8 = 1;
+
 pub(crate) type Identifier = [u8; IDENTIFIER_LENGTH];
 
 /// `MetaHeader` is a header that appears in a metadata QR code.
 // This has a fixed "index" of `0xFFFF`
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct MetaHeader {
     /// Identifier for this document.
+    #[serde(with = "hex_bytes")]
     pub identifier: Identifier,
     /// Hash of the original input file.
+    #[serde(with = "hex_bytes")]
     pub hash: Sha512Array,
     /// Number of original input shards.  None of these are ever printed.
     pub original_count: u16,
@@ -25,11 +53,570 @@ pub struct MetaHeader {
     pub recovery_count: u16,
     /// Number of bytes per shard, excluding headers.
     pub shard_bytes: u64,
+    /// Ed25519 signature of `hash`, if the document was signed.
+    #[serde(
+        with = "hex_bytes_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub signature: Option<Signature>,
+    /// Number of identifier bytes stored in each payload header; this is less than
+    /// [`IDENTIFIER_LENGTH`] for compact headers.
+    #[serde(default = "default_identifier_length")]
+    pub payload_identifier_length: usize,
+    /// Version of paperback that created the document; older documents do not record this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    /// Parity codes on each page, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_parity: Option<PageParity>,
+    /// Number of hash bytes shown as the Document ID.
+    #[serde(default = "default_document_id_length")]
+    pub document_id_length: usize,
+    /// How the Document ID is written.
+    #[serde(default)]
+    pub document_id_encoding: IdEncoding,
+    /// How the file data was compressed before it was split into shards; `hash` is of the
+    /// decompressed file.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Whether the padded data was byte interleaved (see [`interleave_bytes`]) before it was split
+    /// into shards.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub byte_interleave: bool,
+    /// Whether the file data is followed by [`END_MARKER`] before its size.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub end_marker: bool,
+    /// Whether the file is a bundle of files (see [`pack_bundle`]), to be unpacked into a
+    /// directory.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bundle: bool,
+    /// How the stored data was encrypted, if it was; `hash` is of the decrypted file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// Whether the data is encrypted, with the [`Encryption`] record on a separate key card (see
+    /// [`Encryption::key_card_text`]) rather than in the metadata.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub key_card: bool,
+    /// Format version of the document's codes (see [`FORMAT_VERSION`]); `None` for documents
+    /// created before codes started with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<u8>,
+}
+
+/// `PageParity` describes the parity codes added to each page, which are computed from the
+/// recovery shards on that page alone; they let a damaged page be repaired before the shards from
+/// all pages are combined.  Parity code `j` of page `p` has the index
+/// `recovery_count + p * parity_count + j`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PageParity {
+    /// Number of recovery shards on each page.
+    pub shards_per_page: u16,
+    /// Number of parity codes on each page.
+    pub parity_count: u16,
+}
+
+/// `Encryption` is what is needed, along with the passphrase, to decrypt the data of an encrypted
+/// document: the key is derived from the passphrase with Argon2id, and the data is encrypted
+/// with XChaCha20-Poly1305 (see [`crate::encryption`]).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Encryption {
+    /// Salt for deriving the key.
+    #[serde(with = "hex_bytes")]
+    pub salt: [u8; 16],
+    /// Nonce for XChaCha20-Poly1305.
+    #[serde(with = "hex_bytes")]
+    pub nonce: [u8; 24],
+    /// The first bytes of the SHA-512 of the key, to tell a wrong passphrase from damaged data.
+    #[serde(with = "hex_bytes")]
+    pub key_check: [u8; 8],
+}
+
+/// The prefix of the code on a key card; codes starting with this are not shards.
+pub const KEY_CARD_PREFIX: &str = "PAPERBACK KEY CARD ";
+
+impl Encryption {
+    /// The text of the code on a key card, which holds the record for the document with the given
+    /// identifier: [`KEY_CARD_PREFIX`], the identifier in hex, a space, and the record in hex.
+    pub fn key_card_text(&self, identifier: &Identifier) -> String {
+        format!(
+            "{KEY_CARD_PREFIX}{} {}{}{}",
+            to_hex(identifier),
+            to_hex(&self.salt),
+            to_hex(&self.nonce),
+            to_hex(&self.key_check)
+        )
+    }
+
+    /// Read the code on a key card, returning the identifier of its document and the record; other
+    /// codes give `None`.
+    pub fn from_key_card(code: &[u8]) -> Option<(Identifier, Encryption)> {
+        let text = std::str::from_utf8(code)
+            .ok()?
+            .strip_prefix(KEY_CARD_PREFIX)?;
+        let (identifier, record) = text.trim_end().split_once(' ')?;
+        if record.len() != 96 || !record.is_ascii() {
+            return None;
+        }
+        Some((
+            parse_hex(identifier).ok()?,
+            Encryption {
+                salt: parse_hex(&record[..32]).ok()?,
+                nonce: parse_hex(&record[32..80]).ok()?,
+                key_check: parse_hex(&record[80..]).ok()?,
+            },
+        ))
+    }
+}
+
+fn default_identifier_length() -> usize {
+    IDENTIFIER_LENGTH
+}
+
+fn default_document_id_length() -> usize {
+    DOCUMENT_ID_LENGTH
+}
+
+/// `Signature` is an Ed25519 signature.
+pub(crate) type Signature = [u8; 64];
+
+/// Tags for the optional records that may follow the fixed fields of a [`MetaHeader`].  Each
+/// record is a one byte tag, a little endian u16 length, and that many bytes of data; unknown
+/// records are skipped, so that new ones can be added without breaking older readers.
+#[repr(u8)]
+enum MetaRecord {
+    Signature = 1,
+    PayloadIdentifierLength = 2,
+    ToolVersion = 3,
+    PageParity = 4,
+    ByteInterleave = 5,
+    EndMarker = 6,
+    DocumentIdLength = 7,
+    DocumentIdEncoding = 8,
+    Compression = 9,
+    Bundle = 10,
+    Encryption = 11,
 }
 
 impl MetaHeader {
-    pub const LENGTH: usize =
-        size_of::<Sha512Array>() + size_of::<u16>() + size_of::<u16>() + size_of::<u64>();
+    pub const LENGTH: usize = size_of::<u8>()
+        + size_of::<u16>()
+        + IDENTIFIER_LENGTH
+        + size_of::<Sha512Array>()
+        + size_of::<u16>()
+        + size_of::<u16>()
+        + size_of::<u64>();
+
+    /// The Document ID printed on each page: the start of the hash.
+    pub fn document_id(&self) -> String {
+        document_id(
+            &self.hash,
+            self.document_id_length,
+            self.document_id_encoding,
+        )
+    }
+
+    /// Whether the given text is this document's Document ID or hex identifier.  A Document ID of
+    /// another length or encoding is also accepted, as it may have been printed with a different
+    /// `--id-length` or `--id-encoding`.
+    pub fn has_id(&self, id: &str) -> bool {
+        let is_hash_prefix = IdEncoding::value_variants().iter().any(|encoding| {
+            encoding.decode(id).is_some_and(|bytes| {
+                bytes.len() >= MIN_DOCUMENT_ID_LENGTH && self.hash.starts_with(&bytes)
+            })
+        });
+        self.document_id() == id
+            || is_hash_prefix
+            || to_hex(&self.identifier).eq_ignore_ascii_case(id)
+    }
+
+    /// Whether the document was created by a version of paperback whose format is compatible with
+    /// this one: the same major version, or for 0.x versions, the same minor version.  Documents
+    /// that do not record a version are assumed to be compatible.
+    pub fn is_compatible_version(&self) -> bool {
+        let Some(ref version) = self.tool_version else {
+            return true;
+        };
+        fn release(version: &str) -> (Option<&str>, Option<&str>) {
+            let mut parts = version.split('.');
+            let major = parts.next();
+            (
+                major,
+                if major == Some("0") {
+                    parts.next()
+                } else {
+                    None
+                },
+            )
+        }
+        release(version) == release(TOOL_VERSION)
+    }
+
+    /// Whether the given payload belongs to this document.
+    pub fn matches(&self, payload: &PayloadHeader) -> bool {
+        let length = payload.identifier_length;
+        self.identifier[..length] == payload.identifier[..length]
+            && self.format_version == payload.format_version
+    }
+
+    /// The lowest format version that readers must know to decode this document: a reader that
+    /// skipped the records for byte interleaving, compression, bundles or encryption would restore
+    /// the wrong data, so documents with them are version 1.
+    pub fn required_format_version(&self) -> u8 {
+        if self.byte_interleave
+            || self.compression != Compression::None
+            || self.bundle
+            || self.encryption.is_some()
+            || self.key_card
+        {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Explain why reed-solomon-simd rejected the given shard parameters, so that the user knows which
+/// option to adjust rather than seeing only the library's error.
+pub(crate) fn shard_parameter_error(
+    error: reed_solomon_simd::Error,
+    original_count: usize,
+    recovery_count: usize,
+    shard_bytes: usize,
+) -> anyhow::Error {
+    let reason = match error {
+        reed_solomon_simd::Error::UnsupportedShardCount { .. } => {
+            "both counts must be non-zero, and the original count plus the recovery count \
+             (rounded up to a power of two) must not exceed 65536; try a larger module length or a \
+             lower recovery factor"
+        }
+        reed_solomon_simd::Error::InvalidShardSize { .. } => {
+            "the shard size must be a non-zero multiple of 64 bytes"
+        }
+        _ => "the library rejected these parameters",
+    };
+    anyhow!(
+        "invalid reed-solomon parameters ({original_count} original shards, {recovery_count} \
+         recovery shards, {shard_bytes} bytes per shard): {error}; {reason}"
+    )
+}
+
+/// A short checksum of the payload codes on one page, so that an auditor can tell whether any of
+/// them were swapped: the first four bytes, in hex, of the SHA-512 of the codes' contents (headers
+/// included), concatenated in order of their index.  Metadata codes are ignored.
+pub(crate) fn page_checksum<'a>(codes: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = sha512::new();
+    for code in codes
+        .into_iter()
+        .filter(|code| code.len() >= size_of::<u16>() && !is_meta(code))
+        .sorted_by_key(|code| LittleEndian::read_u16(code))
+    {
+        hasher.update(code);
+    }
+    to_hex(&hasher.digest().into_inner()[..4])
+}
+
+/// The fewest hash bytes accepted as a Document ID.
+pub const MIN_DOCUMENT_ID_LENGTH: usize = 4;
+
+/// The Document ID for the given hash: its first `length` bytes, in the given encoding.
+pub fn document_id(hash: &Sha512Array, length: usize, encoding: IdEncoding) -> String {
+    encoding.encode(&hash[..length])
+}
+
+/// How the Document ID is written.  The order of the variants is their value in the metadata.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IdEncoding {
+    /// Base58, the shortest.
+    #[default]
+    Base58,
+    /// Crockford's base32, which has no letters that are easily confused, so it is easier to read
+    /// aloud.
+    Base32,
+    /// Lower case hex.
+    Hex,
+}
+
+/// The digits of Crockford's base32, which leaves out I, L, O and U.
+const CROCKFORD_DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+impl IdEncoding {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            IdEncoding::Base58 => bytes.to_base58(),
+            IdEncoding::Base32 => {
+                // Five bits per digit, most significant first, with the last digit padded with
+                // zero bits.
+                let bit_count = bytes.len() * 8;
+                (0..bit_count.div_ceil(5))
+                    .map(|digit| {
+                        let value = (digit * 5..digit * 5 + 5).fold(0, |value, bit| {
+                            let set = bit < bit_count && bytes[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                            value << 1 | usize::from(set)
+                        });
+                        CROCKFORD_DIGITS[value] as char
+                    })
+                    .collect()
+            }
+            IdEncoding::Hex => to_hex(bytes),
+        }
+    }
+
+    /// Decode an ID written in this encoding; any bits left over after the last whole byte are
+    /// dropped.  Crockford's base32 is read without regard to case, with O read as 0 and I and L
+    /// as 1, and hyphens are ignored.
+    pub fn decode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            IdEncoding::Base58 => text.from_base58().ok(),
+            IdEncoding::Base32 => {
+                let mut bytes = Vec::new();
+                let (mut value, mut bit_count) = (0u32, 0);
+                for c in text.chars().filter(|&c| c != '-') {
+                    let c = match c.to_ascii_uppercase() {
+                        'O' => '0',
+                        'I' | 'L' => '1',
+                        c => c,
+                    };
+                    let digit = CROCKFORD_DIGITS.iter().position(|&d| d as char == c)?;
+                    value = value << 5 | digit as u32;
+                    bit_count += 5;
+                    if bit_count >= 8 {
+                        bit_count -= 8;
+                        bytes.push((value >> bit_count) as u8);
+                        value &= (1 << bit_count) - 1;
+                    }
+                }
+                Some(bytes)
+            }
+            IdEncoding::Hex => {
+                if !text.len().is_multiple_of(2) || !text.is_ascii() {
+                    return None;
+                }
+                (0..text.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+                    .collect()
+            }
+        }
+    }
+}
+
+/// How the file data is compressed before it is split into shards.  The order of the variants is
+/// their value in the metadata.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// The file is stored as it is.
+    #[default]
+    None,
+    /// Gzip, which can be decompressed by almost anything.
+    Gzip,
+    /// Zstandard, which usually compresses better than gzip.
+    Zstd,
+}
+
+impl Compression {
+    /// The levels that can be chosen for this compression, from the fastest to the one giving the
+    /// smallest output.
+    pub fn levels(self) -> Option<RangeInclusive<u32>> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(0..=9),
+            Compression::Zstd => Some(1..=22),
+        }
+    }
+
+    /// Compress the data at the given level, which must be one of [`Compression::levels`].  Without
+    /// a level, gzip uses its best (9), and zstd the best that is still reasonably fast (19).
+    pub fn compress(self, data: Vec<u8>, level: Option<u32>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Gzip => {
+                let level = level.map_or(flate2::Compression::best(), flate2::Compression::new);
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(&data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(
+                data.as_slice(),
+                level.map_or(19, |level| level as i32),
+            )?),
+        }
+    }
+
+    pub fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Compression::Zstd => Ok(zstd::decode_all(data.as_slice())?),
+        }
+    }
+}
+
+/// Pack several files into one, to be encoded as a single document: for each file, the length of
+/// its name as a u16, the name in UTF-8, the length of its contents as a u64, and the contents.
+pub(crate) fn pack_bundle(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut bundle = Vec::new();
+    for (index, (name, contents)) in files.iter().enumerate() {
+        check_bundle_name(name)?;
+        if files[..index].iter().any(|(other, _)| other == name) {
+            Err(anyhow!("more than one file is named {name:?}"))?;
+        }
+        bundle.write_u16::<LittleEndian>(name.len().try_into()?)?;
+        bundle.write_all(name.as_bytes())?;
+        bundle.write_u64::<LittleEndian>(contents.len() as u64)?;
+        bundle.write_all(contents)?;
+    }
+    Ok(bundle)
+}
+
+/// Undo [`pack_bundle`].  The names are checked to be plain file names, so that unpacking the
+/// bundle into a directory cannot write anywhere else.
+pub(crate) fn unpack_bundle(mut bundle: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    while !bundle.is_empty() {
+        let mut name = vec![0; bundle.read_u16::<LittleEndian>()? as usize];
+        bundle.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| anyhow!("file name is not UTF-8"))?;
+        check_bundle_name(&name)?;
+        let length = bundle.read_u64::<LittleEndian>()?;
+        if length > bundle.len() as u64 {
+            Err(anyhow!("{name:?} is longer than the rest of the bundle"))?;
+        }
+        let (contents, rest) = bundle.split_at(length as usize);
+        files.push((name, contents.to_vec()));
+        bundle = rest;
+    }
+    Ok(files)
+}
+
+/// Check that a name in a bundle is a plain file name, without any directories.
+fn check_bundle_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        Err(anyhow!("{name:?} is not a plain file name"))?;
+    }
+    Ok(())
+}
+
+/// `END_MARKER` follows the file data in documents created with `--end-marker`, so that the end
+/// of the data can be found even if its recorded size is damaged.
+pub const END_MARKER: &[u8] = b"<PAPERBACK EOF/>";
+
+/// Spread the bytes of `buffer` (which holds `shard_count` shards) across the shards, so that
+/// consecutive bytes end up in different shards: byte `i` moves to shard `i % shard_count`, at
+/// offset `i / shard_count`.
+pub(crate) fn interleave_bytes(buffer: &[u8], shard_count: usize) -> Vec<u8> {
+    let shard_bytes = buffer.len() / shard_count;
+    let mut result = vec![0; buffer.len()];
+    for (index, byte) in buffer.iter().enumerate() {
+        result[(index % shard_count) * shard_bytes + index / shard_count] = *byte;
+    }
+    result
+}
+
+/// Undo [`interleave_bytes`].
+pub(crate) fn deinterleave_bytes(buffer: &[u8], shard_count: usize) -> Vec<u8> {
+    let shard_bytes = buffer.len() / shard_count;
+    (0..buffer.len())
+        .map(|index| buffer[(index % shard_count) * shard_bytes + index / shard_count])
+        .collect()
+}
+
+/// Format bytes as a lower case hex string.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parse a hex string into a fixed number of bytes.
+pub(crate) fn parse_hex<const N: usize>(text: &str) -> Result<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        Err(anyhow!("expected {} hex digits", N * 2))?;
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+/// `hex_bytes` (de)serializes fixed size byte arrays as lower case hex strings.
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        super::parse_hex(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// `hex_bytes_option` is [`hex_bytes`] for optional values, which are omitted when missing.
+mod hex_bytes_option {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &Option<[u8; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&super::to_hex(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Option<[u8; N]>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| super::parse_hex(&text).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl fmt::Display for MetaHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Document ID {} (identifier {}): {} shards of {} bytes, any {} of which are needed",
+            self.document_id(),
+            to_hex(&self.identifier),
+            self.recovery_count,
+            self.shard_bytes,
+            self.original_count,
+        )?;
+        if let Some(ref version) = self.tool_version {
+            write!(f, " (created by paperback {version})")?;
+        }
+        if self.signature.is_some() {
+            write!(f, " (signed)")?;
+        }
+        match self.compression {
+            Compression::None => {}
+            Compression::Gzip => write!(f, " (gzip compressed)")?,
+            Compression::Zstd => write!(f, " (zstd compressed)")?,
+        }
+        if self.bundle {
+            write!(f, " (bundle of files)")?;
+        }
+        if self.key_card {
+            write!(f, " (encrypted, with a separate key card)")?;
+        } else if self.encryption.is_some() {
+            write!(f, " (encrypted)")?;
+        }
+        Ok(())
+    }
 }
 
 /// `PayloadHeader` is a header that appears in a payload QR code.
@@ -37,24 +624,74 @@ impl MetaHeader {
 pub struct PayloadHeader {
     /// Index for a recovery shard; can be between 0 and 65534 inclusive.
     pub index: u16,
-    /// Identifier for this document.
+    /// Identifier for this document; only the first `identifier_length` bytes are stored, and
+    /// the rest are zero.
     pub identifier: Identifier,
+    pub identifier_length: usize,
+    /// Format version of the code (see [`FORMAT_VERSION`]); `None` for documents created before
+    /// codes started with one.
+    pub format_version: Option<u8>,
 }
 
 impl PayloadHeader {
-    pub const LENGTH: usize = size_of::<u16>() + IDENTIFIER_LENGTH;
+    /// The length of a payload header storing the given number of identifier bytes.
+    pub const fn length(identifier_length: usize) -> usize {
+        size_of::<u8>() + size_of::<u16>() + identifier_length
+    }
 }
 
 /// Header that gets written to one QR code.
+// Headers are only held briefly while a code is read or written, so the size of the metadata
+// variant does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Header {
     Meta(MetaHeader),
     Payload(PayloadHeader),
 }
 
+/// Whether the given code contents are a metadata header, which can be read without knowing the
+/// document's payload header layout.  Metadata codes start with the format version and then the
+/// index `0xFFFF`, or with the index alone in documents created before there was a version.
+pub fn is_meta(bytes: &[u8]) -> bool {
+    let meta_index = u16::MAX.to_le_bytes();
+    bytes.starts_with(&meta_index) || bytes.get(1..3) == Some(&meta_index)
+}
+
+/// The error for a code in a format version this build does not know.
+fn newer_format_error(version: u8) -> anyhow::Error {
+    anyhow!(
+        "document created by a newer paperback (format version {version}, this build reads up \
+         to {FORMAT_VERSION}); restore it with a newer version"
+    )
+}
+
 impl Header {
-    pub fn read_from(reader: &mut impl Read) -> Result<Self> {
-        let index = reader.read_u16::<LittleEndian>()?;
+    /// Read a header; `payload_identifier_length` is the number of identifier bytes in payload
+    /// headers, and `versioned` whether they start with a format version, as given by the
+    /// document's metadata.  Metadata headers are read whatever their version, as their layout
+    /// tells them apart.  Payload headers are read with whatever version byte they have, for the
+    /// caller to compare with the document's (see [`MetaHeader::matches`]); codes that paperback
+    /// did not create can look like payloads.
+    pub fn read_from(
+        reader: &mut impl Read,
+        payload_identifier_length: usize,
+        versioned: bool,
+    ) -> Result<Self> {
+        // Metadata headers are recognised by their index, which follows the version byte, or comes
+        // first in documents from before there was one.
+        let mut start = [0; 3];
+        reader.read_exact(&mut start[..2])?;
+        let (index, format_version) = if start[..2] == u16::MAX.to_le_bytes() {
+            (u16::MAX, None)
+        } else {
+            reader.read_exact(&mut start[2..])?;
+            match LittleEndian::read_u16(&start[1..]) {
+                u16::MAX => (u16::MAX, Some(start[0])),
+                index if versioned => (index, Some(start[0])),
+                _ => (LittleEndian::read_u16(&start), None),
+            }
+        };
         if index == u16::MAX {
             // This is a metadata block
             let mut result = MetaHeader {
@@ -63,37 +700,582 @@ impl Header {
                 original_count: 0,
                 recovery_count: 0,
                 shard_bytes: 0,
+                signature: None,
+                payload_identifier_length: IDENTIFIER_LENGTH,
+                tool_version: None,
+                page_parity: None,
+                document_id_length: DOCUMENT_ID_LENGTH,
+                document_id_encoding: IdEncoding::Base58,
+                compression: Compression::None,
+                byte_interleave: false,
+                end_marker: false,
+                bundle: false,
+                encryption: None,
+                key_card: false,
+                format_version,
             };
             reader.read_exact(result.identifier.as_mut_slice())?;
             reader.read_exact(result.hash.as_mut_slice())?;
             result.original_count = reader.read_u16::<LittleEndian>()?;
             result.recovery_count = reader.read_u16::<LittleEndian>()?;
             result.shard_bytes = reader.read_u64::<LittleEndian>()?;
+            // Only now that the code has the layout of a metadata header is its version trusted.
+            if let Some(version) = format_version.filter(|&version| version > FORMAT_VERSION) {
+                Err(newer_format_error(version))?;
+            }
+            // Older documents end here; newer ones may have optional records.
+            let mut records = Vec::new();
+            reader.read_to_end(&mut records)?;
+            let mut records = records.as_slice();
+            while !records.is_empty() {
+                let tag = records.read_u8()?;
+                let mut data = vec![0; records.read_u16::<LittleEndian>()? as usize];
+                records.read_exact(&mut data)?;
+                if tag == MetaRecord::Signature as u8 {
+                    result.signature = Some(
+                        data.try_into()
+                            .map_err(|_| anyhow!("invalid signature length"))?,
+                    );
+                } else if tag == MetaRecord::PayloadIdentifierLength as u8 {
+                    let length = *data.first().ok_or(anyhow!("invalid identifier length"))?;
+                    if length as usize > IDENTIFIER_LENGTH {
+                        Err(anyhow!("invalid identifier length {length}"))?;
+                    }
+                    result.payload_identifier_length = length as usize;
+                } else if tag == MetaRecord::ToolVersion as u8 {
+                    result.tool_version = Some(String::from_utf8_lossy(&data).into_owned());
+                } else if tag == MetaRecord::PageParity as u8 {
+                    let mut data = data.as_slice();
+                    result.page_parity = Some(PageParity {
+                        shards_per_page: data.read_u16::<LittleEndian>()?,
+                        parity_count: data.read_u16::<LittleEndian>()?,
+                    });
+                } else if tag == MetaRecord::ByteInterleave as u8 {
+                    result.byte_interleave = true;
+                } else if tag == MetaRecord::EndMarker as u8 {
+                    result.end_marker = true;
+                } else if tag == MetaRecord::DocumentIdLength as u8 {
+                    let length = *data.first().ok_or(anyhow!("invalid document ID length"))?;
+                    if !(MIN_DOCUMENT_ID_LENGTH..=size_of::<Sha512Array>())
+                        .contains(&(length as usize))
+                    {
+                        Err(anyhow!("invalid document ID length {length}"))?;
+                    }
+                    result.document_id_length = length as usize;
+                } else if tag == MetaRecord::DocumentIdEncoding as u8 {
+                    let value = *data
+                        .first()
+                        .ok_or(anyhow!("invalid document ID encoding"))?;
+                    result.document_id_encoding = *IdEncoding::value_variants()
+                        .get(value as usize)
+                        .ok_or(anyhow!("invalid document ID encoding {value}"))?;
+                } else if tag == MetaRecord::Bundle as u8 {
+                    result.bundle = true;
+                } else if tag == MetaRecord::Encryption as u8 && data.is_empty() {
+                    result.key_card = true;
+                } else if tag == MetaRecord::Encryption as u8 {
+                    let mut data = data.as_slice();
+                    let mut encryption = Encryption {
+                        salt: [0; 16],
+                        nonce: [0; 24],
+                        key_check: [0; 8],
+                    };
+                    data.read_exact(&mut encryption.salt)?;
+                    data.read_exact(&mut encryption.nonce)?;
+                    data.read_exact(&mut encryption.key_check)?;
+                    result.encryption = Some(encryption);
+                } else if tag == MetaRecord::Compression as u8 {
+                    let value = *data.first().ok_or(anyhow!("invalid compression"))?;
+                    result.compression = *Compression::value_variants()
+                        .get(value as usize)
+                        .ok_or(anyhow!("unknown compression {value}"))?;
+                }
+            }
 
             Ok(Header::Meta(result))
         } else {
             let mut identifier: Identifier = [0; IDENTIFIER_LENGTH];
-            reader.read_exact(&mut identifier)?;
+            if format_version.is_none() {
+                // The first identifier byte was read along with the index.
+                let Some(first) = identifier[..payload_identifier_length].first_mut() else {
+                    Err(anyhow!(
+                        "invalid identifier length {payload_identifier_length}"
+                    ))?
+                };
+                *first = start[2];
+                reader.read_exact(&mut identifier[1..payload_identifier_length])?;
+            } else {
+                reader.read_exact(&mut identifier[..payload_identifier_length])?;
+            }
 
-            Ok(Header::Payload(PayloadHeader { index, identifier }))
+            Ok(Header::Payload(PayloadHeader {
+                index,
+                identifier,
+                identifier_length: payload_identifier_length,
+                format_version,
+            }))
         }
     }
 
     pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
         match self {
             Header::Meta(m) => {
+                if let Some(version) = m.format_version {
+                    writer.write_u8(version)?;
+                }
                 writer.write_u16::<LittleEndian>(u16::MAX)?;
                 writer.write_all(m.identifier.as_slice())?;
                 writer.write_all(m.hash.as_slice())?;
                 writer.write_u16::<LittleEndian>(m.original_count)?;
                 writer.write_u16::<LittleEndian>(m.recovery_count)?;
                 writer.write_u64::<LittleEndian>(m.shard_bytes)?;
+                if let Some(signature) = m.signature {
+                    writer.write_u8(MetaRecord::Signature as u8)?;
+                    writer.write_u16::<LittleEndian>(signature.len() as u16)?;
+                    writer.write_all(&signature)?;
+                }
+                // Only written for compact headers, so that other documents stay readable by
+                // older versions.
+                if m.payload_identifier_length != IDENTIFIER_LENGTH {
+                    writer.write_u8(MetaRecord::PayloadIdentifierLength as u8)?;
+                    writer.write_u16::<LittleEndian>(1)?;
+                    writer.write_u8(m.payload_identifier_length as u8)?;
+                }
+                if let Some(ref version) = m.tool_version {
+                    writer.write_u8(MetaRecord::ToolVersion as u8)?;
+                    writer.write_u16::<LittleEndian>(version.len().try_into()?)?;
+                    writer.write_all(version.as_bytes())?;
+                }
+                if let Some(parity) = m.page_parity {
+                    writer.write_u8(MetaRecord::PageParity as u8)?;
+                    writer.write_u16::<LittleEndian>(2 * size_of::<u16>() as u16)?;
+                    writer.write_u16::<LittleEndian>(parity.shards_per_page)?;
+                    writer.write_u16::<LittleEndian>(parity.parity_count)?;
+                }
+                if m.byte_interleave {
+                    writer.write_u8(MetaRecord::ByteInterleave as u8)?;
+                    writer.write_u16::<LittleEndian>(0)?;
+                }
+                if m.end_marker {
+                    writer.write_u8(MetaRecord::EndMarker as u8)?;
+                    writer.write_u16::<LittleEndian>(0)?;
+                }
+                if m.document_id_length != DOCUMENT_ID_LENGTH {
+                    writer.write_u8(MetaRecord::DocumentIdLength as u8)?;
+                    writer.write_u16::<LittleEndian>(1)?;
+                    writer.write_u8(m.document_id_length.try_into()?)?;
+                }
+                if m.document_id_encoding != IdEncoding::Base58 {
+                    writer.write_u8(MetaRecord::DocumentIdEncoding as u8)?;
+                    writer.write_u16::<LittleEndian>(1)?;
+                    writer.write_u8(m.document_id_encoding as u8)?;
+                }
+                if m.bundle {
+                    writer.write_u8(MetaRecord::Bundle as u8)?;
+                    writer.write_u16::<LittleEndian>(0)?;
+                }
+                if m.compression != Compression::None {
+                    writer.write_u8(MetaRecord::Compression as u8)?;
+                    writer.write_u16::<LittleEndian>(1)?;
+                    writer.write_u8(m.compression as u8)?;
+                }
+                if m.key_card {
+                    writer.write_u8(MetaRecord::Encryption as u8)?;
+                    writer.write_u16::<LittleEndian>(0)?;
+                } else if let Some(encryption) = m.encryption {
+                    writer.write_u8(MetaRecord::Encryption as u8)?;
+                    writer.write_u16::<LittleEndian>(48)?;
+                    writer.write_all(&encryption.salt)?;
+                    writer.write_all(&encryption.nonce)?;
+                    writer.write_all(&encryption.key_check)?;
+                }
             }
             Header::Payload(p) => {
+                if let Some(version) = p.format_version {
+                    writer.write_u8(version)?;
+                }
                 writer.write_u16::<LittleEndian>(p.index)?;
-                writer.write_all(&p.identifier)?;
+                writer.write_all(&p.identifier[..p.identifier_length])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The prefix of the decoder specification QR code; codes starting with this are not shards.
+pub const DECODER_SPEC_PREFIX: &str = "PAPERBACK DECODER SPEC ";
+
+/// A description of the format, precise enough to write a decoder from.  This is printed (as text
+/// and as a QR code) on the cover page when requested; it does not describe any one document.
+pub const DECODER_SPEC: &str = "PAPERBACK DECODER SPEC 5
+Codes: QR, byte mode. All integers are little endian.
+Each code: u8 format version (0, or 1 if tag 5, 9, 10 or 11
+ is present; older documents have none, and their metadata
+ starts with the index), u16 index, then document identifier
+ (4 bytes). Codes with another version are not of this
+ document.
+Index 0xFFFF is metadata: SHA-512 of the file (64 bytes),
+ u16 original shard count K, u16 recovery shard count N,
+ u64 shard size S, then optional records: u8 tag, u16 length,
+ data. Tag 1: Ed25519 signature (64 bytes) of the 64 byte
+ SHA-512 above; only the hash is signed, not the records.
+ Tag 2: u8 identifier length in other codes (else 4).
+ Tag 3: version of paperback that created it (text).
+ Tag 4: u16 shards per page R, u16 parity codes per page P;
+ index N+p*P+j is parity j of page p, from the page's R
+ recovery shards as originals (same Reed-Solomon code).
+ Tag 5 (no data): byte i of the padded file data (below)
+ is moved to offset (i mod K)*S + (i div K) before splitting.
+ Tag 6 (no data): the file data is followed by the 16 bytes
+ \"<PAPERBACK EOF/>\" before its size (as a check of the size).
+ Tag 7: u8 number of hash bytes in the printed ID (else 6).
+ Tag 8: u8 printed ID encoding: 1 Crockford base32, 2 hex
+ (else base58).
+ Tag 9: u8 1: the file data (below) is gzip compressed, 2:
+ zstd compressed; the SHA-512 is of the decompressed file.
+ Tag 10 (no data): the file is a bundle of files, each a u16
+ name length, UTF-8 name, u64 size, then its contents.
+ Tag 11: 16 byte salt, 24 byte nonce, 8 byte key check: the
+ file data (below) is encrypted with XChaCha20-Poly1305
+ (16 byte tag appended), with the 32 byte key derived from a
+ passphrase by Argon2id (v19, 19 MiB, 2 passes, 1 lane); the
+ check is the start of the key's SHA-512. Decrypt before
+ decompressing; the SHA-512 is of the decrypted file. If
+ empty, the 48 bytes are on a key card: a code with text
+ \"PAPERBACK KEY CARD \", identifier hex, space, 48 bytes hex.
+Other indices are recovery shard <index> (S bytes follow).
+File data is followed by its u64 size, zero padded to K*S,
+ and split into K original shards of S bytes.
+Recovery shards: reed-solomon-simd 2.x (Leopard-RS, GF(2^16)).
+Any K distinct recovery shards restore the original shards.
+";
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{
+        deinterleave_bytes, document_id, interleave_bytes, pack_bundle, unpack_bundle, Compression,
+        Encryption, Header, IdEncoding, MetaHeader, PayloadHeader, COMPACT_IDENTIFIER_LENGTH,
+        DOCUMENT_ID_LENGTH, FORMAT_VERSION, IDENTIFIER_LENGTH,
+    };
+    use anyhow::Result;
+
+    /// A metadata header for tests to override the fields they exercise in.
+    pub(crate) fn test_header() -> MetaHeader {
+        MetaHeader {
+            identifier: [0x01, 0x23, 0xab, 0xff],
+            hash: [0x5a; 64],
+            original_count: 3,
+            recovery_count: 9,
+            shard_bytes: 128,
+            signature: None,
+            payload_identifier_length: IDENTIFIER_LENGTH,
+            tool_version: None,
+            page_parity: None,
+            document_id_length: DOCUMENT_ID_LENGTH,
+            document_id_encoding: IdEncoding::Base58,
+            compression: Compression::None,
+            byte_interleave: false,
+            end_marker: false,
+            bundle: false,
+            encryption: None,
+            key_card: false,
+            format_version: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_interleave_bytes() {
+        let buffer = (0..12).collect::<Vec<u8>>();
+        let interleaved = interleave_bytes(&buffer, 3);
+        assert_eq!(interleaved, [0, 3, 6, 9, 1, 4, 7, 10, 2, 5, 8, 11]);
+        assert_eq!(deinterleave_bytes(&interleaved, 3), buffer);
+    }
+
+    #[test]
+    fn test_meta_header_json() -> Result<()> {
+        let meta = test_header();
+        let json = serde_json::to_value(&meta)?;
+        assert_eq!(json["identifier"], "0123abff");
+        assert_eq!(json["hash"], "5a".repeat(64));
+        assert_eq!(serde_json::from_value::<MetaHeader>(json)?, meta);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_payload_header() -> Result<()> {
+        let mut meta = MetaHeader {
+            payload_identifier_length: COMPACT_IDENTIFIER_LENGTH,
+            tool_version: Some("1.2.3".to_string()),
+            ..test_header()
+        };
+        let mut buf = Vec::new();
+        Header::Meta(meta).write_to(&mut buf)?;
+        let Header::Meta(read_meta) =
+            Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert_eq!(
+            read_meta.payload_identifier_length,
+            COMPACT_IDENTIFIER_LENGTH
+        );
+        assert_eq!(read_meta.tool_version.as_deref(), Some("1.2.3"));
+        assert!(!read_meta.is_compatible_version());
+        meta = read_meta;
+
+        let mut buf = Vec::new();
+        Header::Payload(PayloadHeader {
+            index: 7,
+            identifier: meta.identifier,
+            identifier_length: meta.payload_identifier_length,
+            format_version: meta.format_version,
+        })
+        .write_to(&mut buf)?;
+        assert_eq!(buf.len(), PayloadHeader::length(COMPACT_IDENTIFIER_LENGTH));
+        let Header::Payload(payload) = Header::read_from(
+            &mut buf.as_slice(),
+            meta.payload_identifier_length,
+            meta.format_version.is_some(),
+        )?
+        else {
+            panic!("expected payload");
+        };
+        assert_eq!(payload.index, 7);
+        assert!(meta.matches(&payload));
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_id_length() -> Result<()> {
+        let meta = MetaHeader {
+            document_id_length: 10,
+            ..test_header()
+        };
+        let mut buf = Vec::new();
+        Header::Meta(meta).write_to(&mut buf)?;
+        let Header::Meta(meta) = Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert_eq!(meta.document_id_length, 10);
+        let id = |hash, length| document_id(hash, length, IdEncoding::Base58);
+        assert_eq!(meta.document_id(), id(&meta.hash, 10));
+        // IDs printed with other lengths still match, but not ones too short to be distinctive.
+        assert!(meta.has_id(&id(&meta.hash, DOCUMENT_ID_LENGTH)));
+        assert!(!meta.has_id(&id(&meta.hash, 2)));
+        assert!(!meta.has_id(&id(&[0x5b; 64], DOCUMENT_ID_LENGTH)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_id_encoding() -> Result<()> {
+        let hash = [0x5a; 64];
+        assert_eq!(document_id(&hash, 5, IdEncoding::Base32), "B9D5MPJT");
+        assert_eq!(document_id(&hash, 4, IdEncoding::Hex), "5a5a5a5a");
+        // Lower case, and letters that look like digits, are read as the digits.
+        assert_eq!(IdEncoding::Base32.decode("b9d5-mpjt"), Some(vec![0x5a; 5]));
+        assert_eq!(
+            IdEncoding::Base32.decode("0O1IL"),
+            IdEncoding::Base32.decode("00111")
+        );
+        assert_eq!(IdEncoding::Base32.decode("B9U"), None);
+
+        let meta = MetaHeader {
+            hash,
+            document_id_encoding: IdEncoding::Base32,
+            ..test_header()
+        };
+        let mut buf = Vec::new();
+        Header::Meta(meta).write_to(&mut buf)?;
+        let Header::Meta(meta) = Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert_eq!(meta.document_id_encoding, IdEncoding::Base32);
+        assert_eq!(meta.document_id(), "B9D5MPJTB8");
+        // An ID printed in another encoding still matches.
+        assert!(meta.has_id(&document_id(&hash, DOCUMENT_ID_LENGTH, IdEncoding::Base58)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression() -> Result<()> {
+        let data = b"paperback ".repeat(100);
+        let compressed = Compression::Gzip.compress(data.clone(), None)?;
+        assert!(compressed.len() < data.len());
+        assert_eq!(Compression::Gzip.decompress(compressed)?, data);
+        assert!(Compression::Gzip.decompress(data.clone()).is_err());
+        let compressed = Compression::Zstd.compress(data.clone(), None)?;
+        assert!(compressed.len() < data.len());
+        assert_eq!(Compression::Zstd.decompress(compressed)?, data);
+        assert!(Compression::Zstd.decompress(data.clone()).is_err());
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            for level in compression.levels().unwrap() {
+                let compressed = compression.compress(data.clone(), Some(level))?;
+                assert_eq!(compression.decompress(compressed)?, data);
             }
         }
+        assert_eq!(Compression::None.levels(), None);
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let meta = MetaHeader {
+                compression,
+                ..test_header()
+            };
+            let mut buf = Vec::new();
+            Header::Meta(meta).write_to(&mut buf)?;
+            let Header::Meta(meta) =
+                Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+            else {
+                panic!("expected metadata");
+            };
+            assert_eq!(meta.compression, compression);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_encryption_record() -> Result<()> {
+        let encryption = Encryption {
+            salt: [1; 16],
+            nonce: [2; 24],
+            key_check: [3; 8],
+        };
+        let meta = MetaHeader {
+            encryption: Some(encryption),
+            ..test_header()
+        };
+        assert_eq!(meta.required_format_version(), FORMAT_VERSION);
+        let mut buf = Vec::new();
+        Header::Meta(meta).write_to(&mut buf)?;
+        let Header::Meta(meta) = Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert_eq!(meta.encryption, Some(encryption));
+
+        // With a key card, the metadata only says that there is one.
+        let meta = MetaHeader {
+            key_card: true,
+            ..test_header()
+        };
+        assert_eq!(meta.required_format_version(), FORMAT_VERSION);
+        let mut buf = Vec::new();
+        Header::Meta(meta).write_to(&mut buf)?;
+        let Header::Meta(meta) = Header::read_from(&mut buf.as_slice(), IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert!(meta.key_card);
+        assert_eq!(meta.encryption, None);
+
+        let text = encryption.key_card_text(&[0xab; IDENTIFIER_LENGTH]);
+        assert!(text.starts_with("PAPERBACK KEY CARD abababab "));
+        assert_eq!(
+            Encryption::from_key_card(text.as_bytes()),
+            Some(([0xab; IDENTIFIER_LENGTH], encryption))
+        );
+        assert_eq!(Encryption::from_key_card(&text.as_bytes()[1..]), None);
+        assert_eq!(
+            Encryption::from_key_card(&text.as_bytes()[..text.len() - 1]),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle() -> Result<()> {
+        let files = vec![
+            ("key.txt".to_string(), b"secret".to_vec()),
+            ("empty".to_string(), Vec::new()),
+        ];
+        let bundle = pack_bundle(&files)?;
+        assert_eq!(unpack_bundle(&bundle)?, files);
+        assert!(unpack_bundle(&bundle[..bundle.len() - 1]).is_err());
+        // Names that could write outside the output directory are rejected.
+        for name in ["../key.txt", "dir/key.txt", "..", ""] {
+            assert!(pack_bundle(&[(name.to_string(), Vec::new())]).is_err());
+        }
+        let mut bundle = pack_bundle(&[("a.txt".to_string(), Vec::new())])?;
+        bundle[2..7].copy_from_slice(b"../ab");
+        assert!(unpack_bundle(&bundle).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version() -> Result<()> {
+        let meta = test_header();
+        assert_eq!(meta.required_format_version(), 0);
+        let mut meta_bytes = Vec::new();
+        Header::Meta(meta).write_to(&mut meta_bytes)?;
+        let mut payload_bytes = Vec::new();
+        Header::Payload(PayloadHeader {
+            index: 0x1234,
+            identifier: [0x01, 0x23, 0xab, 0xff],
+            identifier_length: IDENTIFIER_LENGTH,
+            format_version: Some(0),
+        })
+        .write_to(&mut payload_bytes)?;
+        assert_eq!(meta_bytes[0], 0);
+        assert_eq!(payload_bytes, [0, 0x34, 0x12, 0x01, 0x23, 0xab, 0xff]);
+        assert!(super::is_meta(&meta_bytes));
+        assert!(!super::is_meta(&payload_bytes));
+
+        // Documents whose data older readers would decode wrongly need a newer version.
+        for meta in [
+            MetaHeader {
+                byte_interleave: true,
+                ..test_header()
+            },
+            MetaHeader {
+                compression: Compression::Gzip,
+                ..test_header()
+            },
+            MetaHeader {
+                bundle: true,
+                ..test_header()
+            },
+        ] {
+            assert_eq!(meta.required_format_version(), FORMAT_VERSION);
+        }
+
+        // Documents from before the version byte are still read.
+        let legacy_meta = &meta_bytes[1..];
+        assert!(super::is_meta(legacy_meta));
+        let Header::Meta(read_meta) =
+            Header::read_from(&mut &legacy_meta[..], IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected metadata");
+        };
+        assert_eq!(read_meta.format_version, None);
+        assert_eq!(read_meta.shard_bytes, 128);
+        let Header::Payload(payload) =
+            Header::read_from(&mut &payload_bytes[1..], IDENTIFIER_LENGTH, false)?
+        else {
+            panic!("expected a payload");
+        };
+        assert_eq!(payload.index, 0x1234);
+        assert_eq!(payload.identifier, [0x01, 0x23, 0xab, 0xff]);
+        assert!(read_meta.matches(&payload));
+
+        // A newer version is reported as such.
+        meta_bytes[0] = FORMAT_VERSION + 1;
+        let error = Header::read_from(&mut meta_bytes.as_slice(), IDENTIFIER_LENGTH, true)
+            .expect_err("newer metadata should not be read");
+        assert!(error.to_string().contains("newer paperback"));
+        // But not for a code that only starts like metadata.
+        let error = Header::read_from(&mut &meta_bytes[..20], IDENTIFIER_LENGTH, true)
+            .expect_err("a truncated header should not be read");
+        assert!(!error.to_string().contains("newer paperback"));
+        // Payloads with another version, such as codes that paperback did not create, are read,
+        // but do not match the document.
+        let Header::Payload(payload) =
+            Header::read_from(&mut &b"https://example.com/"[..], IDENTIFIER_LENGTH, true)?
+        else {
+            panic!("expected a payload");
+        };
+        assert_eq!(payload.format_version, Some(b'h'));
+        assert!(!test_header().matches(&payload));
         Ok(())
     }
 }
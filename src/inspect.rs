@@ -0,0 +1,87 @@
+use crate::{
+    args::InspectArgs,
+    header::{self, Header, IDENTIFIER_LENGTH},
+    restore::read_shards,
+};
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Print what the QR codes in the given images contain: the decoder specification, the metadata
+/// for each document, and how many payload shards were found for each document.
+pub(crate) fn inspect(args: &InspectArgs) -> Result<()> {
+    let shards = read_shards(&args.input_path, args.tile_size, None, false, None)?;
+    let mut specs = BTreeSet::<&str>::new();
+    let mut metas = Vec::<header::MetaHeader>::new();
+    let mut payloads = BTreeMap::<header::Identifier, BTreeSet<u16>>::new();
+    let mut unknown_count = 0;
+
+    for shard in shards.iter().filter(|shard| header::is_meta(shard)) {
+        match Header::read_from(&mut shard.as_slice(), IDENTIFIER_LENGTH, true) {
+            Ok(Header::Meta(m)) if !metas.contains(&m) => metas.push(m),
+            Ok(_) => {}
+            Err(_) => unknown_count += 1,
+        }
+    }
+    // Payloads are read with the shortest identifier any document uses, and then attributed to the
+    // document they match.
+    let payload_identifier_length = metas
+        .iter()
+        .map(|m| m.payload_identifier_length)
+        .min()
+        .unwrap_or(IDENTIFIER_LENGTH);
+    let versioned = metas.first().is_none_or(|m| m.format_version.is_some());
+
+    for shard in shards.iter() {
+        if shard.starts_with(header::DECODER_SPEC_PREFIX.as_bytes()) {
+            match std::str::from_utf8(shard) {
+                Ok(spec) => _ = specs.insert(spec),
+                Err(_) => unknown_count += 1,
+            }
+            continue;
+        }
+        if header::is_meta(shard) {
+            continue;
+        }
+        match Header::read_from(&mut shard.as_slice(), payload_identifier_length, versioned) {
+            Ok(Header::Payload(p)) => {
+                let identifier = metas
+                    .iter()
+                    .find(|m| m.matches(&p))
+                    .map_or(p.identifier, |m| m.identifier);
+                payloads.entry(identifier).or_default().insert(p.index);
+            }
+            Ok(Header::Meta(_)) => {}
+            Err(_) => unknown_count += 1,
+        }
+    }
+
+    for spec in specs {
+        println!("Decoder specification:\n{}", spec.trim_end());
+    }
+    for meta in &metas {
+        println!("{meta}");
+    }
+    for (identifier, indices) in &payloads {
+        let known = metas.iter().find(|m| m.identifier.eq(identifier));
+        println!(
+            "Identifier {}: {} distinct payload shards{}",
+            header::to_hex(identifier),
+            indices.len(),
+            match known {
+                // Page parity codes do not count on their own, as they may not repair anything.
+                Some(m)
+                    if indices.range(..m.recovery_count).count() >= m.original_count as usize =>
+                {
+                    " (enough to restore)"
+                }
+                Some(_) => " (not enough to restore)",
+                None => " (no metadata found)",
+            }
+        );
+    }
+    if unknown_count > 0 {
+        println!("{unknown_count} codes were not recognized");
+    }
+
+    Ok(())
+}
@@ -1,20 +1,174 @@
 mod args;
 mod create;
+mod encryption;
 mod fonts;
 mod header;
+mod inspect;
+mod manifest;
+mod reflow;
 mod restore;
+mod scale;
+mod signing;
+mod verify;
 use args::Commands;
 use clap::Parser;
+use std::{fmt, process::ExitCode};
 
-fn main() -> anyhow::Result<()> {
-    match args::TopLevelArgs::parse().command {
+/// Failures that scripts may want to tell apart from other errors; each has its own exit code.
+#[derive(Debug)]
+pub(crate) enum Failure {
+    /// Too few shards were found to restore the document.
+    InsufficientShards(String),
+    /// The restored data does not match the hash recorded in the metadata.
+    ChecksumMismatch(String),
+    /// Options were given that cannot be used together, found after the command line was parsed.
+    BadArguments(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::InsufficientShards(message)
+            | Failure::ChecksumMismatch(message)
+            | Failure::BadArguments(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code for errors not covered by a more specific code.
+const EXIT_ERROR: u8 = 1;
+/// Exit code when too few shards were found to restore the document.
+const EXIT_INSUFFICIENT_SHARDS: u8 = 2;
+/// Exit code when the restored data does not match its recorded hash.
+const EXIT_CHECKSUM_MISMATCH: u8 = 3;
+/// Exit code when the command line could not be parsed, or has options that cannot be used
+/// together.
+const EXIT_BAD_ARGUMENTS: u8 = 4;
+/// Exit code when reading or writing a file failed.
+const EXIT_IO_ERROR: u8 = 5;
+
+/// Pick the exit code for an error from the first cause that has a specific one.
+fn exit_code(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return match failure {
+                Failure::InsufficientShards(_) => EXIT_INSUFFICIENT_SHARDS,
+                Failure::ChecksumMismatch(_) => EXIT_CHECKSUM_MISMATCH,
+                Failure::BadArguments(_) => EXIT_BAD_ARGUMENTS,
+            };
+        }
+        // The image crate wraps the errors from reading and writing image files in its own type.
+        if cause.is::<std::io::Error>()
+            || matches!(
+                cause.downcast_ref::<image::ImageError>(),
+                Some(image::ImageError::IoError(_))
+            )
+        {
+            return EXIT_IO_ERROR;
+        }
+    }
+    EXIT_ERROR
+}
+
+fn run(command: Commands) -> anyhow::Result<()> {
+    match command {
         Commands::Create(args) => {
-            create::create(&args)?;
+            print_create_report(create::create(&args)?, &args)?;
         }
         Commands::Restore(args) => {
             restore::restore(&args)?;
         }
+        Commands::Inspect(args) => {
+            inspect::inspect(&args)?;
+        }
+        Commands::Reflow(args) => {
+            print_create_report(reflow::reflow(&args)?, &args.create)?;
+        }
+        Commands::Verify(args) => {
+            verify::verify(&args)?;
+        }
+        Commands::Ruler(args) => {
+            create::ruler(&args)?;
+        }
     }
 
     Ok(())
 }
+
+/// Print what `create` (or `reflow`) wrote: as JSON with `--porcelain`, as a table with
+/// `--dry-run`, and otherwise as a line saying where the pages went.
+fn print_create_report(
+    report: Option<create::CreateReport>,
+    args: &args::CreateArgs,
+) -> anyhow::Result<()> {
+    let Some(report) = report else {
+        return Ok(());
+    };
+    if args.porcelain {
+        println!("{}", serde_json::to_string(&report)?);
+        if let Some(note) = report.draft_note() {
+            eprintln!("{note}");
+        }
+    } else if args.dry_run {
+        print!("{}", report.layout_table());
+    } else if report.out_path.is_some() {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match args::TopLevelArgs::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            // This also handles --help and --version, which are not failures.
+            let _ = err.print();
+            return if err.use_stderr() {
+                ExitCode::from(EXIT_BAD_ARGUMENTS)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    match run(args.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow!(Failure::InsufficientShards("need 2 more".to_string()))
+            .context("failed to decode original data");
+        assert_eq!(exit_code(&err), EXIT_INSUFFICIENT_SHARDS);
+        assert_eq!(err.to_string(), "failed to decode original data");
+
+        let err: anyhow::Error = Failure::ChecksumMismatch("checksum mismatch".to_string()).into();
+        assert_eq!(exit_code(&err), EXIT_CHECKSUM_MISMATCH);
+
+        let err: anyhow::Error =
+            Failure::BadArguments("--n-up is not supported with --format svg".to_string()).into();
+        assert_eq!(exit_code(&err), EXIT_BAD_ARGUMENTS);
+
+        let err = std::fs::read("/nonexistent/paperback")
+            .context("failed to read")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_IO_ERROR);
+        let err = image::open("/nonexistent/paperback.png")
+            .context("failed to open")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_IO_ERROR);
+
+        assert_eq!(exit_code(&anyhow!("something else")), EXIT_ERROR);
+    }
+}
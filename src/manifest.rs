@@ -0,0 +1,94 @@
+use crate::{args::CreateArgs, create::layout, header::MetaHeader};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::BufWriter, path::Path};
+
+/// The version of the manifest schema; this must be changed whenever existing fields change
+/// meaning, or are removed.
+const MANIFEST_VERSION: u32 = 1;
+
+/// `Manifest` describes a generated document, so that it can be reprinted or verified later without
+/// scanning it.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// The version of this schema; see [`MANIFEST_VERSION`].
+    pub manifest_version: u32,
+    /// The version of paperback that created the document.
+    pub tool_version: String,
+    /// The commit of paperback that created the document, as printed on each page.
+    pub tool_commit: String,
+    /// The name of the original file, without any directories.
+    pub file_name: String,
+    /// The size of the original file, in bytes.
+    pub file_size: u64,
+    /// The document ID printed on each page.
+    pub document_id: String,
+    /// The contents of the metadata QR codes.
+    pub meta: MetaHeader,
+    pub layout: LayoutManifest,
+}
+
+/// `LayoutManifest` is the part of the manifest describing how the codes are laid out.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LayoutManifest {
+    /// The QR code version (size) used for the payload codes.
+    pub qr_version: i16,
+    /// The QR code error correction level used for the payload codes: one of L, M, Q, or H.
+    pub error_correction: String,
+    pub shards_per_row: usize,
+    pub shards_per_page: usize,
+    /// The number of data bytes in each payload code, excluding the header.
+    pub data_bytes_per_shard: usize,
+    pub data_shard_count: usize,
+    pub recovery_shard_count: usize,
+    /// The minimum number of pages needed to restore the file.
+    pub data_page_count: usize,
+    /// The total number of pages with payload codes.
+    pub page_count: usize,
+    pub page_width_mm: f32,
+    pub page_height_mm: f32,
+    pub module_length_mm: f32,
+}
+
+impl Manifest {
+    /// Build the manifest for a document with the given layout.
+    pub(crate) fn new(args: &CreateArgs, layout: &layout::Options, file_size: u64) -> Result<Self> {
+        Ok(Manifest {
+            manifest_version: MANIFEST_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            tool_commit: args.override_commit.clone(),
+            file_name: args
+                .file_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            file_size,
+            document_id: layout.document_id(),
+            meta: layout.meta_header()?,
+            layout: LayoutManifest {
+                qr_version: match layout.version {
+                    qrcode::Version::Normal(v) | qrcode::Version::Micro(v) => v,
+                },
+                error_correction: format!("{:?}", layout.level),
+                shards_per_row: layout.shards_per_row,
+                shards_per_page: layout.shards_per_page,
+                data_bytes_per_shard: layout.data_bytes_per_shard,
+                data_shard_count: layout.data_shard_count,
+                recovery_shard_count: layout.recovery_shard_count,
+                data_page_count: layout.data_page_count,
+                page_count: layout.recovery_page_count,
+                page_width_mm: layout.page_width.0,
+                page_height_mm: layout.page_height.0,
+                module_length_mm: layout.module_length.0,
+            },
+        })
+    }
+
+    /// Write the manifest as JSON to the given path.
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use crate::{
+    args::ReflowArgs,
+    create::{self, CreateReport},
+    restore, Failure,
+};
+use anyhow::Result;
+use std::iter;
+
+/// Restore a document from the given images, and create it again with the new layout.  The data
+/// never touches the disk, so this works even when the original file has been lost.  The hash is
+/// unchanged, so any signature is carried over to the new document.
+pub(crate) fn reflow(args: &ReflowArgs) -> Result<Option<CreateReport>> {
+    if !args.create.add_files.is_empty() {
+        Err(Failure::BadArguments(
+            "--add-file cannot be used with reflow, which encodes the restored document"
+                .to_string(),
+        ))?;
+    }
+    let input_paths = iter::once(&args.create.file_path)
+        .chain(&args.scans)
+        .cloned()
+        .collect::<Vec<_>>();
+    let (meta, data) = restore::restore_data(&input_paths, args.tile_size)?;
+    create::create_from_data(&args.create, data, meta.bundle, meta.signature)
+}
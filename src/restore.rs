@@ -1,176 +1,1865 @@
 use crate::{
-    args::RestoreArgs,
-    header::{self, Header},
+    args::{RestoreArgs, SHARD_GRANULARITY},
+    create::raster,
+    encryption,
+    header::{self, Encryption, Header, IDENTIFIER_LENGTH},
+    scale, signing, Failure,
 };
 use anyhow::{anyhow, Context, Result};
+use base64::prelude::*;
 use byteorder::{ByteOrder, LittleEndian};
 use chksum_hash_sha2_512 as sha512;
+use image::{imageops::FilterType, DynamicImage};
+use itertools::Itertools;
+use num_integer::Integer;
+use pdfium_render::prelude::{PdfRenderConfig, Pdfium, PdfiumError};
+use qrcode::{EcLevel, QrCode, Version};
 use rayon::prelude::*;
 use reed_solomon_simd::ReedSolomonDecoder;
 use rxing::{
     common::HybridBinarizer,
     multi::{GenericMultipleBarcodeReader, MultipleBarcodeReader},
+    qrcode::{decoder::ErrorCorrectionLevel, QRCodeReader},
     BarcodeFormat, BinaryBitmap, BufferedImageLuminanceSource,
     DecodeHintType::{POSSIBLE_FORMATS, TRY_HARDER},
     DecodeHintValue::{PossibleFormats, TryHarder},
+    Exceptions, RXingResult, RXingResultMetadataType, RXingResultMetadataValue, Reader,
 };
+use serde::Serialize;
 use std::{
-    fs,
+    collections::{BTreeMap, HashSet},
+    env, fs,
     io::{Read, Write},
     path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+const PT_PER_INCH: f32 = 72.;
+
 /// `IntoFlatIter` is a helper to make the return type of [`read_shards`] easier to read.
-struct IntoFlatIter<T> {
-    value: Vec<Vec<T>>,
+pub(crate) struct IntoFlatIter<T> {
+    pub(crate) value: Vec<Vec<T>>,
+    /// Where each entry of `value` was read from.
+    pub(crate) paths: Vec<PathBuf>,
 }
 
 impl<T> IntoFlatIter<T> {
-    fn iter(&self) -> impl Iterator<Item = &T> {
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
         self.value.iter().flatten()
     }
 }
 
-/// `read_shards` reads the given files, returning scanned QR codes.
-fn read_shards(input_paths: &Vec<PathBuf>) -> Result<IntoFlatIter<rxing::RXingResult>> {
-    let shard_list = input_paths
+/// `Payloads` holds the payload data of a document's shards, with their indices.
+type Payloads = Vec<(u16, Vec<u8>)>;
+
+/// `scan_image` finds the QR codes in the given image, returning the bytes stored in each code.
+/// `try_harder` makes the reader look more thoroughly, which is slower but finds more codes in
+/// poor scans.
+pub(crate) fn scan_image(image: DynamicImage, try_harder: bool) -> Result<Vec<Vec<u8>>> {
+    Ok(find_codes(image, try_harder)?
+        .iter()
+        .map(|result| result.getRawBytes().clone())
+        .collect())
+}
+
+/// `find_codes` finds the QR codes in the given image, returning the full results, which include
+/// where each code was found.
+pub(crate) fn find_codes(image: DynamicImage, try_harder: bool) -> Result<Vec<RXingResult>> {
+    let bitmap = &mut BinaryBitmap::new(HybridBinarizer::new(BufferedImageLuminanceSource::new(
+        image,
+    )));
+    let reader = rxing::MultiUseMultiFormatReader::default();
+    let mut scanner = GenericMultipleBarcodeReader::new(reader);
+    match scanner.decode_multiple_with_hints(
+        bitmap,
+        &rxing::DecodingHintDictionary::from([
+            (
+                POSSIBLE_FORMATS,
+                PossibleFormats(vec![BarcodeFormat::QR_CODE].into_iter().collect()),
+            ),
+            (TRY_HARDER, TryHarder(try_harder)),
+        ]),
+    ) {
+        Ok(results) => Ok(results),
+        Err(Exceptions::NotFoundException(_)) => Ok(vec![]),
+        Err(e) => Err(e)?,
+    }
+}
+
+/// `scan_single` reads an image that contains a single QR code, such as a photograph of one code
+/// cut from a page.  The multiple barcode reader sometimes misses a lone code, so this uses the
+/// plain QR code reader instead, retrying with the image rotated if the first attempt fails.
+fn scan_single(image: &DynamicImage, try_harder: bool) -> Result<Option<Vec<u8>>> {
+    let hints = rxing::DecodingHintDictionary::from([(TRY_HARDER, TryHarder(try_harder))]);
+    let rotations: [fn(&DynamicImage) -> DynamicImage; 4] = [
+        DynamicImage::clone,
+        DynamicImage::rotate90,
+        DynamicImage::rotate180,
+        DynamicImage::rotate270,
+    ];
+    for rotate in rotations {
+        let bitmap = &mut BinaryBitmap::new(HybridBinarizer::new(
+            BufferedImageLuminanceSource::new(rotate(image)),
+        ));
+        match QRCodeReader.decode_with_hints(bitmap, &hints) {
+            Ok(result) => return Ok(Some(result.getRawBytes().clone())),
+            Err(Exceptions::NotFoundException(_))
+            | Err(Exceptions::ChecksumException(_))
+            | Err(Exceptions::FormatException(_)) => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok(None)
+}
+
+/// `scan_tiles` scans an image as a series of overlapping tiles no larger than `tile_size` pixels
+/// on each side, returning the unique codes found.  Tiles overlap by half, so that any code no
+/// larger than half a tile is completely contained in at least one tile.
+fn scan_tiles(image: DynamicImage, tile_size: u32, try_harder: bool) -> Result<Vec<Vec<u8>>> {
+    if image.width() <= tile_size && image.height() <= tile_size {
+        return scan_image(image, try_harder);
+    }
+    let stride = std::cmp::max(tile_size / 2, 1);
+    let offsets = |length: u32| {
+        (0..length.saturating_sub(tile_size).div_ceil(stride) + 1)
+            .map(move |n| std::cmp::min(n * stride, length.saturating_sub(tile_size)))
+    };
+    let tiles = offsets(image.height())
+        .cartesian_product(offsets(image.width()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let results = tiles
         .par_iter()
-        .map(|input_path| -> anyhow::Result<Vec<_>> {
-            let image = image::open(input_path)?;
-            let bitmap = &mut BinaryBitmap::new(HybridBinarizer::new(
-                BufferedImageLuminanceSource::new(image),
-            ));
-            let reader = rxing::MultiUseMultiFormatReader::default();
-            let mut scanner = GenericMultipleBarcodeReader::new(reader);
-            let results = scanner.decode_multiple_with_hints(
-                bitmap,
-                &rxing::DecodingHintDictionary::from([
-                    (
-                        POSSIBLE_FORMATS,
-                        PossibleFormats(vec![BarcodeFormat::QR_CODE].into_iter().collect()),
-                    ),
-                    (TRY_HARDER, TryHarder(true)),
-                ]),
-            )?;
-            Ok(results)
-        })
+        .map(|(y, x)| scan_image(image.crop_imm(*x, *y, tile_size, tile_size), try_harder))
         .collect::<Result<Vec<_>>>()?;
-    Ok(IntoFlatIter { value: shard_list })
+    // Codes in the overlapping areas are found more than once; since the position of a code is not
+    // needed for decoding, identical contents are merged.
+    Ok(results.into_iter().flatten().unique().collect())
+}
+
+/// `scan` finds the QR codes in the given image, tiling it if `tile_size` is set.  If no codes are
+/// found, the image is tried again as a single code.
+fn scan(image: DynamicImage, tile_size: Option<u32>, try_harder: bool) -> Result<Vec<Vec<u8>>> {
+    let codes = match tile_size {
+        Some(tile_size) => scan_tiles(image.clone(), tile_size, try_harder)?,
+        None => scan_image(image.clone(), try_harder)?,
+    };
+    if !codes.is_empty() {
+        return Ok(codes);
+    }
+    Ok(scan_single(&image, try_harder)?.into_iter().collect())
+}
+
+/// `module_pixels` returns the length in pixels of a module of the smallest of the given codes.
+fn module_pixels(results: &[RXingResult]) -> Option<f32> {
+    results
+        .iter()
+        .filter_map(|result| {
+            // The points are either the four corners of the code, or the centres of the three
+            // finder patterns, which are (width - 7) modules apart.
+            let width = code_width(result)?;
+            let (points, modules) = match result.getPoints().as_slice() {
+                points @ [_, _, _, _] => (points, width),
+                points @ [_, _, _] => (points, width - 7),
+                _ => return None,
+            };
+            let side_length = points
+                .iter()
+                .zip(points.iter().skip(1))
+                .map(|(a, b)| a.distance(*b))
+                .sum::<f32>()
+                / (points.len() - 1) as f32;
+            Some(side_length / modules as f32)
+        })
+        .min_by(f32::total_cmp)
+}
+
+/// `code_width` works out the width in modules of a code from its contents.  The metadata code is
+/// always encoded in the smallest version that holds it, but the data in a payload code is
+/// rounded down to a multiple of [`SHARD_GRANULARITY`] bytes (with the default
+/// `--shard-granularity`), so this picks the largest version that could have been used;
+/// overestimating the width only means that images are shrunk a little less.
+fn code_width(result: &RXingResult) -> Option<usize> {
+    let bytes = result.getRawBytes();
+    if header::is_meta(bytes) {
+        let code = QrCode::with_error_correction_level(bytes, EcLevel::H).ok()?;
+        return Some(code.width());
+    }
+    let Some(RXingResultMetadataValue::ErrorCorrectionLevel(level)) = result
+        .getRXingResultMetadata()
+        .get(&RXingResultMetadataType::ERROR_CORRECTION_LEVEL)
+    else {
+        return None;
+    };
+    let level = match level.parse().ok()? {
+        ErrorCorrectionLevel::L => EcLevel::L,
+        ErrorCorrectionLevel::M => EcLevel::M,
+        ErrorCorrectionLevel::Q => EcLevel::Q,
+        ErrorCorrectionLevel::H => EcLevel::H,
+        ErrorCorrectionLevel::Invalid => return None,
+    };
+    (1..=40)
+        .rev()
+        .find(|&version| {
+            let Ok(bits) = qrcode::bits::Bits::new(Version::Normal(version)).max_len(level) else {
+                return false;
+            };
+            // Byte mode uses a 4 bit mode indicator, and an 8 or 16 bit character count.
+            let char_count_length = if version <= 9 { 8 } else { 16 };
+            let capacity = (bits - 4 - char_count_length) / 8;
+            capacity >= bytes.len() && capacity < bytes.len() + SHARD_GRANULARITY
+        })
+        .map(|version| version as usize * 4 + 17)
+}
+
+/// `shrink` scales an image down by the given factor, if it is less than one.
+fn shrink(image: DynamicImage, factor: f32) -> DynamicImage {
+    if factor >= 1. {
+        return image;
+    }
+    image.resize(
+        (image.width() as f32 * factor).ceil() as u32,
+        (image.height() as f32 * factor).ceil() as u32,
+        FilterType::Triangle,
+    )
+}
+
+/// `report_codes` prints how many codes were found in an input.  When there is only one code, as
+/// when codes are photographed one at a time, its index is printed too.
+fn report_codes(input_path: &Path, codes: &[Vec<u8>]) {
+    match codes {
+        [code] if header::is_meta(code) => {
+            println!("{}: found 1 code (metadata)", input_path.display())
+        }
+        [code] => match Header::read_from(&mut code.as_slice(), IDENTIFIER_LENGTH, true) {
+            Ok(Header::Payload(p)) => {
+                println!("{}: found 1 code (index {})", input_path.display(), p.index)
+            }
+            _ => println!("{}: found 1 code", input_path.display()),
+        },
+        _ => println!("{}: found {} codes", input_path.display(), codes.len()),
+    }
+}
+
+/// `ImageSource` is an image to be scanned: a file, an entry read out of a ZIP archive, or a page
+/// of a PDF.
+pub(crate) enum ImageSource {
+    File(PathBuf),
+    /// The path of the entry (the archive path joined with the name of the entry), and its
+    /// contents.
+    ZipEntry(PathBuf, Vec<u8>),
+    /// The path to report the page as (such as `scans.pdf#page=3`), and the page rendered as a
+    /// PNG image.
+    PdfPage(PathBuf, Vec<u8>),
+}
+
+impl ImageSource {
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            ImageSource::File(path)
+            | ImageSource::ZipEntry(path, _)
+            | ImageSource::PdfPage(path, _) => path,
+        }
+    }
+
+    pub(crate) fn open(&self) -> Result<DynamicImage> {
+        match self {
+            ImageSource::File(path) => Ok(image::open(path)?),
+            ImageSource::ZipEntry(path, bytes) => Ok(image::load_from_memory_with_format(
+                bytes,
+                image::ImageFormat::from_path(path)?,
+            )?),
+            ImageSource::PdfPage(_, bytes) => Ok(image::load_from_memory_with_format(
+                bytes,
+                image::ImageFormat::Png,
+            )?),
+        }
+    }
+
+    /// Read the encoded image, as stored in the file; PDF pages are PNG images, recording the
+    /// resolution they were rendered at.
+    pub(crate) fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            ImageSource::File(path) => Ok(fs::read(path)?),
+            ImageSource::ZipEntry(_, bytes) | ImageSource::PdfPage(_, bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
+/// The resolution that the pages of PDFs are rendered at.  Codes printed with the smallest
+/// modules that scan reliably are still several pixels across at this resolution.
+const PDF_DPI: u32 = 300;
+
+/// `pdfium` loads the PDFium library, once, to render the pages of PDFs.  It is looked for next to
+/// the executable, and then in the system library path.
+fn pdfium() -> Result<&'static Pdfium> {
+    static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+    PDFIUM
+        .get_or_init(|| {
+            env::current_exe()
+                .map_err(PdfiumError::IoError)
+                .and_then(|exe| {
+                    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(
+                        exe.parent().unwrap_or(Path::new(".")),
+                    ))
+                })
+                .or_else(|_| Pdfium::bind_to_system_library())
+                .map(Pdfium::new)
+                .map_err(|err| match err {
+                    // The library's own message says why it could not be loaded.
+                    PdfiumError::LoadLibraryError(err) => std::error::Error::source(&err)
+                        .map_or_else(|| err.to_string(), ToString::to_string),
+                    err => err.to_string(),
+                })
+        })
+        .as_ref()
+        .map_err(|err| {
+            anyhow!(
+                "reading PDFs needs the PDFium library ({}), which was not found next to \
+                 paperback or in the system library path ({err}); install it, or convert the PDF \
+                 into images (for example with `pdftocairo -png`) and restore from those",
+                Pdfium::pdfium_platform_library_name().to_string_lossy()
+            )
+        })
+}
+
+/// `pdf_sources` renders each page of a PDF at [`PDF_DPI`], to be scanned as an image.
+fn pdf_sources(pdf_path: &Path) -> Result<Vec<ImageSource>> {
+    let document = pdfium()?
+        .load_pdf_from_file(pdf_path, None)
+        .with_context(|| format!("failed to read {}", pdf_path.display()))?;
+    let config = PdfRenderConfig::new().scale_page_by_factor(PDF_DPI as f32 / PT_PER_INCH);
+    document
+        .pages()
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+            let path = format!("{}#page={}", pdf_path.display(), index + 1);
+            let image = page
+                .render_with_config(&config)
+                .with_context(|| format!("failed to render {path}"))?
+                .as_image();
+            Ok(ImageSource::PdfPage(
+                path.into(),
+                raster::encode_png(&image.to_rgb8(), PDF_DPI)?,
+            ))
+        })
+        .collect()
+}
+
+/// `image_sources` lists the images to scan for an input path: the file itself, every page of a
+/// PDF, or if it is a directory or a ZIP archive, every image in it (including those in
+/// subdirectories, and the pages of PDFs in a directory).  Other entries are skipped with a
+/// warning.
+pub(crate) fn image_sources(input_path: &Path) -> Result<Vec<ImageSource>> {
+    if input_path.is_dir() {
+        return directory_sources(input_path);
+    }
+    if is_pdf(input_path) {
+        return pdf_sources(input_path);
+    }
+    if !is_zip(input_path) {
+        return Ok(vec![ImageSource::File(input_path.to_path_buf())]);
+    }
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("failed to open {}", input_path.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("failed to read {}", input_path.display()))?;
+    let mut sources = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let path = input_path.join(entry.name());
+        if image::ImageFormat::from_path(&path).is_err() {
+            eprintln!("Warning: skipping {}: not an image", path.display());
+            continue;
+        }
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+        sources.push(ImageSource::ZipEntry(path, bytes));
+    }
+    Ok(sources)
+}
+
+/// `directory_sources` lists the images in a directory and its subdirectories, in name order.
+fn directory_sources(dir: &Path) -> Result<Vec<ImageSource>> {
+    let mut paths = fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let mut sources = Vec::new();
+    for path in paths {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if !path.is_dir()
+            && !is_zip(&path)
+            && !is_pdf(&path)
+            && image::ImageFormat::from_path(&path).is_err()
+        {
+            eprintln!("Warning: skipping {}: not an image", path.display());
+            continue;
+        }
+        sources.extend(image_sources(&path)?);
+    }
+    Ok(sources)
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+/// `input_sources` lists the images to scan for the given files, along with the index of the file
+/// each came from.  Directories and ZIP archives are read as if their images had been given
+/// instead, and each page of a PDF is read as an image.  If `limit` is set, only that many images
+/// are listed, however many files they come from.
+pub(crate) fn input_sources(
+    input_paths: &[PathBuf],
+    limit: Option<usize>,
+) -> Result<Vec<(usize, ImageSource)>> {
+    input_paths
+        .iter()
+        .enumerate()
+        .map(|(input_index, input_path)| {
+            image_sources(input_path)
+                .map(|sources| sources.into_iter().map(move |source| (input_index, source)))
+        })
+        .flatten_ok()
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// `read_shards` reads the given files, returning the contents of scanned QR codes.  The images in
+/// them are found by [`input_sources`], without a limit.
+pub(crate) fn read_shards(
+    input_paths: &[PathBuf],
+    tile_size: Option<u32>,
+    min_module_pixels: Option<f32>,
+    fast_scan: bool,
+    deadline: Option<Instant>,
+) -> Result<IntoFlatIter<Vec<u8>>> {
+    let sources = input_sources(input_paths, None)?;
+    read_sources(
+        input_paths,
+        sources,
+        tile_size,
+        min_module_pixels,
+        fast_scan,
+        deadline,
+    )
+}
+
+/// `read_sources` scans the images listed by [`input_sources`] for the given files, returning the
+/// contents of the QR codes found.  Once `deadline` passes, no more images are started, and the
+/// codes found so far are returned.  If `min_module_pixels` is set, images are read one at a time
+/// until the size of the codes is known, and the rest are shrunk to just keep their modules that
+/// many pixels across.  With `fast_scan`, images are first scanned without trying hard, and only
+/// those with fewer codes than the best image are scanned again more thoroughly.
+pub(crate) fn read_sources(
+    input_paths: &[PathBuf],
+    sources: Vec<(usize, ImageSource)>,
+    tile_size: Option<u32>,
+    min_module_pixels: Option<f32>,
+    fast_scan: bool,
+    deadline: Option<Instant>,
+) -> Result<IntoFlatIter<Vec<u8>>> {
+    // Remember which input each image came from, to summarize them afterwards.
+    let (origins, sources): (Vec<_>, Vec<_>) = sources.into_iter().unzip();
+    let past_deadline = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let open = |source: &ImageSource| {
+        source
+            .open()
+            .with_context(|| format!("failed to open {}", source.path().display()))
+    };
+    let mut shard_list = Vec::with_capacity(sources.len());
+    let mut factor = 1.;
+    if let Some(min_module_pixels) = min_module_pixels {
+        for source in &sources {
+            if past_deadline() {
+                break;
+            }
+            let image = open(source)?;
+            let results = find_codes(image.clone(), !fast_scan)?;
+            let measured = module_pixels(&results);
+            shard_list.push(Some(if results.is_empty() {
+                scan_single(&image, !fast_scan)?.into_iter().collect()
+            } else {
+                results
+                    .iter()
+                    .map(|result| result.getRawBytes().clone())
+                    .collect()
+            }));
+            if let Some(measured) = measured {
+                factor = min_module_pixels / measured;
+                if factor < 1. && shard_list.len() < sources.len() {
+                    println!(
+                        "Modules are {measured:.1} pixels across; shrinking the remaining images \
+                         to {:.0}%",
+                        factor * 100.
+                    );
+                }
+                break;
+            }
+        }
+    }
+    // The images read while measuring the codes are kept at their full size.
+    let measured_count = shard_list.len();
+    let scan_source = |index: usize, try_harder: bool| -> anyhow::Result<Option<Vec<_>>> {
+        if past_deadline() {
+            return Ok(None);
+        }
+        let image = open(&sources[index])?;
+        let image = if index < measured_count {
+            image
+        } else {
+            shrink(image, factor)
+        };
+        scan(image, tile_size, try_harder).map(Some)
+    };
+    shard_list.extend(
+        (measured_count..sources.len())
+            .into_par_iter()
+            .map(|index| scan_source(index, !fast_scan))
+            .collect::<Result<Vec<_>>>()?,
+    );
+    if fast_scan {
+        // Every page has the same number of codes, so an image with fewer codes than the best one
+        // probably had some that the quick scan missed.
+        let expected = shard_list
+            .iter()
+            .flatten()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let marginal = shard_list
+            .iter()
+            .positions(|shards| {
+                shards
+                    .as_ref()
+                    .is_some_and(|shards| shards.len() < expected)
+            })
+            .collect::<Vec<_>>();
+        let rescanned = marginal
+            .par_iter()
+            .map(|&index| scan_source(index, true))
+            .collect::<Result<Vec<_>>>()?;
+        let mut rescanned_count = 0;
+        let mut found_count = 0;
+        for (index, codes) in marginal.into_iter().zip(rescanned) {
+            let (Some(codes), Some(shards)) = (codes, &mut shard_list[index]) else {
+                continue;
+            };
+            rescanned_count += 1;
+            for code in codes {
+                if !shards.contains(&code) {
+                    shards.push(code);
+                    found_count += 1;
+                }
+            }
+        }
+        if rescanned_count > 0 {
+            println!(
+                "Fast scan: {rescanned_count} images had fewer than {expected} codes and were \
+                 scanned again more thoroughly, finding {found_count} more codes; these scans may \
+                 be marginal"
+            );
+        }
+    }
+    let mut skipped_count = 0;
+    for (source, shards) in sources.iter().zip(&shard_list) {
+        match shards {
+            Some(shards) => report_codes(source.path(), shards),
+            None => skipped_count += 1,
+        }
+    }
+    if skipped_count > 0 {
+        println!("Time limit reached; skipped {skipped_count} images");
+    }
+    if input_paths.len() > 1
+        && input_paths
+            .iter()
+            .any(|path| path.is_dir() || is_zip(path) || is_pdf(path))
+    {
+        report_inputs(input_paths, &origins, &shard_list);
+    }
+    let paths = sources
+        .iter()
+        .zip(&shard_list)
+        .filter(|(_, shards)| shards.is_some())
+        .map(|(source, _)| source.path().to_path_buf())
+        .collect();
+    Ok(IntoFlatIter {
+        value: shard_list.into_iter().flatten().collect(),
+        paths,
+    })
+}
+
+/// Summarize the codes found in each input (such as a directory of scans from one session), and
+/// how many of them were not already found in earlier inputs.
+fn report_inputs(input_paths: &[PathBuf], origins: &[usize], shard_list: &[Option<Vec<Vec<u8>>>]) {
+    let mut seen = HashSet::<&[u8]>::new();
+    for (input_index, input_path) in input_paths.iter().enumerate() {
+        let codes = origins
+            .iter()
+            .zip(shard_list)
+            .filter(|(&origin, _)| origin == input_index)
+            .flat_map(|(_, shards)| shards.iter().flatten())
+            .collect::<Vec<_>>();
+        let new_count = codes
+            .iter()
+            .filter(|code| seen.insert(code.as_slice()))
+            .count();
+        println!(
+            "{}: {} codes, {new_count} not found in earlier inputs",
+            input_path.display(),
+            codes.len()
+        );
+    }
+}
+
+/// Read the paths listed in an `--input-list` file (or standard input, for "-"), one per line.
+/// Blank lines and lines starting with "#" are skipped, and relative paths are relative to the
+/// directory of the list file.
+fn read_input_list(list_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut text = String::new();
+    let base_dir = if list_path.as_os_str() == "-" {
+        std::io::stdin().read_to_string(&mut text)?;
+        Path::new("")
+    } else {
+        fs::File::open(list_path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .with_context(|| format!("failed to read {}", list_path.display()))?;
+        list_path.parent().unwrap_or(Path::new(""))
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base_dir.join(line))
+        .collect())
+}
+
+/// `read_base64_shards` reads base64 encoded images from the given files (or standard input, for
+/// "-"), returning the contents of scanned QR codes.  Data URLs are accepted, and whitespace is
+/// ignored.
+fn read_base64_shards(input_paths: &[PathBuf], tile_size: Option<u32>) -> Result<Vec<Vec<u8>>> {
+    let mut shards = Vec::new();
+    for input_path in input_paths {
+        let mut text = String::new();
+        if input_path.as_os_str() == "-" {
+            std::io::stdin().read_to_string(&mut text)?;
+        } else {
+            fs::File::open(input_path)
+                .and_then(|mut file| file.read_to_string(&mut text))
+                .with_context(|| format!("failed to read {}", input_path.display()))?;
+        }
+        let encoded = match text.split_once(";base64,") {
+            Some((prefix, data)) if prefix.trim_start().starts_with("data:") => data,
+            _ => &text,
+        };
+        let bytes = BASE64_STANDARD
+            .decode(encoded.split_ascii_whitespace().collect::<String>())
+            .with_context(|| format!("{} is not valid base64", input_path.display()))?;
+        let image = image::load_from_memory(&bytes)
+            .with_context(|| format!("failed to load image from {}", input_path.display()))?;
+        let image_shards = scan(image, tile_size, true)?;
+        report_codes(input_path, &image_shards);
+        shards.extend(image_shards);
+    }
+    Ok(shards)
+}
+
+/// `read_shard_files` reads shards that were written as individual files by `create
+/// --dump-shards`.
+fn read_shard_files(shard_dir: &Path) -> Result<Vec<Vec<u8>>> {
+    fs::read_dir(shard_dir)
+        .with_context(|| format!("failed to read {}", shard_dir.display()))?
+        .map(|entry| entry.map_err(anyhow::Error::from))
+        .filter_ok(|entry| entry.path().extension().is_some_and(|ext| ext == "bin"))
+        .map_ok(|entry| fs::read(entry.path()).map_err(anyhow::Error::from))
+        .flatten_ok()
+        .collect()
 }
 
-/// Given the reed-solomon recovery shards, reconstruct the file and write it to the given name.
-/// If `force` is not set, this will return an error if the file already exists.
+/// Write a file, which must not already exist unless `force` is set.
+fn write_file(path: &Path, contents: &[u8], force: bool) -> Result<()> {
+    fs::File::options()
+        .truncate(true)
+        .create(true)
+        .create_new(!force)
+        .write(true)
+        .open(path)
+        .and_then(|mut out_file| out_file.write_all(contents))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// `write_verified` writes the file to a temporary file next to `path`, reads it back from disk,
+/// and only moves it into place if it is exactly `contents` (which [`decode_data`] has already
+/// checked against the document's hash); otherwise the temporary file is deleted, so nothing that
+/// failed verification is ever left at `path`.
+fn write_verified(path: &Path, contents: &[u8], force: bool) -> Result<()> {
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+    write_file(&partial_path, contents, false)?;
+    let result = fs::read(&partial_path)
+        .with_context(|| format!("failed to read back {}", partial_path.display()))
+        .and_then(|written| {
+            if written != contents {
+                Err(Failure::ChecksumMismatch(format!(
+                    "the data read back from {} is not what was written",
+                    partial_path.display()
+                )))?;
+            }
+            if !force && path.exists() {
+                Err(anyhow!(
+                    "{} already exists; use --force to overwrite it",
+                    path.display()
+                ))?;
+            }
+            fs::rename(&partial_path, path)
+                .with_context(|| format!("failed to move output to {}", path.display()))
+        });
+    if result.is_err() {
+        _ = fs::remove_file(&partial_path);
+    }
+    result
+}
+
+/// `write_bundle` unpacks a bundle of files into the directory `dir`, creating it if needed.  If
+/// `force` is not set, nothing is written if any of the files already exist.  If `strict` is set,
+/// each file is checked again after it is written (see [`write_verified`]).
+fn write_bundle(dir: &Path, bundle: &[u8], force: bool, strict: bool) -> Result<()> {
+    let files = header::unpack_bundle(bundle)?;
+    if !force {
+        if let Some(path) = files
+            .iter()
+            .map(|(name, _)| dir.join(name))
+            .find(|path| path.exists())
+        {
+            Err(anyhow!(
+                "{} already exists; use --force to overwrite it",
+                path.display()
+            ))?;
+        }
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    for (name, contents) in &files {
+        let path = dir.join(name);
+        if strict {
+            write_verified(&path, contents, force)?;
+        } else {
+            write_file(&path, contents, force)?;
+        }
+    }
+    println!(
+        "{} files ({} bytes) written to {}",
+        files.len(),
+        files
+            .iter()
+            .map(|(_, contents)| contents.len())
+            .sum::<usize>(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Given the reed-solomon recovery shards, reconstruct the file and write it to the given name, or
+/// for a bundle of files, into the directory of that name.  If `force` is not set, this will
+/// return an error if the file already exists.  If `verify_key` is set, the document must be
+/// signed by that key.  If `strict` is set, the file is checked again after it is written (see
+/// [`write_verified`]).
 fn write_output<P>(
     meta: &header::MetaHeader,
     payloads: &Vec<(u16, Vec<u8>)>,
     force: bool,
     output_path: P,
+    verify_key: Option<&str>,
+    sidecar: bool,
+    strict: bool,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
+    // The signature covers the hash in the metadata, and the restored file is checked against that
+    // hash; checking it first means a forged document is never written out.
+    if let Some(key) = verify_key {
+        let signature = meta
+            .signature
+            .as_ref()
+            .ok_or(anyhow!("document is not signed"))?;
+        signing::verify(key, &meta.hash, signature)?;
+        println!("Signature verified");
+    }
+    let data = decode_data(meta, payloads)
+        .with_context(|| format!("failed to restore {}", output_path.as_ref().display()))?;
+    if meta.bundle {
+        write_bundle(output_path.as_ref(), &data, force, strict)?;
+    } else {
+        if strict {
+            write_verified(output_path.as_ref(), &data, force)?;
+        } else {
+            write_file(output_path.as_ref(), &data, force)?;
+        }
+        println!(
+            "{} bytes written to {}",
+            data.len(),
+            output_path.as_ref().display()
+        );
+    }
+
+    if sidecar {
+        let mut sidecar_path = output_path.as_ref().as_os_str().to_owned();
+        sidecar_path.push(".paperback.json");
+        let record = Sidecar {
+            document_id: meta.document_id(),
+            identifier: header::to_hex(&meta.identifier),
+            hash: header::to_hex(&meta.hash),
+            signature_verified: verify_key.is_some(),
+            shards_used: payloads.len(),
+            byte_count: data.len(),
+            restored_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        write_file(
+            Path::new(&sidecar_path),
+            &serde_json::to_vec_pretty(&record)?,
+            force,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `Sidecar` records how a file was restored, for `--sidecar`.  The identifier and hash can be
+/// compared against the manifest written by `create --manifest`.
+#[derive(Serialize)]
+struct Sidecar {
+    document_id: String,
+    identifier: String,
+    /// The SHA-512 hash that the restored file was checked against.
+    hash: String,
+    signature_verified: bool,
+    /// The number of distinct payload codes that were available to restore from.
+    shards_used: usize,
+    byte_count: usize,
+    /// When the file was restored, in seconds since the Unix epoch.
+    restored_at: u64,
+}
+
+/// `decode_data` recovers the original file from the payloads, decrypting it if needed, and checks
+/// it against the hash in the metadata.
+fn decode_data(meta: &header::MetaHeader, payloads: &Vec<(u16, Vec<u8>)>) -> Result<Vec<u8>> {
     let mut rs_decoder = ReedSolomonDecoder::new(
         meta.original_count as usize,
         meta.recovery_count as usize,
         meta.shard_bytes as usize,
-    )?;
+    )
+    .map_err(|e| {
+        header::shard_parameter_error(
+            e,
+            meta.original_count as usize,
+            meta.recovery_count as usize,
+            meta.shard_bytes as usize,
+        )
+    })?;
     for (index, data) in payloads {
         rs_decoder.add_recovery_shard(*index as usize, data)?;
     }
 
-    let decoder_result = rs_decoder
-        .decode()
-        .with_context(|| "failed to decode original data")?;
-    let decoded = decoder_result
+    let decoder_result = rs_decoder.decode().map_err(|e| match e {
+        reed_solomon_simd::Error::NotEnoughShards { .. } => {
+            anyhow!(Failure::InsufficientShards(e.to_string()))
+        }
+        _ => anyhow!(e),
+    });
+    let decoder_result = decoder_result.with_context(|| "failed to decode original data")?;
+    let mut data = decoder_result
         .restored_original_iter()
         .map(|(_, shard)| shard)
+        .collect::<Vec<_>>()
+        .concat();
+    if meta.byte_interleave {
+        data = header::deinterleave_bytes(&data, meta.original_count as usize);
+    }
+    if data.len() < size_of::<u64>() {
+        Err(anyhow!("no shards"))?;
+    }
+    let mut expected_size = LittleEndian::read_u64(&data[data.len() - size_of::<u64>()..]) as usize;
+    if meta.end_marker {
+        expected_size = find_end_marker(&data, expected_size)?;
+    }
+    if data.len() < expected_size {
+        Err(anyhow!("data is shorter than its recorded size"))?;
+    }
+    data.truncate(expected_size);
+    if let Some(ref encryption) = meta.encryption {
+        let passphrase = encryption::passphrase(false)?;
+        data = encryption::decrypt(&passphrase, encryption, &data)?;
+    }
+    let data = meta.compression.decompress(data).map_err(|e| {
+        Failure::ChecksumMismatch(format!("restored data could not be decompressed: {e}"))
+    })?;
+    if sha512::hash(&data).into_inner().ne(&meta.hash) {
+        Err(Failure::ChecksumMismatch("checksum mismatch".to_string()))?;
+    }
+
+    Ok(data)
+}
+
+/// Find the size of the file data from the [`header::END_MARKER`] that follows it.  The recorded
+/// size is trusted if the marker is where it says; otherwise the last marker is used, and the
+/// hash check decides whether that was right.
+fn find_end_marker(data: &[u8], recorded_size: usize) -> Result<usize> {
+    let marker = header::END_MARKER;
+    if data.get(recorded_size..recorded_size.saturating_add(marker.len())) == Some(marker) {
+        return Ok(recorded_size);
+    }
+    let position = data[..data.len() - size_of::<u64>()]
+        .windows(marker.len())
+        .rposition(|window| window == marker)
+        .ok_or(anyhow!(
+            "the recorded size is damaged, and the end marker is missing"
+        ))?;
+    eprintln!(
+        "Warning: the recorded size ({recorded_size}) is damaged; using the end marker ({position})"
+    );
+    Ok(position)
+}
+
+/// Check that the document was created by a compatible version of paperback; if it was not, this
+/// is an error when `strict` is set, and a warning otherwise.
+fn check_tool_version(meta: &header::MetaHeader, strict: bool) -> Result<()> {
+    if meta.is_compatible_version() {
+        return Ok(());
+    }
+    let message = format!(
+        "document was created by paperback {}, which may not be compatible with this version ({})",
+        meta.tool_version.as_deref().unwrap_or_default(),
+        header::TOOL_VERSION
+    );
+    if strict {
+        return Err(anyhow!(message));
+    }
+    eprintln!("Warning: {message}; use --verify-tool-version to refuse such documents");
+    Ok(())
+}
+
+/// Use the page parity codes to recover the missing shards of any page that has enough codes left,
+/// replacing the parity payloads with the recovered shards.
+fn repair_pages(
+    meta: &header::MetaHeader,
+    parity: header::PageParity,
+    payloads: &mut Vec<(u16, Vec<u8>)>,
+) -> Result<()> {
+    let shards_per_page = parity.shards_per_page as usize;
+    let parity_count = parity.parity_count as usize;
+    if shards_per_page == 0 || parity_count == 0 {
+        Err(anyhow!("invalid page parity description"))?;
+    }
+    let recovery_count = meta.recovery_count as usize;
+    let (shards, parities): (Vec<_>, Vec<_>) = payloads
+        .drain(..)
+        .partition(|(index, _)| (*index as usize) < recovery_count);
+    *payloads = shards;
+
+    let mut pages = BTreeMap::<usize, (Vec<usize>, Vec<(usize, Vec<u8>)>)>::new();
+    for (position, (index, _)) in payloads.iter().enumerate() {
+        let page = pages.entry(*index as usize / shards_per_page).or_default();
+        page.0.push(position);
+    }
+    for (index, data) in parities {
+        let (page_num, parity_index) = (index as usize - recovery_count).div_rem(&parity_count);
+        let page = pages.entry(page_num).or_default();
+        page.1.push((parity_index, data));
+    }
+
+    let mut repaired_count = 0;
+    for (page_num, (positions, parities)) in pages {
+        let first_index = page_num * shards_per_page;
+        if parities.is_empty()
+            || first_index + shards_per_page > recovery_count
+            || positions.len() >= shards_per_page
+            || positions.len() + parities.len() < shards_per_page
+        {
+            continue;
+        }
+        let mut decoder =
+            ReedSolomonDecoder::new(shards_per_page, parity_count, meta.shard_bytes as usize)
+                .map_err(|e| {
+                    header::shard_parameter_error(
+                        e,
+                        shards_per_page,
+                        parity_count,
+                        meta.shard_bytes as usize,
+                    )
+                })?;
+        for position in &positions {
+            let (index, data) = &payloads[*position];
+            decoder.add_original_shard(*index as usize - first_index, data)?;
+        }
+        for (parity_index, data) in &parities {
+            decoder.add_recovery_shard(*parity_index, data)?;
+        }
+        let restored = decoder
+            .decode()
+            .with_context(|| format!("failed to repair page {}", page_num + 1))?
+            .restored_original_iter()
+            .map(|(position, data)| ((first_index + position) as u16, data.to_vec()))
+            .collect::<Vec<_>>();
+        repaired_count += restored.len();
+        payloads.extend(restored);
+    }
+    if repaired_count > 0 {
+        println!("Repaired {repaired_count} shards using page parity codes");
+    }
+    Ok(())
+}
+
+/// Drop payloads of the wrong size, and check that enough remain to restore the document.
+fn check_payloads(meta: &header::MetaHeader, payloads: &mut Vec<(u16, Vec<u8>)>) -> Result<()> {
+    // A misread code might produce a payload of the wrong size; the decoder would reject it with an
+    // unhelpful error, so drop those up front.
+    payloads.retain(|(index, data)| {
+        if data.len() as u64 == meta.shard_bytes {
+            return true;
+        }
+        eprintln!(
+            "Warning: skipping shard {index}: expected {} bytes, got {}",
+            meta.shard_bytes,
+            data.len()
+        );
+        false
+    });
+    if let Some(parity) = meta.page_parity {
+        repair_pages(meta, parity, payloads)?;
+    }
+    println!(
+        "Data loaded: got {}/{} recovery shards",
+        payloads.len(),
+        meta.recovery_count
+    );
+    if payloads.len() < meta.original_count as usize {
+        Err(Failure::InsufficientShards(format!(
+            "not enough shards to restore: need {} more",
+            meta.original_count as usize - payloads.len()
+        )))?;
+    }
+    Ok(())
+}
+
+/// Replace fields of the metadata with those given on the command line, for documents whose
+/// metadata codes were misread.  The shard size must match the codes that were found.
+fn override_meta(
+    meta: &mut header::MetaHeader,
+    payloads: &Payloads,
+    args: &RestoreArgs,
+) -> Result<()> {
+    if let Some(shard_bytes) = args.shard_bytes {
+        if !payloads
+            .iter()
+            .any(|(_, data)| data.len() as u64 == shard_bytes)
+        {
+            let lengths = payloads.iter().map(|(_, data)| data.len()).counts();
+            let common = lengths.iter().max_by_key(|(_, &count)| count);
+            Err(match common {
+                Some((length, _)) => anyhow!(
+                    "none of the codes found hold {shard_bytes} bytes; most hold {length} bytes"
+                ),
+                None => anyhow!("no payload codes were found to check --shard-bytes against"),
+            })?;
+        }
+        println!(
+            "Using a shard size of {shard_bytes} bytes, instead of {} from the metadata",
+            meta.shard_bytes
+        );
+        meta.shard_bytes = shard_bytes;
+    }
+    if let Some(original_count) = args.original_count {
+        if original_count == 0 {
+            Err(Failure::BadArguments(
+                "--original-count must be at least 1".to_string(),
+            ))?;
+        }
+        println!(
+            "Using {original_count} original shards, instead of {} from the metadata",
+            meta.original_count
+        );
+        meta.original_count = original_count;
+    }
+    Ok(())
+}
+
+/// Report how many more codes could be lost without losing the document, and which of the
+/// inputs are needed to restore it, for `--dry-run`.
+fn report_margin(meta: &header::MetaHeader, payloads: &Payloads, shards: &IntoFlatIter<Vec<u8>>) {
+    let needed = meta.original_count as usize;
+    println!(
+        "Any {needed} of the {} distinct codes found can restore the file, so {} more can be lost",
+        payloads.len(),
+        payloads.len() - needed
+    );
+
+    // The usable indices found in each input; codes dropped by `check_payloads` are not counted.
+    let usable = payloads
+        .iter()
+        .map(|(index, _)| *index)
+        .collect::<HashSet<_>>();
+    let input_indices = shards
+        .value
+        .iter()
+        .map(|input_shards| {
+            input_shards
+                .iter()
+                .filter(|shard| !header::is_meta(shard))
+                .filter_map(|shard| {
+                    match Header::read_from(
+                        &mut shard.as_slice(),
+                        meta.payload_identifier_length,
+                        meta.format_version.is_some(),
+                    ) {
+                        Ok(Header::Payload(p)) if meta.matches(&p) && usable.contains(&p.index) => {
+                            Some(p.index)
+                        }
+                        _ => None,
+                    }
+                })
+                .collect::<HashSet<_>>()
+        })
         .collect::<Vec<_>>();
-    let last_shard = decoded.last().ok_or(anyhow!("no shards"))?;
-    let expected_size =
-        LittleEndian::read_u64(&last_shard[last_shard.len() - size_of::<u64>()..]) as usize;
-    let mut bytes_written: usize = 0;
-    let mut hasher = sha512::new();
 
-    let mut out_file = fs::File::options()
-        .truncate(true)
-        .create_new(!force)
-        .write(true)
-        .open(&output_path)?;
-    for shard in decoded {
-        if shard.len() + bytes_written > expected_size {
-            hasher.update(&shard[..expected_size - bytes_written]);
-            out_file.write_all(&shard[..expected_size - bytes_written])?;
+    // An input is necessary if the codes only it holds would leave too few without it.
+    let necessary = input_indices
+        .iter()
+        .enumerate()
+        .filter(|(input, indices)| {
+            let only_here = indices
+                .iter()
+                .filter(|index| {
+                    !input_indices
+                        .iter()
+                        .enumerate()
+                        .any(|(other, others)| other != *input && others.contains(index))
+                })
+                .count();
+            usable.len() - only_here < needed
+        })
+        .map(|(input, _)| shards.paths[input].display().to_string())
+        .collect::<Vec<_>>();
+    if necessary.is_empty() {
+        println!("No single input is necessary");
+    } else {
+        println!("Necessary inputs: {}", necessary.join(", "));
+    }
+
+    // Pick the inputs adding the most new codes until there are enough; this is not always the
+    // smallest possible set, but is close to it.
+    let mut covered = HashSet::<u16>::new();
+    let mut chosen = Vec::new();
+    while covered.len() < needed {
+        let Some((input, indices)) = input_indices
+            .iter()
+            .enumerate()
+            .filter(|(input, _)| !chosen.contains(input))
+            .max_by_key(|(_, indices)| indices.difference(&covered).count())
+        else {
             break;
+        };
+        covered.extend(indices);
+        chosen.push(input);
+    }
+    chosen.sort();
+    println!(
+        "These {} of the {} inputs are enough to restore the file: {}",
+        chosen.len(),
+        input_indices.len(),
+        chosen
+            .iter()
+            .map(|&input| shards.paths[input].display().to_string())
+            .join(", ")
+    );
+}
+
+/// The metadata of every document found in the shards, once each.
+fn document_metas(shards: &IntoFlatIter<Vec<u8>>) -> Result<Vec<header::MetaHeader>> {
+    let mut metas = Vec::<header::MetaHeader>::new();
+    let mut first_error = None;
+    for shard in shards.iter().filter(|shard| header::is_meta(shard)) {
+        match Header::read_from(&mut shard.as_slice(), IDENTIFIER_LENGTH, true) {
+            Ok(Header::Meta(m)) if !metas.iter().any(|meta| meta.identifier == m.identifier) => {
+                metas.push(m)
+            }
+            Ok(_) => {}
+            Err(e) => _ = first_error.get_or_insert(e),
         }
-        hasher.update(shard);
-        out_file.write_all(shard)?;
-        bytes_written += shard.len();
     }
-    let digest = hasher.digest().into_inner();
-    if digest.ne(&meta.hash) {
+    if metas.is_empty() {
+        // A metadata code that could not be read, such as one from a newer version, says more
+        // than that there is none.
+        Err(first_error.unwrap_or_else(|| no_metadata_error(shards)))?;
+    }
+    Ok(metas)
+}
+
+/// The distinct payloads in the shards that belong to the given document.
+fn document_payloads(meta: &header::MetaHeader, shards: &IntoFlatIter<Vec<u8>>) -> Payloads {
+    let mut payloads = Vec::<(u16, Vec<u8>)>::new();
+    let mut seen_indices = HashSet::<u16>::new();
+    for shard in shards.iter() {
+        if shard.starts_with(header::DECODER_SPEC_PREFIX.as_bytes()) || header::is_meta(shard) {
+            continue;
+        }
+        let mut bytes = shard.as_slice();
+        let Ok(Header::Payload(p)) = Header::read_from(
+            &mut bytes,
+            meta.payload_identifier_length,
+            meta.format_version.is_some(),
+        ) else {
+            continue;
+        };
+        if meta.matches(&p) && seen_indices.insert(p.index) {
+            payloads.push((p.index, bytes.to_vec()));
+        }
+    }
+    payloads
+}
+
+/// `DocumentInfo` describes a document found by `--info-only`.
+#[derive(Serialize)]
+struct DocumentInfo {
+    document_id: String,
+    identifier: String,
+    /// The number of distinct payload codes found, including page parity codes.
+    payload_codes_found: usize,
+    /// Whether enough payload codes were found to restore the document.
+    enough_to_restore: bool,
+    meta: header::MetaHeader,
+}
+
+/// Write the metadata of every document found in the shards to `output_path` as JSON, without
+/// decoding any data.
+fn write_info(args: &RestoreArgs, shards: &IntoFlatIter<Vec<u8>>) -> Result<()> {
+    let mut documents = Vec::<DocumentInfo>::new();
+    for meta in document_metas(shards)? {
+        if args
+            .identifier
+            .as_deref()
+            .is_some_and(|id| !meta.has_id(id))
+        {
+            continue;
+        }
+        let payloads = document_payloads(&meta, shards);
+        // Page parity codes do not count on their own, as they may not repair anything.
+        let usable_count = payloads
+            .iter()
+            .filter(|(index, _)| *index < meta.recovery_count)
+            .count();
+        println!(
+            "{} ({}): found {} of the {} codes needed",
+            meta.document_id(),
+            header::to_hex(&meta.identifier),
+            usable_count,
+            meta.original_count
+        );
+        documents.push(DocumentInfo {
+            document_id: meta.document_id(),
+            identifier: header::to_hex(&meta.identifier),
+            payload_codes_found: payloads.len(),
+            enough_to_restore: usable_count >= meta.original_count as usize,
+            meta,
+        });
+    }
+    if documents.is_empty() {
         Err(anyhow!(
-            "failed to restore {}: checksum mismatch",
-            output_path.as_ref().display()
+            "could not locate any metadata shards for document {}",
+            args.identifier.as_deref().unwrap_or_default()
         ))?;
     }
+    write_file(
+        &args.output_path,
+        &serde_json::to_vec_pretty(&documents)?,
+        args.force,
+    )?;
     println!(
-        "{bytes_written} bytes written to {}",
-        output_path.as_ref().display()
+        "Wrote metadata for {} documents to {}",
+        documents.len(),
+        args.output_path.display()
+    );
+    Ok(())
+}
+
+/// How many times the needed number of codes have to be found before `report_over_scan` notes it.
+const OVER_SCAN_RATIO: f32 = 1.5;
+
+/// After a restore, note when far more codes were scanned than needed, so that less scanning can
+/// be done next time (and to confirm that the recovery data is intact).
+fn report_over_scan(meta: &header::MetaHeader, payloads: &Payloads, image_count: usize) {
+    let needed = meta.original_count as usize;
+    let ratio = payloads.len() as f32 / needed as f32;
+    if ratio < OVER_SCAN_RATIO {
+        return;
+    }
+    let unneeded = 1.0 - 1.0 / ratio;
+    print!(
+        "Note: found {ratio:.1}x the {needed} codes needed; about {:.0}% of them were not needed",
+        unneeded * 100.0
     );
+    let skippable = (image_count as f32 * unneeded).floor() as usize;
+    if skippable > 0 {
+        println!(", so about {skippable} of the {image_count} images could have been skipped");
+    } else {
+        println!();
+    }
+}
+
+/// Restore every document found in the shards into the output directory, naming each file after
+/// its Document ID.  Documents that cannot be restored are reported, and do not stop the others.
+fn restore_batch(args: &RestoreArgs, shards: &IntoFlatIter<Vec<u8>>) -> Result<()> {
+    let metas = document_metas(shards)?;
+
+    let mut restored = HashSet::<header::Sha512Array>::new();
+    let mut failed_ids = Vec::<String>::new();
+    for mut meta in metas {
+        println!("{meta}");
+        // Documents printed more than once (e.g. by different versions) have the same contents.
+        if restored.contains(&meta.hash) {
+            println!("Skipping: already restored from another printing");
+            continue;
+        }
+        let mut payloads = document_payloads(&meta, shards);
+        let output_path = args.output_path.join(meta.document_id());
+        let result = read_key_card(&mut meta, shards)
+            .and_then(|()| check_tool_version(&meta, args.verify_tool_version))
+            .and_then(|()| check_payloads(&meta, &mut payloads))
+            .and_then(|()| {
+                write_output(
+                    &meta,
+                    &payloads,
+                    args.force,
+                    &output_path,
+                    args.verify_signature.as_deref(),
+                    args.sidecar,
+                    args.strict,
+                )
+            });
+        match result {
+            Ok(()) => _ = restored.insert(meta.hash),
+            Err(e) => {
+                eprintln!("Failed to restore document {}: {e}", meta.document_id());
+                failed_ids.push(meta.document_id());
+            }
+        }
+    }
 
+    println!(
+        "Restored {} of {} documents",
+        restored.len(),
+        restored.len() + failed_ids.len()
+    );
+    if !failed_ids.is_empty() {
+        Err(anyhow!(
+            "could not restore documents: {}",
+            failed_ids.join(", ")
+        ))?;
+    }
     Ok(())
 }
 
-pub(crate) fn restore(args: &RestoreArgs) -> Result<()> {
-    println!("Restoring from {} images...", args.input_path.len());
-    let shards = read_shards(&args.input_path)?;
+/// `find_document` collects the metadata and payloads of a single document from the scanned
+/// shards.  If `identifier` is given, codes from other documents are skipped; otherwise they are
+/// an error.  Codes that paperback did not create, such as the link to the web restorer, are
+/// ignored.
+fn find_document(
+    shards: &IntoFlatIter<Vec<u8>>,
+    identifier: Option<&str>,
+) -> Result<(header::MetaHeader, Payloads)> {
+    // The metadata describes the layout of the payload headers, so it has to be found first.
     let mut previous_meta: Option<header::MetaHeader> = None;
-    let mut previous_identifier: Option<header::Identifier> = None;
-    let mut payloads = Vec::<(u16, Vec<u8>)>::new();
+    let mut skipped_count = 0;
+    let mut ignored_count = 0;
+    let mut first_error = None;
+    for shard in shards.iter().filter(|shard| header::is_meta(shard)) {
+        let m = match Header::read_from(&mut shard.as_slice(), IDENTIFIER_LENGTH, true) {
+            Ok(Header::Meta(m)) => m,
+            Ok(Header::Payload(_)) => continue,
+            Err(e) => {
+                ignored_count += 1;
+                first_error.get_or_insert(e);
+                continue;
+            }
+        };
+        if identifier.is_some_and(|id| !m.has_id(id)) {
+            skipped_count += 1;
+            continue;
+        }
+        if let Some(ref meta) = previous_meta {
+            if meta.identifier.ne(&m.identifier) {
+                Err(anyhow!(
+                    "identifier mismatch: codes from more than one document were found; use \
+                     --identifier to pick one"
+                ))?;
+            }
+            if meta.ne(&m) {
+                Err(anyhow!("meta header mismatch"))?;
+            }
+        } else {
+            previous_meta = Some(m);
+        }
+    }
+    let payload_identifier_length = previous_meta
+        .as_ref()
+        .map_or(IDENTIFIER_LENGTH, |m| m.payload_identifier_length);
+    let versioned = previous_meta
+        .as_ref()
+        .is_none_or(|m| m.format_version.is_some());
+    // Without metadata, any version this build knows is recognised.
+    let recognised = |version: Option<u8>| match previous_meta {
+        Some(ref meta) => version == meta.format_version,
+        None => version.is_some_and(|version| version <= header::FORMAT_VERSION),
+    };
 
+    let mut previous_payload: Option<header::PayloadHeader> = None;
+    let mut payloads = Vec::<(u16, Vec<u8>)>::new();
+    let mut seen_indices = HashSet::<u16>::new();
     for shard in shards.iter() {
-        let mut bytes = shard.getRawBytes().as_slice();
-        let header = Header::read_from(&mut bytes)?;
-        match header {
-            Header::Meta(m) => {
-                if let Some(ref identifier) = previous_identifier {
-                    if identifier.ne(&m.identifier) {
-                        Err(anyhow!("identifier mismatch"))?;
-                    }
-                } else {
-                    previous_identifier = Some(m.identifier);
-                }
-                if let Some(ref meta) = previous_meta {
-                    if meta.ne(&m) {
-                        Err(anyhow!("meta header mismatch"))?;
-                    }
-                } else {
-                    previous_meta = Some(m);
-                }
-            }
-            Header::Payload(p) => {
-                if let Some(ref id) = previous_identifier {
-                    if id.ne(&p.identifier) {
-                        Err(anyhow!("payload has incorrect identifier"))?;
-                    }
-                } else {
-                    previous_identifier = Some(p.identifier);
-                }
-                let mut buf = Vec::<u8>::new();
-                bytes.read_to_end(&mut buf)?;
-                payloads.push((p.index, buf));
+        // The decoder specification describes the format, not this document, and the key card is
+        // read once the metadata is known.
+        if shard.starts_with(header::DECODER_SPEC_PREFIX.as_bytes())
+            || shard.starts_with(header::KEY_CARD_PREFIX.as_bytes())
+            || header::is_meta(shard)
+        {
+            continue;
+        }
+        let mut bytes = shard.as_slice();
+        let p = match Header::read_from(&mut bytes, payload_identifier_length, versioned) {
+            Ok(Header::Payload(p)) if recognised(p.format_version) => p,
+            _ => {
+                ignored_count += 1;
+                continue;
             }
         };
+        let matches = match (&previous_meta, &previous_payload) {
+            (Some(meta), _) => meta.matches(&p),
+            (None, Some(previous)) => previous.identifier.eq(&p.identifier),
+            (None, None) => true,
+        };
+        if !matches {
+            if identifier.is_some() {
+                skipped_count += 1;
+                continue;
+            }
+            Err(anyhow!(
+                "payload has incorrect identifier: codes from more than one document were found; \
+                 use --identifier to pick one"
+            ))?;
+        }
+        // The same code may be scanned more than once (e.g. from overlapping images).
+        if !seen_indices.insert(p.index) {
+            continue;
+        }
+        let mut buf = Vec::<u8>::new();
+        bytes.read_to_end(&mut buf)?;
+        payloads.push((p.index, buf));
+        previous_payload.get_or_insert(p);
     }
 
-    let meta = previous_meta.ok_or(anyhow!("could not locate any metadata shards"))?;
-    println!(
-        "Data loaded: got {}/{} recovery shards",
-        payloads.len(),
-        meta.recovery_count
-    );
+    if skipped_count > 0 {
+        println!("Skipped {skipped_count} codes from other documents");
+    }
+    if ignored_count > 0 {
+        println!("Ignored {ignored_count} codes that are not paperback codes");
+    }
+    // A metadata code that could not be read, such as one from a newer version, says more than
+    // that there is none.
+    let mut meta = previous_meta.ok_or_else(|| match (first_error, identifier) {
+        (Some(e), _) => e,
+        (None, Some(id)) => anyhow!("could not locate any metadata shards for document {id}"),
+        (None, None) => no_metadata_error(shards),
+    })?;
+    read_key_card(&mut meta, shards)?;
+    // Images without any codes of this document, such as a cover letter in a PDF along with the
+    // pages, are left out without it being an error.
+    let unrelated = shards
+        .value
+        .iter()
+        .zip(&shards.paths)
+        .filter(|(codes, _)| !codes.iter().any(|code| is_document_code(&meta, code)))
+        .map(|(_, path)| path.display())
+        .join(", ");
+    if !unrelated.is_empty() {
+        println!("Note: skipped {unrelated}, which had no codes of this document");
+    }
+    Ok((meta, payloads))
+}
+
+/// Fill in the encryption record of a document whose record is on a separate key card, from the
+/// key card's code among the scanned ones.
+fn read_key_card(meta: &mut header::MetaHeader, shards: &IntoFlatIter<Vec<u8>>) -> Result<()> {
+    if !meta.key_card {
+        return Ok(());
+    }
+    let encryption = shards
+        .iter()
+        .filter_map(|code| Encryption::from_key_card(code))
+        .find(|(identifier, _)| *identifier == meta.identifier)
+        .map(|(_, encryption)| encryption)
+        .ok_or_else(|| {
+            Failure::InsufficientShards(
+                "the document is encrypted, and its salt and nonce are on a separate key card; \
+                 scan the key card along with the pages"
+                    .to_string(),
+            )
+        })?;
+    meta.encryption = Some(encryption);
+    Ok(())
+}
+
+/// Whether the code is the metadata, a payload or the key card of the document described by
+/// `meta`.
+fn is_document_code(meta: &header::MetaHeader, code: &[u8]) -> bool {
+    if let Some((identifier, _)) = Encryption::from_key_card(code) {
+        return identifier == meta.identifier;
+    }
+    if header::is_meta(code) {
+        return matches!(
+            Header::read_from(&mut &code[..], IDENTIFIER_LENGTH, true),
+            Ok(Header::Meta(m)) if m.identifier == meta.identifier
+        );
+    }
+    matches!(
+        Header::read_from(
+            &mut &code[..],
+            meta.payload_identifier_length,
+            meta.format_version.is_some()
+        ),
+        Ok(Header::Payload(p)) if meta.matches(&p)
+    )
+}
+
+/// The error when no metadata was found, which is far more often because nothing could be read
+/// at all than because only the metadata codes are missing.
+fn no_metadata_error(shards: &IntoFlatIter<Vec<u8>>) -> anyhow::Error {
+    if shards.iter().next().is_none() {
+        anyhow!(
+            "no QR codes could be decoded from any input image; check that the scans are sharp, \
+             evenly lit, and at a high enough resolution (at least 300 DPI)"
+        )
+    } else {
+        anyhow!(
+            "could not locate any metadata shards; check that the metadata codes at the edge of each page were scanned"
+        )
+    }
+}
+
+/// `restore_data` restores a document from the given images into memory, without any of the
+/// options `restore` has; this is used to `reflow` a document into a new layout.
+pub(crate) fn restore_data(
+    input_paths: &[PathBuf],
+    tile_size: Option<u32>,
+) -> Result<(header::MetaHeader, Vec<u8>)> {
+    println!("Restoring from {} images...", input_paths.len());
+    let shards = read_shards(input_paths, tile_size, None, false, None)?;
+    let (meta, mut payloads) = find_document(&shards, None)?;
+    println!("{meta}");
+    check_tool_version(&meta, false)?;
+    check_payloads(&meta, &mut payloads)?;
+    let data = decode_data(&meta, &payloads)?;
+    println!("Restored {} bytes", data.len());
+    Ok((meta, data))
+}
+
+pub(crate) fn restore(args: &RestoreArgs) -> Result<()> {
+    // Check the output directory up front, so that we do not scan everything only to fail later.
+    let output_dir = if args.batch {
+        Some(args.output_path.as_path())
+    } else {
+        args.output_path.parent()
+    };
+    if let Some(parent) = output_dir {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            if args.create_dirs {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))?;
+            } else {
+                Err(anyhow!(
+                    "output directory {} does not exist; use --create-dirs to create it",
+                    parent.display()
+                ))?;
+            }
+        }
+    }
+    let mut input_paths = args.input_path.clone();
+    if let Some(ref list_path) = args.input_list {
+        input_paths.extend(read_input_list(list_path)?);
+    }
+    if input_paths.is_empty() && args.base64.is_empty() && args.from_shards.is_none() {
+        Err(Failure::BadArguments(
+            "no input images were given".to_string(),
+        ))?;
+    }
+    let input_paths = &input_paths[..];
+    // The limit counts the images in directories, archives and PDFs, followed by the base64 inputs.
+    let sources = input_sources(input_paths, args.limit)?;
+    let base64_count = args.limit.map_or(args.base64.len(), |limit| {
+        args.base64.len().min(limit - sources.len())
+    });
+    let base64 = &args.base64[..base64_count];
+    println!("Restoring from {} images...", sources.len() + base64.len());
+    let deadline = args
+        .time_limit
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let mut shards = read_sources(
+        input_paths,
+        sources,
+        args.tile_size,
+        args.min_module_pixels,
+        args.fast_scan,
+        deadline,
+    )?;
+    // Each base64 input is one image, but their codes are kept together, as are those of the shard
+    // files, which are not images at all.
+    let image_count = shards.value.len() + base64.len();
+    if !base64.is_empty() {
+        shards
+            .value
+            .push(read_base64_shards(base64, args.tile_size)?);
+        shards.paths.push(PathBuf::from("base64 input"));
+    }
+    if let Some(ref shard_dir) = args.from_shards {
+        shards.value.push(read_shard_files(shard_dir)?);
+        shards.paths.push(shard_dir.clone());
+    }
+    if args.batch {
+        return restore_batch(args, &shards);
+    }
+    if args.info_only {
+        return write_info(args, &shards);
+    }
+    let (mut meta, mut payloads) = find_document(&shards, args.identifier.as_deref())?;
+    override_meta(&mut meta, &payloads, args)?;
+    // Describe the document before trying to decode it, so that this is shown even if there are
+    // not enough shards.
+    println!("{meta}");
+    check_tool_version(&meta, args.verify_tool_version)?;
+    if let Err(e) = check_payloads(&meta, &mut payloads) {
+        // Codes that are too small to read are often caused by scaling when printing.
+        scale::print_scale_hint(input_paths);
+        return Err(e);
+    }
 
-    write_output(&meta, &payloads, args.force, &args.output_path)?;
+    if meta.signature.is_some() && args.verify_signature.is_none() {
+        println!("Note: document is signed; use --verify-signature to check it");
+    }
+    if args.dry_run {
+        decode_data(&meta, &payloads)?;
+        report_margin(&meta, &payloads, &shards);
+        return Ok(());
+    }
+
+    write_output(
+        &meta,
+        &payloads,
+        args.force,
+        &args.output_path,
+        args.verify_signature.as_deref(),
+        args.sidecar,
+        args.strict,
+    )?;
+    report_over_scan(&meta, &payloads, image_count);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_data, find_codes, find_document, find_end_marker, input_sources, is_document_code,
+        module_pixels, scan, IntoFlatIter,
+    };
+    use crate::{
+        args::{Commands, TopLevelArgs},
+        create,
+        header::{Encryption, Header, END_MARKER},
+        Failure,
+    };
+    use anyhow::Result;
+    use assert_fs::{prelude::*, TempDir};
+    use clap::Parser;
+    use image::{DynamicImage, Luma};
+    use qrcode::{bits::Bits, EcLevel, QrCode, Version};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_scan_rotated_single_code() -> Result<()> {
+        let data = b"paperback single code test ".repeat(4);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::Q)?;
+        let image = code.render::<Luma<u8>>().module_dimensions(6, 6).build();
+        let image = DynamicImage::ImageLuma8(image).rotate90();
+        assert_eq!(scan(image, None, true)?, vec![data]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_pixels() -> Result<()> {
+        // A payload that leaves some of the capacity of a version 7 code unused, as shards do.
+        let data = [0x42; 80];
+        let mut bits = Bits::new(Version::Normal(7));
+        bits.push_byte_data(&data)?;
+        bits.push_terminator(EcLevel::M)?;
+        let code = QrCode::with_bits(bits, EcLevel::M)?;
+        let image = code.render::<Luma<u8>>().module_dimensions(5, 5).build();
+        let results = find_codes(DynamicImage::ImageLuma8(image), true)?;
+        let pixels = module_pixels(&results).expect("code should be measured");
+        assert!((pixels - 5.).abs() < 0.2, "unexpected module size {pixels}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_end_marker() -> Result<()> {
+        let mut data = b"file data".to_vec();
+        data.extend_from_slice(END_MARKER);
+        data.extend_from_slice(&[0; 7]);
+        data.extend_from_slice(&9u64.to_le_bytes());
+        assert_eq!(find_end_marker(&data, 9)?, 9);
+        // A damaged size falls back to the marker.
+        assert_eq!(find_end_marker(&data, 1 << 40)?, 9);
+        assert_eq!(find_end_marker(&data, 3)?, 9);
+        assert!(find_end_marker(&data[END_MARKER.len()..], 1 << 40).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_document_ignores_foreign_codes() -> Result<()> {
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from(["paperback", "create", "input.txt", "output.pdf"])?
+        else {
+            panic!("expected the create command");
+        };
+        let data = b"paperback foreign code test ".repeat(40);
+        let (layout, payload_shards) = create::document_shards(&args, data.clone(), false)?;
+        let mut meta_shard = Vec::new();
+        Header::Meta(layout.meta_header()?).write_to(&mut meta_shard)?;
+        // Codes that paperback did not create, such as the link to the web restorer, may be
+        // scanned along with the document.
+        let mut codes = vec![b"https://example.com/restore?id=abc".to_vec(), meta_shard];
+        codes.extend(payload_shards);
+        codes.push(b"x".to_vec());
+        let shards = IntoFlatIter {
+            value: vec![codes],
+            paths: vec![PathBuf::from("scan.png")],
+        };
+        let (meta, payloads) = find_document(&shards, None)?;
+        assert_eq!(decode_data(&meta, &payloads)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_sources_limit() -> Result<()> {
+        let work_dir = TempDir::new()?;
+        let scans = work_dir.child("scans");
+        for name in ["a.png", "b.png", "c.png"] {
+            scans.child(name).touch()?;
+        }
+        let extra = work_dir.child("d.png");
+        extra.touch()?;
+        let input_paths = [scans.to_path_buf(), extra.to_path_buf()];
+
+        // The limit counts the images in the directory, not the paths given.
+        let sources = input_sources(&input_paths, Some(2))?;
+        let names = sources
+            .iter()
+            .map(|(origin, source)| (*origin, source.path().file_name().unwrap().to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [(0, "a.png".into()), (0, "b.png".into())]);
+        assert_eq!(input_sources(&input_paths, None)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_document_skips_unrelated_images() -> Result<()> {
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from(["paperback", "create", "input.txt", "output.pdf"])?
+        else {
+            panic!("expected the create command");
+        };
+        let data = b"paperback cover letter test ".repeat(40);
+        let (layout, payload_shards) = create::document_shards(&args, data.clone(), false)?;
+        let mut meta_shard = Vec::new();
+        Header::Meta(layout.meta_header()?).write_to(&mut meta_shard)?;
+        let mut codes = vec![meta_shard];
+        codes.extend(payload_shards);
+        // A PDF may have a cover letter with no codes, or only codes that are not paperback's.
+        let shards = IntoFlatIter {
+            value: vec![vec![], codes, vec![b"https://example.com/".to_vec()]],
+            paths: vec![
+                PathBuf::from("scans.pdf#page=1"),
+                PathBuf::from("scans.pdf#page=2"),
+                PathBuf::from("scans.pdf#page=3"),
+            ],
+        };
+        let (meta, payloads) = find_document(&shards, None)?;
+        assert!(shards.value[1]
+            .iter()
+            .all(|code| is_document_code(&meta, code)));
+        assert!(!is_document_code(&meta, b"https://example.com/"));
+        assert_eq!(decode_data(&meta, &payloads)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_document_reads_key_card() -> Result<()> {
+        let TopLevelArgs {
+            command: Commands::Create(args),
+        } = TopLevelArgs::try_parse_from(["paperback", "create", "input.txt", "output.pdf"])?
+        else {
+            panic!("expected the create command");
+        };
+        let (mut layout, payload_shards) =
+            create::document_shards(&args, b"paperback key card test ".repeat(40), false)?;
+        let encryption = Encryption {
+            salt: [1; 16],
+            nonce: [2; 24],
+            key_check: [3; 8],
+        };
+        layout.encryption = Some(encryption);
+        layout.key_card = true;
+        let mut meta_shard = Vec::new();
+        Header::Meta(layout.meta_header()?).write_to(&mut meta_shard)?;
+        let mut codes = vec![meta_shard];
+        codes.extend(payload_shards);
+        let shards = IntoFlatIter {
+            value: vec![codes.clone()],
+            paths: vec![PathBuf::from("scan.png")],
+        };
+        let error = find_document(&shards, None).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Failure>(),
+            Some(Failure::InsufficientShards(_))
+        ));
+
+        // A key card for another document does not help.
+        let other_card = encryption.key_card_text(&[0; 4]).into_bytes();
+        let card = encryption.key_card_text(&layout.identifier).into_bytes();
+        let shards = IntoFlatIter {
+            value: vec![codes, vec![other_card, card]],
+            paths: vec![PathBuf::from("scan.png"), PathBuf::from("card.png")],
+        };
+        let (meta, _) = find_document(&shards, None)?;
+        assert_eq!(meta.encryption, Some(encryption));
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    create::layout::META_CODE_LENGTH,
+    header,
+    restore::{find_codes, image_sources},
+};
+use byteorder::{BigEndian, ByteOrder};
+use image::DynamicImage;
+use printpdf::Mm;
+use qrcode::{EcLevel, QrCode};
+use std::path::PathBuf;
+
+const MM_PER_INCH: f32 = 25.4;
+
+/// How far from 100% the print scale may be before it is reported.
+const SCALE_TOLERANCE: f32 = 0.03;
+
+/// `print_scale_hint` checks whether the pages were printed at the intended size, by comparing
+/// the size of a scanned metadata code (which is always the same size) with its intended size.
+/// Pages scaled to fit different paper can have codes too small to read.  Only the first image
+/// with both a readable metadata code and a recorded resolution is checked.
+pub(crate) fn print_scale_hint(input_paths: &[PathBuf]) {
+    let sources = input_paths
+        .iter()
+        .filter_map(|input_path| image_sources(input_path).ok())
+        .flatten();
+    for source in sources {
+        let Ok(bytes) = source.read() else {
+            continue;
+        };
+        let Some(dpi) = image_dpi(&bytes) else {
+            continue;
+        };
+        let Ok(image) = image::load_from_memory(&bytes) else {
+            continue;
+        };
+        let Some(scale) = meta_code_scale(image, dpi) else {
+            continue;
+        };
+        if (scale - 1.).abs() > SCALE_TOLERANCE {
+            println!(
+                "Hint: {} appears to have been printed at {:.0}% of its intended size; if the \
+                 pages were scaled to fit the paper, print them again at 100%",
+                source.path().display(),
+                scale * 100.
+            );
+        } else {
+            println!(
+                "Note: {} was printed at its intended size, so the codes may be unreadable for \
+                 another reason, such as focus or damage",
+                source.path().display()
+            );
+        }
+        return;
+    }
+    println!(
+        "Note: could not check whether the pages were printed at their intended size; this needs \
+         a scan with a readable metadata code and a recorded resolution"
+    );
+}
+
+/// `meta_code_scale` finds a metadata code in an image scanned at the given resolution, and
+/// returns its size relative to the size it should have been printed at.
+fn meta_code_scale(image: DynamicImage, dpi: f32) -> Option<f32> {
+    let results = find_codes(image, true).ok()?;
+    let result = results
+        .iter()
+        .find(|result| header::is_meta(result.getRawBytes()))?;
+    // Encoding the contents again gives the same version that `create` used.
+    let width = QrCode::with_error_correction_level(result.getRawBytes(), EcLevel::H)
+        .ok()?
+        .width();
+    // The points are either the four corners of the code, or the centres of the three finder
+    // patterns, which are (width - 7) modules apart.
+    let (points, modules) = match result.getPoints().as_slice() {
+        points @ [_, _, _, _] => (points, width),
+        points @ [_, _, _] => (points, width - 7),
+        _ => return None,
+    };
+    let side_length = points
+        .iter()
+        .zip(points.iter().skip(1))
+        .map(|(a, b)| a.distance(*b))
+        .sum::<f32>()
+        / (points.len() - 1) as f32;
+    let module_length = side_length / modules as f32 / dpi * MM_PER_INCH;
+    // The metadata code is drawn with a quiet zone of four modules on each side.
+    let intended_module_length = Mm::from(META_CODE_LENGTH).0 / (width + 8) as f32;
+    Some(module_length / intended_module_length)
+}
+
+/// `image_dpi` reads the resolution recorded in a PNG (in its pHYs chunk) or JPEG (in its JFIF
+/// header) file, in dots per inch.
+fn image_dpi(bytes: &[u8]) -> Option<f32> {
+    let dpi = if let Some(mut chunks) = bytes.strip_prefix(b"\x89PNG\r\n\x1a\n") {
+        loop {
+            let length = BigEndian::read_u32(chunks.get(..4)?) as usize;
+            let kind = chunks.get(4..8)?;
+            let data = chunks.get(8..8 + length)?;
+            // The unit must be metres; otherwise, only the aspect ratio is known.
+            if kind == b"pHYs" && length == 9 && data[8] == 1 {
+                break BigEndian::read_u32(data) as f32 * MM_PER_INCH / 1000.;
+            }
+            if kind == b"IDAT" {
+                return None;
+            }
+            // Skip the length, type, data, and CRC.
+            chunks = chunks.get(12 + length..)?;
+        }
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF, 0xE0]) && bytes.get(6..11)? == b"JFIF\0" {
+        let density = BigEndian::read_u16(bytes.get(14..16)?) as f32;
+        match bytes.get(13)? {
+            1 => density,
+            2 => density * 2.54,
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    (dpi > 0.).then_some(dpi)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{image_dpi, meta_code_scale, MM_PER_INCH};
+    use crate::create::layout::META_CODE_LENGTH;
+    use anyhow::Result;
+    use image::{DynamicImage, Luma};
+    use printpdf::Mm;
+    use qrcode::{EcLevel, QrCode};
+
+    #[test]
+    fn test_image_dpi() {
+        // 11811 pixels per metre is 300 dpi.
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x09pHYs".to_vec();
+        png.extend_from_slice(&[0, 0, 0x2e, 0x23, 0, 0, 0x2e, 0x23, 1, 0, 0, 0, 0]);
+        assert_eq!(image_dpi(&png).map(f32::round), Some(300.));
+        let jpeg = b"\xff\xd8\xff\xe0\0\x10JFIF\0\x01\x01\x01\x00\xc8\x00\xc8";
+        assert_eq!(image_dpi(jpeg), Some(200.));
+        assert_eq!(image_dpi(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_meta_code_scale() -> Result<()> {
+        const MODULE_PIXELS: u32 = 8;
+        let mut meta = vec![0xFF, 0xFF];
+        meta.extend_from_slice(&[0x42; 90]);
+        let code = QrCode::with_error_correction_level(&meta, EcLevel::H)?;
+        let intended_module_length = Mm::from(META_CODE_LENGTH).0 / (code.width() + 8) as f32;
+        let dpi = MODULE_PIXELS as f32 / intended_module_length * MM_PER_INCH;
+        let image = DynamicImage::ImageLuma8(
+            code.render::<Luma<u8>>()
+                .module_dimensions(MODULE_PIXELS, MODULE_PIXELS)
+                .build(),
+        );
+        let scale = meta_code_scale(image.clone(), dpi).expect("code should be found");
+        assert!((scale - 1.).abs() < 0.02, "unexpected scale {scale}");
+        let scale = meta_code_scale(image, dpi / 0.94).expect("code should be found");
+        assert!((scale - 0.94).abs() < 0.02, "unexpected scale {scale}");
+        Ok(())
+    }
+}
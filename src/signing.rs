@@ -0,0 +1,39 @@
+use crate::header::{self, Sha512Array, Signature};
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signer, SigningKey, VerifyingKey,
+};
+use std::{fs, path::Path};
+
+/// Sign a document hash with the private key in the given file.  The key may be a PKCS#8 PEM
+/// file, or the 32 byte secret key as hex digits.
+pub(crate) fn sign(key_path: &Path, hash: &Sha512Array) -> Result<Signature> {
+    let text = fs::read_to_string(key_path)
+        .with_context(|| format!("failed to read {}", key_path.display()))?;
+    let key = if text.contains("-----BEGIN") {
+        SigningKey::from_pkcs8_pem(&text)
+            .map_err(|e| anyhow!("invalid private key in {}: {e}", key_path.display()))?
+    } else {
+        SigningKey::from_bytes(&header::parse_hex(text.trim())?)
+    };
+    Ok(key.sign(hash).to_bytes())
+}
+
+/// Check that a document hash was signed by the given public key.  The key may be the path of a
+/// PEM file, or the 32 byte public key as hex digits.
+pub(crate) fn verify(key: &str, hash: &Sha512Array, signature: &Signature) -> Result<()> {
+    let key = if Path::new(key).is_file() {
+        let text = fs::read_to_string(key).with_context(|| format!("failed to read {key}"))?;
+        if text.contains("-----BEGIN") {
+            VerifyingKey::from_public_key_pem(&text)
+                .map_err(|e| anyhow!("invalid public key in {key}: {e}"))?
+        } else {
+            VerifyingKey::from_bytes(&header::parse_hex(text.trim())?)?
+        }
+    } else {
+        VerifyingKey::from_bytes(&header::parse_hex(key.trim())?)?
+    };
+    key.verify_strict(hash, &ed25519_dalek::Signature::from_bytes(signature))
+        .map_err(|_| anyhow!("signature does not match the given public key"))
+}
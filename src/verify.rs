@@ -0,0 +1,108 @@
+use crate::{
+    args::VerifyArgs,
+    create::{self, layout},
+    header::{self, Header},
+    restore::read_shards,
+};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use std::{
+    collections::{BTreeSet, HashMap},
+    slice,
+};
+
+/// Check each scanned page against the codes it should contain, which are found by creating the
+/// document again from the original file.  Pages where some codes no longer read are reported, so
+/// that they can be reprinted before the document becomes unrecoverable.
+pub(crate) fn verify(args: &VerifyArgs) -> Result<()> {
+    let (data, bundle) = create::read_input(&args.create)?;
+    let (layout, shards) = create::document_shards(&args.create, data, bundle)?;
+    // The shards are in page order, so a shard's position in the list gives its page.
+    let codes_per_page = layout.codes_per_page();
+    let page_count = shards.len().div_ceil(codes_per_page);
+    let expected = shards
+        .iter()
+        .enumerate()
+        .map(|(position, shard)| (shard.as_slice(), position))
+        .collect::<HashMap<_, _>>();
+
+    let mut incomplete_count = 0;
+    for scan in &args.scans {
+        let codes = read_shards(slice::from_ref(scan), args.tile_size, None, false, None)?;
+        let mut found = BTreeSet::new();
+        let mut mismatched_count = 0;
+        for code in codes.iter() {
+            match expected.get(code.as_slice()) {
+                Some(&position) => _ = found.insert(position),
+                None if is_from_document(&layout, code) => mismatched_count += 1,
+                None => {}
+            }
+        }
+        if mismatched_count > 0 {
+            println!(
+                "{}: {mismatched_count} codes of this document do not match the original file; \
+                 check that the options are the ones it was created with",
+                scan.display()
+            );
+        }
+        // The page is the one most of the codes read belong to.
+        let Some((page_num, _)) = found
+            .iter()
+            .counts_by(|position| position / codes_per_page)
+            .into_iter()
+            .max_by_key(|&(page_num, count)| (count, std::cmp::Reverse(page_num)))
+        else {
+            println!(
+                "{}: no codes of this document could be read",
+                scan.display()
+            );
+            incomplete_count += 1;
+            continue;
+        };
+        let page_positions =
+            page_num * codes_per_page..shards.len().min((page_num + 1) * codes_per_page);
+        let missing = page_positions
+            .clone()
+            .filter(|position| !found.contains(position))
+            .map(|position| layout.shard_index(page_num, position - page_num * codes_per_page))
+            .collect::<Vec<_>>();
+        println!(
+            "{}: page {} of {page_count}: {} of {} codes read correctly{}",
+            scan.display(),
+            page_num + 1,
+            page_positions.len() - missing.len(),
+            page_positions.len(),
+            if missing.is_empty() {
+                String::new()
+            } else {
+                format!("; unreadable codes: {}", missing.iter().join(", "))
+            }
+        );
+        if !missing.is_empty() {
+            incomplete_count += 1;
+        }
+    }
+    if incomplete_count > 0 {
+        Err(anyhow!(
+            "{incomplete_count} of {} scanned pages did not read completely; consider reprinting \
+             them from the original file",
+            args.scans.len()
+        ))?;
+    }
+    println!("All {} scanned pages read completely", args.scans.len());
+    Ok(())
+}
+
+/// Whether the code is a payload code of the given document, going by its identifier.
+fn is_from_document(layout: &layout::Options, code: &[u8]) -> bool {
+    if header::is_meta(code) {
+        return false;
+    }
+    match Header::read_from(&mut &code[..], layout.payload_identifier_length, true) {
+        Ok(Header::Payload(payload)) => {
+            let length = payload.identifier_length;
+            payload.identifier[..length] == layout.identifier[..length]
+        }
+        _ => false,
+    }
+}